@@ -1,28 +1,124 @@
+use std::time::Instant;
+
+use crate::bvh::*;
 use crate::geom::*;
 use crate::prims::*;
 use crate::shape::*;
-// use crate::types::*;
+use crate::types::*;
+use crate::wide_bvh::*;
 
+/// A collection of primitives with an acceleration structure over them.
 pub struct Aggregate {
-    _prims: Box<Vec<Box<dyn Primitive>>>,
-    bvh: Box<BVH>,
+    prims: Vec<Box<dyn Primitive>>,
+    bvh: BVH,
+    split_method: SplitMethod,
+    stats: BVHStats,
+    /// SAH cost right after the last full build, to measure how much refitting has degraded the tree.
+    built_sah_cost: Float,
+}
+
+/// What `Aggregate::refit` did to bring the BVH up to date.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Refit {
+    /// Only node bounds were updated, possibly after rebuilding some badly degraded subtrees.
+    Refitted {
+        rebuilt_subtrees: usize,
+        /// The SAH cost relative to that after the last full build.
+        cost_ratio: Float,
+    },
+    /// The tree had degraded too much and was rebuilt from scratch.
+    Rebuilt,
 }
 
-impl<'a> Aggregate {
-    pub fn new(prims: Vec<Box<dyn Primitive>>) -> Self {
-        unsafe {
-            let prims = Box::new(prims);
-            let bvh = BVH::new(&prims);
-            Aggregate { _prims: prims, bvh }
+impl Aggregate {
+    pub fn new(prims: Vec<Box<dyn Primitive>>) -> Result<Self, BuildError> {
+        Self::with_split_method(prims, SplitMethod::Object)
+    }
+
+    pub fn with_split_method(
+        prims: Vec<Box<dyn Primitive>>, split_method: SplitMethod,
+    ) -> Result<Self, BuildError> {
+        let t_begin = Instant::now();
+        let bvh = BVH::with_split_method(&prims, split_method)?;
+        let stats = BVHStats { build_time: t_begin.elapsed(), ..bvh.stats() };
+        Ok(Aggregate { prims, bvh, split_method, stats, built_sah_cost: stats.sah_cost })
+    }
+
+    /// Statistics of the BVH as of the last build or refit.
+    pub fn stats(&self) -> &BVHStats {
+        &self.stats
+    }
+
+    /// The primitives, in the order they were given. Primitives may be replaced (e.g. to move them for the next
+    /// frame of an animation) as long as `refit` is called before the next intersection.
+    pub fn prims_mut(&mut self) -> &mut [Box<dyn Primitive>] {
+        &mut self.prims
+    }
+
+    /// Once the tree's SAH cost has grown by this factor since the last full build, `refit` rebuilds from scratch.
+    const REBUILD_COST_RATIO: Float = 1.3;
+
+    /// Updates the BVH after primitives were changed through `prims_mut`, refitting bounds in place where the tree
+    /// quality allows and rebuilding otherwise.
+    pub fn refit(&mut self) -> Result<Refit, BuildError> {
+        let t_begin = Instant::now();
+        let rebuilt_subtrees = self.bvh.refit(&self.prims, self.split_method)?;
+        let stats = self.bvh.stats();
+        let cost_ratio = stats.sah_cost / self.built_sah_cost;
+        if cost_ratio <= Self::REBUILD_COST_RATIO {
+            self.stats = BVHStats { build_time: t_begin.elapsed(), ..stats };
+            return Ok(Refit::Refitted { rebuilt_subtrees, cost_ratio });
         }
+        self.bvh = BVH::with_split_method(&self.prims, self.split_method)?;
+        self.stats = BVHStats { build_time: t_begin.elapsed(), ..self.bvh.stats() };
+        self.built_sah_cost = self.stats.sah_cost;
+        Ok(Refit::Rebuilt)
+    }
+}
+
+impl Primitive for Aggregate {
+    fn intersect(&self, r: Ray3f) -> Option<SurfaceInteraction<'_>> {
+        self.bvh.intersect(&self.prims, r)
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bvh.bounding_box())
+    }
+}
+
+/// Like `Aggregate`, but with the BVH collapsed to four children per node, whose bounds are tested together using
+/// SIMD instructions where the CPU supports them.
+pub struct WideAggregate {
+    prims: Vec<Box<dyn Primitive>>,
+    bvh: WideBVH,
+    /// Statistics of the binary hierarchy the wide one was collapsed from.
+    stats: BVHStats,
+}
+
+impl WideAggregate {
+    pub fn new(prims: Vec<Box<dyn Primitive>>) -> Result<Self, BuildError> {
+        Self::with_split_method(prims, SplitMethod::Object)
+    }
+
+    pub fn with_split_method(
+        prims: Vec<Box<dyn Primitive>>, split_method: SplitMethod,
+    ) -> Result<Self, BuildError> {
+        let t_begin = Instant::now();
+        let binary = BVH::with_split_method(&prims, split_method)?;
+        let bvh = WideBVH::new(&binary);
+        let stats = BVHStats { build_time: t_begin.elapsed(), ..binary.stats() };
+        Ok(WideAggregate { prims, bvh, stats })
+    }
+
+    pub fn stats(&self) -> &BVHStats {
+        &self.stats
     }
 }
 
-impl<'a> Primitive for Aggregate {
+impl Primitive for WideAggregate {
     fn intersect(&self, r: Ray3f) -> Option<SurfaceInteraction<'_>> {
-        self.bvh.intersect(r)
+        self.bvh.intersect(&self.prims, r)
     }
     fn bounding_box(&self) -> Option<AABB> {
-        self.bvh.bounding_box()
+        Some(self.bvh.bounding_box())
     }
 }
@@ -0,0 +1,104 @@
+use image::GenericImageView;
+use std::cmp::Ordering;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::types::*;
+use crate::util;
+
+/// The shape of a thin lens's aperture, which out of focus highlights take on.
+#[derive(Clone, Debug)]
+pub enum Aperture {
+    Circle,
+    /// A regular polygon made by `blades` straight blades, with a corner `rotation` degrees counter-clockwise from the
+    /// right.
+    Polygon {
+        blades: u32,
+        rotation: Float,
+    },
+    /// An image that is brighter where the aperture lets more light through.
+    Mask(Arc<ApertureMask>),
+}
+
+impl Aperture {
+    /// Where the sample `u` from [0,1)² lands on the aperture. The circle and polygons fit in the unit disk, and masks
+    /// cover [-1,1]².
+    pub fn sample(&self, u: Point2f) -> Point2f {
+        match self {
+            Aperture::Circle => util::disk_sample(u),
+            Aperture::Polygon { blades, rotation } => {
+                // Pick one of the triangles between the center and a blade, all of which have the same area, and then a
+                // point in it.
+                let n = max!(*blades, 3) as Float;
+                let sector = min!((u.x * n).floor(), n - 1.0);
+                let (u, v) = (u.x * n - sector, u.y);
+                let corner = |i: Float| {
+                    let angle = rotation * PI / 180.0 + i * 2.0 * PI / n;
+                    Vector2f::new(angle.cos(), angle.sin())
+                };
+                let s = u.sqrt();
+                Point2f::from_vec((corner(sector) * (1.0 - v) + corner(sector + 1.0) * v) * s)
+            }
+            Aperture::Mask(mask) => mask.sample(u),
+        }
+    }
+}
+
+/// An image of an aperture, sampled in proportion to its brightness.
+#[derive(Clone, Debug)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    /// The running total of the pixels' brightness, row by row from the top, scaled to end at 1.
+    cdf: Vec<Float>,
+}
+
+impl ApertureMask {
+    /// A mask of `width` by `height` pixels, row by row from the top, with how much light each lets through. If none
+    /// do, the aperture is a pinhole.
+    pub fn new(width: usize, height: usize, transmission: &[Float]) -> ApertureMask {
+        let mut total = 0.0;
+        let mut cdf: Vec<Float> = transmission
+            .iter()
+            .map(|t| {
+                total += max!(*t, 0.0);
+                total
+            })
+            .collect();
+        if total > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= total);
+        }
+        ApertureMask { width, height, cdf }
+    }
+
+    /// A mask from the brightness of an image file.
+    pub fn load<P: AsRef<Path>>(path: P) -> image::ImageResult<ApertureMask> {
+        let image = image::open(path)?;
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut transmission = vec![0.0; width * height];
+        for (x, y, pixel) in image.pixels() {
+            let [r, g, b, _] = pixel.0;
+            transmission[y as usize * width + x as usize] =
+                (0.2126 * r as Float + 0.7152 * g as Float + 0.0722 * b as Float) / 255.0;
+        }
+        Ok(ApertureMask::new(width, height, &transmission))
+    }
+
+    fn sample(&self, u: Point2f) -> Point2f {
+        if !matches!(self.cdf.last(), Some(&total) if total > 0.0) {
+            return Point2f::new(0.0, 0.0);
+        }
+        // The first pixel whose running total passes u.x; where u.x falls within it spreads the sample across it.
+        let i = self
+            .cdf
+            .binary_search_by(|&c| iff!(c <= u.x, Ordering::Less, Ordering::Greater))
+            .unwrap_or_else(|i| min!(i, self.cdf.len() - 1));
+        let before = iff!(i == 0, 0.0, self.cdf[i - 1]);
+        let across = clamp!((u.x - before) / (self.cdf[i] - before), 0.0, 1.0);
+        let (x, y) = ((i % self.width) as Float, (i / self.width) as Float);
+        Point2f::new(
+            (x + across) / self.width as Float * 2.0 - 1.0,
+            1.0 - (y + u.y) / self.height as Float * 2.0,
+        )
+    }
+}
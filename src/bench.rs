@@ -0,0 +1,346 @@
+use log::info;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::aggregate::*;
+use crate::aperture::*;
+use crate::bvh::*;
+use crate::camera::*;
+use crate::exposure::*;
+use crate::geom::*;
+use crate::lens::*;
+use crate::material::*;
+use crate::prims::*;
+use crate::scene;
+use crate::shape::*;
+use crate::stereo::*;
+use crate::types::*;
+use crate::util;
+
+/// Builds a scene to benchmark.
+type Scene = fn() -> Result<Vec<Box<dyn Primitive>>, Box<dyn Error>>;
+
+/// Compares the BVH builders and layouts on a few scenes: build time, tree statistics and the time to trace
+/// primary rays. Run with `cargo run --release -- bench-bvh`, and again with `--features f32` to compare precisions.
+pub fn bvh() -> Result<(), Box<dyn Error>> {
+    info!("geometry is {}", std::any::type_name::<Float>());
+    let scenes: [(&str, Scene, Point3f, Point3f); 10] = [
+        (
+            "cover",
+            || Ok(scene::cover_scene()),
+            Point3f::new(12.0, 3.0, 3.0),
+            Point3f::new(0.0, 0.0, -1.0),
+        ),
+        (
+            "slivers",
+            || Ok(scene::slivers_scene()),
+            Point3f::new(0.0, 0.0, 25.0),
+            Point3f::new(0.0, 0.0, 0.0),
+        ),
+        (
+            "instances",
+            || Ok(scene::instances_scene()?),
+            Point3f::new(0.0, 8.0, 60.0),
+            Point3f::new(0.0, 0.0, 0.0),
+        ),
+        ("motion", scene::motion_scene, Point3f::new(12.0, 3.0, 3.0), Point3f::new(0.0, 0.0, -1.0)),
+        (
+            "shapes",
+            || Ok(scene::shapes_scene()),
+            Point3f::new(0.0, 4.0, 14.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ),
+        (
+            "fur",
+            || Ok(scene::fur_scene()),
+            Point3f::new(0.0, 2.0, 5.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ),
+        (
+            "sdf",
+            || Ok(scene::sdf_scene()),
+            Point3f::new(0.0, 3.0, 9.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ),
+        (
+            "csg",
+            || Ok(scene::csg_scene()),
+            Point3f::new(0.0, 4.0, 9.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ),
+        (
+            "subdiv",
+            || {
+                let (from, to) = (Point3f::new(0.0, 3.0, 9.0), Point3f::new(0.0, 1.0, 0.0));
+                Ok(scene::subdiv_scene(&camera(from, to)))
+            },
+            Point3f::new(0.0, 3.0, 9.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ),
+        (
+            "mesh",
+            || Ok(scene::mesh_scene()),
+            Point3f::new(0.0, 2.0, 5.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ),
+    ];
+    for (name, scene, from, to) in scenes.iter() {
+        let rays = primary_rays(*from, *to);
+        for split_method in [SplitMethod::Object, SplitMethod::Spatial { alpha: 1e-5 }].iter() {
+            let world = Aggregate::with_split_method(scene()?, *split_method)?;
+            trace(&format!("{} {:?} binary", name, split_method), &world, world.stats(), &rays);
+            let world = WideAggregate::with_split_method(scene()?, *split_method)?;
+            trace(&format!("{} {:?} wide", name, split_method), &world, world.stats(), &rays);
+        }
+    }
+    projections()?;
+    Ok(refit()?)
+}
+
+/// Times the materials on scenes that show them off. Each primary ray's hit is found first, so that only scattering
+/// off it is timed. Run with `cargo run --release -- bench-shading`.
+pub fn shading() -> Result<(), Box<dyn Error>> {
+    let scenes: [(&str, Scene, Point3f, Point3f); 3] = [
+        (
+            "materials",
+            || Ok(scene::materials_scene()),
+            Point3f::new(0.0, 4.0, 24.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ),
+        (
+            "textures",
+            || Ok(scene::textures_scene()?),
+            Point3f::new(0.0, 3.0, 9.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ),
+        (
+            "bump",
+            || Ok(scene::bump_scene()?),
+            Point3f::new(0.0, 3.0, 9.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ),
+    ];
+    for (name, scene, from, to) in scenes.iter() {
+        let world = WideAggregate::new(scene()?)?;
+        let hits: Vec<_> = primary_rays(*from, *to)
+            .into_iter()
+            .filter_map(|ray| {
+                let mut hit = world.intersect(ray)?;
+                hit.compute_differentials(&ray);
+                Some((ray, hit))
+            })
+            .collect();
+        let t_begin = Instant::now();
+        let scattered =
+            hits.iter().filter(|(ray, hit)| hit.material.scatter(*ray, hit).is_some()).count();
+        let elapsed = t_begin.elapsed();
+        info!(
+            "{}: {} of {} hits scattered in {:?} ({:.3} M/s)",
+            name,
+            scattered,
+            hits.len(),
+            elapsed,
+            hits.len() as f64 / elapsed.as_secs_f64() / 1e6,
+        );
+    }
+    Ok(())
+}
+
+/// Traces the cover scene through each kind of camera: wide views see more of the scene and so cost more per ray, and
+/// the realistic lens blocks some rays.
+fn projections() -> Result<(), Box<dyn Error>> {
+    let (from, to) = (Point3f::new(12.0, 3.0, 3.0), Point3f::new(0.0, 0.0, -1.0));
+    let projections = [
+        (
+            "perspective",
+            Projection::Perspective {
+                fov: 55.0,
+                aperture: 0.1,
+                focus_dist: 13.0,
+                lens: ThinLens::default(),
+            },
+        ),
+        (
+            "perspective anamorphic tilt-shift",
+            Projection::Perspective {
+                fov: 55.0,
+                aperture: 0.4,
+                focus_dist: 13.0,
+                lens: ThinLens {
+                    aperture: Aperture::Polygon { blades: 6, rotation: 15.0 },
+                    squeeze: 2.0,
+                    cats_eye: 0.6,
+                    tilt: Vector2f::new(8.0, 0.0),
+                },
+            },
+        ),
+        (
+            "perspective star bokeh",
+            Projection::Perspective {
+                fov: 55.0,
+                aperture: 0.4,
+                focus_dist: 13.0,
+                lens: ThinLens {
+                    aperture: Aperture::Mask(Arc::new(ApertureMask::load(concat!(
+                        env!("CARGO_MANIFEST_DIR"),
+                        "/lenses/star-aperture.png"
+                    ))?)),
+                    ..ThinLens::default()
+                },
+            },
+        ),
+        ("orthographic", Projection::Orthographic { height: 6.0 }),
+        ("equirectangular", Projection::Equirectangular),
+        (
+            "equidistant fisheye",
+            Projection::Fisheye { fov: 180.0, mapping: FisheyeMapping::Equidistant },
+        ),
+        (
+            "equisolid fisheye",
+            Projection::Fisheye { fov: 220.0, mapping: FisheyeMapping::Equisolid },
+        ),
+        (
+            "realistic 50mm",
+            Projection::Realistic {
+                lens: load_lens(concat!(env!("CARGO_MANIFEST_DIR"), "/lenses/dgauss.50mm.dat"))?,
+                aperture: 10.0,
+                film_diagonal: 43.3,
+                exposure: LensExposure::Physical,
+            },
+        ),
+    ];
+    let world = WideAggregate::new(scene::cover_scene())?;
+    let eye_size = Point2u::new(320, 200);
+    for (name, projection) in projections.iter() {
+        let desc = CameraDesc {
+            from,
+            to,
+            up: Vector3f::unit_y(),
+            projection: projection.clone(),
+            shutter: (0.0, 1.0),
+            stereo: None,
+            exposure: None,
+        };
+        let rays = rays_from(&*desc.build(eye_size)?, eye_size);
+        trace(&format!("cover {}", name), &world, world.stats(), &rays);
+    }
+
+    // Exposure only scales the rays' weights, and sets the perspective camera's aperture.
+    for (name, projection) in [&projections[0], &projections[projections.len() - 1]].iter() {
+        let desc = CameraDesc {
+            from,
+            to,
+            up: Vector3f::unit_y(),
+            projection: projection.clone(),
+            shutter: (0.0, 1.0 / 60.0),
+            stereo: None,
+            exposure: Some(Exposure { f_number: 8.0, iso: 400.0 }),
+        };
+        let rays = rays_from(&*desc.build(eye_size)?, eye_size);
+        trace(&format!("cover {} f/8 ISO 400", name), &world, world.stats(), &rays);
+    }
+
+    // Both views of a stereo pair come from the one pass, so they take twice as long as either.
+    let stereo = [
+        ("perspective", StereoRig::Parallel, StereoLayout::SideBySide, projections[0].1.clone()),
+        ("perspective", StereoRig::Converged, StereoLayout::Separate, projections[0].1.clone()),
+        (
+            "omni-directional",
+            StereoRig::Parallel,
+            StereoLayout::TopBottom,
+            Projection::Equirectangular,
+        ),
+    ];
+    for (name, rig, layout, projection) in stereo.iter() {
+        let desc = CameraDesc {
+            from,
+            to,
+            up: Vector3f::unit_y(),
+            projection: projection.clone(),
+            shutter: (0.0, 1.0),
+            stereo: Some(StereoDesc { interaxial: 0.065, rig: *rig, layout: *layout }),
+            exposure: None,
+        };
+        let rays = rays_from(&*desc.build(eye_size)?, desc.film_size(eye_size));
+        trace(
+            &format!("cover {} {:?} {:?} stereo", name, rig, layout),
+            &world,
+            world.stats(),
+            &rays,
+        );
+    }
+    Ok(())
+}
+
+/// Moves a cloud of spheres a little each frame, comparing refitting the BVH with rebuilding it.
+fn refit() -> Result<(), BuildError> {
+    let mut random = util::new_random(0);
+    let mut random_vector =
+        || Vector3f::new(random(), random(), random()) * 2.0 - Vector3f::from_value(1.0);
+    let spheres: Vec<(Point3f, Vector3f)> = (0..100_000)
+        .map(|_| (Point3f::from_vec(random_vector() * 50.0), random_vector()))
+        .collect();
+    let sphere_at = |(center, velocity): &(Point3f, Vector3f), time: Float| {
+        Box::new(ShapePrimitive::new(
+            Sphere { center: center + velocity * time, radius: 0.2 },
+            Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+        )) as Box<dyn Primitive>
+    };
+
+    let mut world = Aggregate::new(spheres.iter().map(|s| sphere_at(s, 0.0)).collect())?;
+    for frame in 1..=10 {
+        let time = frame as Float;
+        for (prim, s) in world.prims_mut().iter_mut().zip(spheres.iter()) {
+            *prim = sphere_at(s, time);
+        }
+        let t_begin = Instant::now();
+        let refit = world.refit()?;
+        let refit_time = t_begin.elapsed();
+        let rebuilt = Aggregate::new(spheres.iter().map(|s| sphere_at(s, time)).collect())?;
+        info!(
+            "frame {}: {:?} in {:?} with SAH cost {:.3}; full rebuild in {:?} with SAH cost {:.3}",
+            frame,
+            refit,
+            refit_time,
+            world.stats().sah_cost,
+            rebuilt.stats().build_time,
+            rebuilt.stats().sah_cost,
+        );
+    }
+    Ok(())
+}
+
+fn trace(name: &str, world: &dyn Primitive, stats: &BVHStats, rays: &[Ray3f]) {
+    let t_begin = Instant::now();
+    let hits = rays.iter().filter(|r| world.intersect(**r).is_some()).count();
+    let elapsed = t_begin.elapsed();
+    info!(
+        "{}: {:?}; {} of {} rays hit in {:?} ({:.3} Mrays/s)",
+        name,
+        stats,
+        hits,
+        rays.len(),
+        elapsed,
+        rays.len() as f64 / elapsed.as_secs_f64() / 1e6,
+    );
+}
+
+fn camera(from: Point3f, to: Point3f) -> Perspective {
+    let film_size = Point2u::new(320, 200);
+    Perspective::new(from, to, Vector3f::unit_y(), 55.0, 0.0, (to - from).magnitude(), film_size)
+        .with_shutter(0.0, 1.0)
+}
+
+fn primary_rays(from: Point3f, to: Point3f) -> Vec<Ray3f> {
+    rays_from(&camera(from, to), Point2u::new(320, 200))
+}
+
+fn rays_from(camera: &dyn Camera, film_size: Point2u) -> Vec<Ray3f> {
+    (0..film_size.y)
+        .flat_map(|y| (0..film_size.x).map(move |x| Point2u::new(x, y)))
+        .flat_map(|pixel| camera.get_rays(1, pixel))
+        .filter(|c| c.weight > 0.0)
+        .map(|c| c.ray)
+        .collect()
+}
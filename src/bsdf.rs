@@ -0,0 +1,89 @@
+use crate::geom::*;
+use crate::prims::*;
+use crate::types::*;
+use crate::util::*;
+
+/// How a surface scatters light, in a local frame with the surface's normal along z. Both directions point away from
+/// the surface: `wo` towards where the light leaves, and `wi` towards where it arrives from.
+pub trait Bsdf {
+    /// The fraction of the light arriving from `wi` that leaves towards `wo`, per unit of projected solid angle. This
+    /// is zero for perfectly specular scattering, which only `sample` can find.
+    fn eval(&self, wo: Vector3f, wi: Vector3f) -> Vector3f;
+    /// The density, per unit of solid angle, of `sample` picking `wi` for `wo`.
+    fn pdf(&self, wo: Vector3f, wi: Vector3f) -> Float;
+    /// Picks a direction for the light leaving towards `wo` to have arrived from, with the samples `u` from [0,1).
+    fn sample(&self, wo: Vector3f, u: [Float; 3]) -> Option<BsdfSample>;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BsdfSample {
+    pub wi: Vector3f,
+    /// The BSDF times the cosine of `wi` over the density of picking it.
+    pub weight: Vector3f,
+    /// Whether `wi` is the only direction the light could have come from, as off a mirror or through polished glass.
+    pub specular: bool,
+    /// The index of refraction on `wi`'s side of the surface over that on `wo`'s; 1 for reflection.
+    pub eta: Float,
+}
+
+/// An orthonormal basis at a hit, which takes directions in and out of a BSDF's local frame.
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    pub s: Vector3f,
+    pub t: Vector3f,
+    pub n: Vector3f,
+}
+
+impl Frame {
+    /// A frame around the unit normal `n`, with `s` along the part of `dpdu` that is tangent to the surface, so that
+    /// anisotropic BSDFs line up with the surface's parameterization. Any tangent does where `dpdu` is degenerate.
+    pub fn new(n: Vector3f, dpdu: Vector3f) -> Frame {
+        let s = dpdu - n * n.dot(dpdu);
+        if s.magnitude2() > 1e-12 * dpdu.magnitude2() && s.magnitude2() > 0.0 {
+            let s = s.normalize();
+            Frame { s, t: n.cross(s), n }
+        } else {
+            let (s, t) = coordinate_system(n);
+            Frame { s, t, n }
+        }
+    }
+
+    pub fn to_local(self, v: Vector3f) -> Vector3f {
+        Vector3f::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    pub fn to_world(self, v: Vector3f) -> Vector3f {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+}
+
+/// Fresnel reflectance of unpolarized light at a dielectric boundary with relative index of refraction `eta`, from the
+/// side the normal points to if `cos_theta_i` is positive, and otherwise from the other side.
+pub fn fresnel_dielectric(cos_theta_i: Float, eta: Float) -> Float {
+    let (cos_theta_i, eta) = iff!(cos_theta_i < 0.0, (-cos_theta_i, 1.0 / eta), (cos_theta_i, eta));
+    let sin_theta_t = safe_sqrt(1.0 - cos_theta_i * cos_theta_i) / eta;
+    if sin_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+    let r_parl = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+/// Scatters `in_` at `hit` by sampling `bsdf` in the frame of the hit's shading normal and `dpdu`, for materials that
+/// are written as a BSDF.
+pub fn scatter_bsdf(
+    bsdf: &dyn Bsdf, in_: Ray3f, hit: &SurfaceInteraction,
+) -> Option<(Ray3f, Vector3f)> {
+    let frame = Frame::new(hit.shading.normal, hit.shading.dpdu);
+    let wo = frame.to_local(-in_.direction.normalize());
+    let sample = bsdf.sample(wo, [random(), random(), random()])?;
+    let wi = frame.to_world(sample.wi);
+    let ray = iff!(
+        sample.specular,
+        hit.spawn_specular_ray(&in_, wi, sample.eta),
+        hit.spawn_ray(wi, in_.time)
+    );
+    Some((ray, sample.weight))
+}
@@ -0,0 +1,128 @@
+use crate::geom::*;
+use crate::material::*;
+use crate::prims::*;
+use crate::texture::*;
+use crate::types::*;
+
+/// A way of perturbing the shading frame of a surface, for detail too fine to be worth modelling.
+pub trait Bump: Sync + Send {
+    /// The shading frame at `hit`, perturbed from the one it has.
+    fn shading(&self, hit: &SurfaceInteraction) -> Shading;
+}
+
+/// A normal map in tangent space: the image's red, green and blue, from 0 to 1, map to -1 to 1 along the surface's
+/// `dpdu`, along its `dpdv` and along its normal, as most tools bake them. The image is looked up through the
+/// texture's mapping, which should be a `Mapping::Uv` for the tangents to follow it, and should be loaded with
+/// `TextureImage::load_linear`.
+#[derive(Clone, Debug)]
+pub struct NormalMap {
+    pub texture: ImageTexture,
+}
+
+impl Bump for NormalMap {
+    fn shading(&self, hit: &SurfaceInteraction) -> Shading {
+        let n = hit.shading.normal;
+        let local = self.texture.evaluate(hit) * 2.0 - Vector3f::from_value(1.0);
+        let tangent = hit.shading.dpdu - n * n.dot(hit.shading.dpdu);
+        if local.magnitude2() == 0.0 || tangent.magnitude2() == 0.0 {
+            return hit.shading;
+        }
+        // The bitangent goes along v whichever way round the uvs wind, so that mirrored uvs and the flipped
+        // parameterizations of disks read the green channel the same way.
+        let handedness = iff!(n.cross(hit.shading.dpdu).dot(hit.shading.dpdv) < 0.0, -1.0, 1.0);
+        let s = tangent.normalize();
+        let t = n.cross(s) * handedness;
+        let normal = (s * local.x + t * local.y + n * local.z).normalize();
+        // Keep the derivatives' lengths, and turn them to lie across the new normal.
+        let dpdu = hit.shading.dpdu - normal * normal.dot(hit.shading.dpdu);
+        let dpdu = dpdu.normalize() * hit.shading.dpdu.magnitude();
+        let dpdv = normal.cross(dpdu).normalize() * (hit.shading.dpdv.magnitude() * handedness);
+        Shading { normal, dpdu, dpdv }
+    }
+}
+
+/// A height field over the surface, which tilts the normal as if the surface were displaced along it by `scale` times
+/// the height, without moving the surface itself (Blinn, "Simulation of Wrinkled Surfaces", as in PBRT 10.5.2).
+#[derive(Copy, Clone, Debug)]
+pub struct BumpMap<T> {
+    pub height: T,
+    /// How far a height of 1 displaces the surface.
+    pub scale: Float,
+}
+
+impl<T: Texture<Float>> Bump for BumpMap<T> {
+    fn shading(&self, hit: &SurfaceInteraction) -> Shading {
+        let Shading { normal: n, dpdu, dpdv } = hit.shading;
+        // Take finite differences over about half the pixel's footprint, or a small step without differentials.
+        let step = |dx: Float, dy: Float| {
+            let d = (dx.abs() + dy.abs()) / 2.0;
+            iff!(d > 0.0, d, 0.0005)
+        };
+        let (du, dv) = (step(hit.duvdx.x, hit.duvdy.x), step(hit.duvdx.y, hit.duvdy.y));
+        let height_at = |dp: Vector3f, duv: Vector2f| {
+            self.height.evaluate(&SurfaceInteraction {
+                point: hit.point + dp,
+                uv: hit.uv + duv,
+                ..*hit
+            })
+        };
+        let displace = self.height.evaluate(hit);
+        let u_displace = height_at(dpdu * du, Vector2f::new(du, 0.0));
+        let v_displace = height_at(dpdv * dv, Vector2f::new(0.0, dv));
+        // The derivatives of the displaced point, leaving out the turning of the normal, which matters little for
+        // small bumps.
+        let dpdu = dpdu + n * ((u_displace - displace) / du * self.scale);
+        let dpdv = dpdv + n * ((v_displace - displace) / dv * self.scale);
+        let normal = dpdu.cross(dpdv);
+        if normal.magnitude2() == 0.0 || !normal.magnitude2().is_finite() {
+            return hit.shading;
+        }
+        let normal = normal.normalize();
+        Shading { normal: iff!(normal.dot(n) < 0.0, -normal, normal), dpdu, dpdv }
+    }
+}
+
+/// `base` with its shading normal perturbed by `bump`.
+///
+/// Where the shading normal leans away from the geometric one, some directions are on different sides of the
+/// surface for each: the view can be behind the shading normal, and light scattered off it can go into the surface.
+/// The normal is bent back just far enough for the view to mirror off it above the surface (Keller et al., "The Iray
+/// Light Transport Simulation and Rendering System", appendix A.3), which leaves no black fringes on mirrors. Other
+/// scattering that goes through the surface when the shading normal says it doesn't, or the other way round, is
+/// absorbed so that light can't leak through.
+pub struct Bumped<M: Material, B: Bump> {
+    pub base: M,
+    pub bump: B,
+}
+
+impl<M: Material, B: Bump> Material for Bumped<M, B> {
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        let wo = -in_.direction.normalize();
+        let mut shading = self.bump.shading(hit);
+        shading.normal = bend(wo, shading.normal, hit.normal);
+        let hit = SurfaceInteraction { shading, ..*hit };
+        let (ray, weight) = self.base.scatter(in_, &hit)?;
+        let side = |n: Vector3f| (ray.direction.dot(n) > 0.0) == (wo.dot(n) > 0.0);
+        iff!(side(hit.normal) == side(shading.normal), Some((ray, weight)), None)
+    }
+}
+
+/// Bends the shading normal `ns` towards the geometric normal `ng`, if need be, so that `wo` mirrors off it to the
+/// same side of the surface that it is on.
+fn bend(wo: Vector3f, ns: Vector3f, ng: Vector3f) -> Vector3f {
+    let flip = iff!(wo.dot(ng) < 0.0, -1.0, 1.0);
+    let (ns, ng) = (ns * flip, ng * flip);
+    let r = reflect(-wo, ns);
+    // How far above the surface the mirrored direction has to be, less at grazing angles so there is room for it.
+    let min_height = min!(0.9 * wo.dot(ng), 0.01);
+    let height = r.dot(ng);
+    if height >= min_height {
+        return ns * flip;
+    }
+    let along = r - ng * height;
+    if along.magnitude2() == 0.0 {
+        return ng * flip;
+    }
+    let r = along.normalize() * (1.0 - min_height * min_height).sqrt() + ng * min_height;
+    (wo + r).normalize() * flip
+}
@@ -0,0 +1,606 @@
+use rayon::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::geom::*;
+use crate::prims::*;
+use crate::shape::*;
+use crate::types::*;
+
+/// A bounding volume hierarchy over a slice of primitives.
+///
+/// The hierarchy doesn't own the primitives: leaves hold indices into the slice it was built from, and the same
+/// slice must be passed back in to `intersect`.
+pub enum BVH {
+    Leaf { aabb: AABB, prims: Vec<usize> },
+    Node { aabb: AABB, left: Box<BVH>, right: Box<BVH> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// No primitives were given.
+    Empty,
+    /// The primitive at this index has no bounding box.
+    Unbounded(usize),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Empty => write!(f, "BVH has no primitives"),
+            BuildError::Unbounded(i) => write!(f, "BVH primitive {} has no bounding box", i),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SplitMethod {
+    /// Partition primitives by their centers using the binned surface area heuristic.
+    Object,
+    /// Also consider splitting primitive references at planes, clipping each part to its side (SBVH). Spatial splits
+    /// are only tried where the best object split's children overlap by more than `alpha` of the root's surface
+    /// area; 1e-5 is a reasonable default, and larger values trade traversal speed for fewer references.
+    Spatial { alpha: Float },
+}
+
+/// Summary of a built hierarchy.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BVHStats {
+    pub build_time: Duration,
+    /// Number of interior nodes.
+    pub nodes: usize,
+    pub leaves: usize,
+    pub max_depth: usize,
+    pub max_leaf_prims: usize,
+    /// Number of primitive references in leaves; more than the number of primitives if any were split.
+    pub references: usize,
+    /// Expected cost of tracing a ray through the hierarchy, relative to a single primitive intersection.
+    pub sah_cost: Float,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PrimitiveInfo {
+    index: usize,
+    aabb: AABB,
+    center: Point3f,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ObjectSplit {
+    cost: Float,
+    dim: usize,
+    bucket: usize,
+    left_aabb: AABB,
+    right_aabb: AABB,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct SpatialSplit {
+    cost: Float,
+    dim: usize,
+    pos: Float,
+    left_aabb: AABB,
+    left_count: usize,
+    right_aabb: AABB,
+    right_count: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct SpatialBin {
+    aabb: AABB,
+    /// Number of references starting in this bin.
+    entries: usize,
+    /// Number of references ending in this bin.
+    exits: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct BucketInfo {
+    count: usize,
+    aabb: AABB,
+}
+
+/// Buckets for all three axes, filled in a single pass over the primitives.
+#[derive(Copy, Clone)]
+struct Bins([[BucketInfo; BVH::OBJECT_SPLIT_BUCKETS]; 3]);
+
+impl Bins {
+    fn new() -> Bins {
+        Bins([[BucketInfo { count: 0, aabb: AABB::empty() }; BVH::OBJECT_SPLIT_BUCKETS]; 3])
+    }
+
+    fn add(mut self, center_bounds: &AABB, pi: &PrimitiveInfo) -> Bins {
+        for dim in 0..3 {
+            let b = &mut self.0[dim][BVH::bucket(center_bounds, dim, pi)];
+            b.count += 1;
+            b.aabb = b.aabb.union(&pi.aabb);
+        }
+        self
+    }
+
+    fn merge(mut self, other: Bins) -> Bins {
+        for dim in 0..3 {
+            for (b, o) in self.0[dim].iter_mut().zip(other.0[dim].iter()) {
+                b.count += o.count;
+                b.aabb = b.aabb.union(&o.aabb);
+            }
+        }
+        self
+    }
+}
+
+impl BVH {
+    const OBJECT_SPLIT_BUCKETS: usize = 16;
+    const MAX_PRIMITIVES_PER_NODE: usize = 4;
+    /// Nodes with at least this many primitives are binned and built in parallel.
+    const PARALLEL_THRESHOLD: usize = 4096;
+
+    pub fn new(prims: &[Box<dyn Primitive>]) -> Result<Self, BuildError> {
+        Self::with_split_method(prims, SplitMethod::Object)
+    }
+
+    pub fn with_split_method(
+        prims: &[Box<dyn Primitive>], split_method: SplitMethod,
+    ) -> Result<Self, BuildError> {
+        let indices: Vec<usize> = (0..prims.len()).collect();
+        Ok(*Self::build_subset(prims, &indices, split_method)?)
+    }
+
+    /// Builds a hierarchy over just the primitives at `indices`.
+    fn build_subset(
+        prims: &[Box<dyn Primitive>], indices: &[usize], split_method: SplitMethod,
+    ) -> Result<Box<Self>, BuildError> {
+        if indices.is_empty() {
+            return Err(BuildError::Empty);
+        }
+        let mut infos = indices
+            .par_iter()
+            .map(|&index| {
+                let aabb = prims[index].bounding_box().ok_or(BuildError::Unbounded(index))?;
+                Ok(PrimitiveInfo { index, aabb, center: aabb.center() })
+            })
+            .collect::<Result<Vec<PrimitiveInfo>, BuildError>>()?;
+        Ok(match split_method {
+            SplitMethod::Object => Self::build(&mut infos),
+            SplitMethod::Spatial { alpha } => {
+                let (aabb, _) = Self::bounds(&infos);
+                SpatialBuilder { prims, min_overlap: alpha * aabb.surface_area() }.build(infos, 0)
+            }
+        })
+    }
+
+    fn build(prims: &mut [PrimitiveInfo]) -> Box<Self> {
+        let (aabb, center_bounds) = Self::bounds(prims);
+        if prims.len() <= Self::MAX_PRIMITIVES_PER_NODE {
+            return Self::new_leaf(aabb, prims);
+        }
+        let split = match Self::object_split(prims, &aabb, &center_bounds) {
+            Some(split) if split.cost < prims.len() as Float => split,
+            // BVH cost same as just checking everything, so don't bother with a node.
+            _ => return Self::new_leaf(aabb, prims),
+        };
+
+        let mid =
+            partition(prims, |pi| Self::bucket(&center_bounds, split.dim, pi) <= split.bucket);
+        if mid == 0 || mid == prims.len() {
+            return Self::new_leaf(aabb, prims);
+        }
+        let parallel = prims.len() >= Self::PARALLEL_THRESHOLD;
+        let (left, right) = prims.split_at_mut(mid);
+        let (left, right) = if parallel {
+            rayon::join(|| Self::build(left), || Self::build(right))
+        } else {
+            (Self::build(left), Self::build(right))
+        };
+        Box::new(BVH::Node { aabb, left, right })
+    }
+
+    fn new_leaf(aabb: AABB, prims: &[PrimitiveInfo]) -> Box<Self> {
+        Box::new(BVH::Leaf { aabb, prims: prims.iter().map(|pi| pi.index).collect() })
+    }
+
+    /// Returns the bounds of the primitives and the bounds of their centers.
+    fn bounds(prims: &[PrimitiveInfo]) -> (AABB, AABB) {
+        let empty = || (AABB::empty(), AABB::empty());
+        let add =
+            |(b, c): (AABB, AABB), pi: &PrimitiveInfo| (b.union(&pi.aabb), c.union_p(&pi.center));
+        if prims.len() >= Self::PARALLEL_THRESHOLD {
+            prims
+                .par_iter()
+                .fold(empty, add)
+                .reduce(empty, |(b0, c0), (b1, c1)| (b0.union(&b1), c0.union(&c1)))
+        } else {
+            prims.iter().fold(empty(), add)
+        }
+    }
+
+    fn bucket(center_bounds: &AABB, dim: usize, pi: &PrimitiveInfo) -> usize {
+        let c_buckets = Self::OBJECT_SPLIT_BUCKETS;
+        let i = (center_bounds.offset_p(&pi.center)[dim] * (c_buckets as Float)) as usize;
+        clamp!(i, 0, c_buckets - 1)
+    }
+
+    /// Finds the cheapest bucket boundary to split at across all axes, using the surface area heuristic.
+    fn object_split(
+        prims: &[PrimitiveInfo], bounds: &AABB, center_bounds: &AABB,
+    ) -> Option<ObjectSplit> {
+        let bins = if prims.len() >= Self::PARALLEL_THRESHOLD {
+            prims
+                .par_iter()
+                .fold(Bins::new, |bins, pi| bins.add(center_bounds, pi))
+                .reduce(Bins::new, Bins::merge)
+        } else {
+            prims.iter().fold(Bins::new(), |bins, pi| bins.add(center_bounds, pi))
+        };
+
+        let c_buckets = Self::OBJECT_SPLIT_BUCKETS;
+        let mut best: Option<ObjectSplit> = None;
+        for dim in 0..3 {
+            if center_bounds.min[dim] == center_bounds.max[dim] {
+                continue;
+            }
+            let buckets = &bins.0[dim];
+
+            // Sweep from the right to accumulate the cost of everything past each boundary, then from the left.
+            let mut right = [(AABB::empty(), 0.0 as Float); BVH::OBJECT_SPLIT_BUCKETS];
+            let (mut aabb_right, mut count_right) = (AABB::empty(), 0);
+            for i in (1..c_buckets).rev() {
+                aabb_right = aabb_right.union(&buckets[i].aabb);
+                count_right += buckets[i].count;
+                right[i - 1] = (aabb_right, count_right as Float * aabb_right.surface_area());
+            }
+            let (mut aabb_left, mut count_left) = (AABB::empty(), 0);
+            for i in 0..c_buckets - 1 {
+                aabb_left = aabb_left.union(&buckets[i].aabb);
+                count_left += buckets[i].count;
+                let (right_aabb, right_cost) = right[i];
+                let cost = 1.0
+                    + (count_left as Float * aabb_left.surface_area() + right_cost)
+                        / bounds.surface_area();
+                if best.map(|b| cost < b.cost).unwrap_or(true) {
+                    best = Some(ObjectSplit {
+                        cost,
+                        dim,
+                        bucket: i,
+                        left_aabb: aabb_left,
+                        right_aabb,
+                    });
+                }
+            }
+        }
+        best
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats::default();
+        self.collect_stats(&mut stats, 1, self.bounding_box().surface_area());
+        stats
+    }
+
+    fn collect_stats(&self, stats: &mut BVHStats, depth: usize, root_area: Float) {
+        stats.max_depth = max!(stats.max_depth, depth);
+        match self {
+            BVH::Leaf { aabb, prims } => {
+                stats.leaves += 1;
+                stats.max_leaf_prims = max!(stats.max_leaf_prims, prims.len());
+                stats.references += prims.len();
+                stats.sah_cost += prims.len() as Float * aabb.surface_area() / root_area;
+            }
+            BVH::Node { aabb, left, right } => {
+                stats.nodes += 1;
+                stats.sah_cost += aabb.surface_area() / root_area;
+                left.collect_stats(stats, depth + 1, root_area);
+                right.collect_stats(stats, depth + 1, root_area);
+            }
+        }
+    }
+
+    /// Updates node bounds after primitives in `prims` have moved, rebuilding any subtree whose SAH cost grew by
+    /// more than `REFIT_REBUILD_RATIO`. Returns the number of rebuilt subtrees.
+    ///
+    /// Refitting keeps the tree topology, so the tree gets worse as primitives move away from where they were at
+    /// build time; callers should compare `stats().sah_cost` with its value after the last full build to decide
+    /// when to rebuild from scratch. Spatial split references are refitted to their whole primitive's bounds.
+    pub fn refit(
+        &mut self, prims: &[Box<dyn Primitive>], split_method: SplitMethod,
+    ) -> Result<usize, BuildError> {
+        let mut rebuilt = 0;
+        self.refit_node(prims, split_method, &mut rebuilt)?;
+        Ok(rebuilt)
+    }
+
+    /// Subtrees whose expected traversal cost grows by more than this factor in one refit are rebuilt.
+    const REFIT_REBUILD_RATIO: Float = 1.5;
+
+    /// Refits the subtree, returning its SAH cost (relative to its own surface area) before and after.
+    fn refit_node(
+        &mut self, prims: &[Box<dyn Primitive>], split_method: SplitMethod, rebuilt: &mut usize,
+    ) -> Result<(Float, Float), BuildError> {
+        match self {
+            BVH::Leaf { aabb, prims: indices } => {
+                *aabb = indices.iter().try_fold(AABB::empty(), |b, &i| {
+                    Ok(b.union(&prims[i].bounding_box().ok_or(BuildError::Unbounded(i))?))
+                })?;
+                let cost = indices.len() as Float;
+                Ok((cost, cost))
+            }
+            BVH::Node { aabb, left, right } => {
+                let area_before = (
+                    aabb.surface_area(),
+                    left.bounding_box().surface_area(),
+                    right.bounding_box().surface_area(),
+                );
+                let (left_before, left_after) = left.refit_node(prims, split_method, rebuilt)?;
+                let (right_before, right_after) = right.refit_node(prims, split_method, rebuilt)?;
+                *aabb = left.bounding_box().union(&right.bounding_box());
+                let area_after = (
+                    aabb.surface_area(),
+                    left.bounding_box().surface_area(),
+                    right.bounding_box().surface_area(),
+                );
+
+                let before = 1.0
+                    + (area_before.1 * left_before + area_before.2 * right_before) / area_before.0;
+                let after =
+                    1.0 + (area_after.1 * left_after + area_after.2 * right_after) / area_after.0;
+                if after <= before * Self::REFIT_REBUILD_RATIO {
+                    return Ok((before, after));
+                }
+                let mut indices = Vec::new();
+                self.collect_indices(&mut indices);
+                indices.sort_unstable();
+                indices.dedup();
+                *self = *Self::build_subset(prims, &indices, split_method)?;
+                *rebuilt += 1;
+                let stats = self.stats();
+                Ok((before, stats.sah_cost))
+            }
+        }
+    }
+
+    fn collect_indices(&self, indices: &mut Vec<usize>) {
+        match self {
+            BVH::Leaf { aabb: _, prims } => indices.extend_from_slice(prims),
+            BVH::Node { aabb: _, left, right } => {
+                left.collect_indices(indices);
+                right.collect_indices(indices);
+            }
+        }
+    }
+
+    /// Finds the closest intersection with `prims`, which must be the slice the hierarchy was built from.
+    pub fn intersect<'a>(
+        &self, prims: &'a [Box<dyn Primitive>], r: Ray3f,
+    ) -> Option<SurfaceInteraction<'a>> {
+        match self {
+            BVH::Leaf { aabb, prims: indices } if aabb.intersect(r) => {
+                indices.iter().fold(None, |hit, &i| match (hit, prims[i].intersect(r)) {
+                    (None, hit) | (hit, None) => hit,
+                    (Some(best), Some(hit)) => Some(iff!(best.t < hit.t, best, hit)),
+                })
+            }
+            BVH::Node { aabb, left, right } if aabb.intersect(r) => {
+                match (left.intersect(prims, r), right.intersect(prims, r)) {
+                    (None, None) => None,
+                    (hit, None) | (None, hit) => hit,
+                    (Some(hl), Some(hr)) => Some(iff!(hl.t < hr.t, hl, hr)),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn bounding_box(&self) -> AABB {
+        *match self {
+            BVH::Leaf { aabb, prims: _ } => aabb,
+            BVH::Node { aabb, left: _, right: _ } => aabb,
+        }
+    }
+}
+
+type SpatialBins = [SpatialBin; SpatialBuilder::SPATIAL_SPLIT_BINS];
+
+/// Builds spatial split BVHs; see Stich et al., "Spatial Splits in Bounding Volume Hierarchies" (2009).
+///
+/// References to primitives straddling a split plane are duplicated into both children, with each part's bounds
+/// clipped to its side, so a primitive may appear in several leaves.
+struct SpatialBuilder<'a> {
+    prims: &'a [Box<dyn Primitive>],
+    /// Only try spatial splits when object split children overlap by more than this area.
+    min_overlap: Float,
+}
+
+impl SpatialBuilder<'_> {
+    const SPATIAL_SPLIT_BINS: usize = 16;
+    /// Spatial splits keep duplicating references that can't be separated, so stop trying them past this depth.
+    const MAX_SPATIAL_SPLIT_DEPTH: usize = 48;
+
+    fn build(&self, mut refs: Vec<PrimitiveInfo>, depth: usize) -> Box<BVH> {
+        let (aabb, center_bounds) = BVH::bounds(&refs);
+        if refs.len() <= BVH::MAX_PRIMITIVES_PER_NODE {
+            return BVH::new_leaf(aabb, &refs);
+        }
+        let object = BVH::object_split(&refs, &aabb, &center_bounds);
+        let overlap = object.map(|o| o.left_aabb.intersection(&o.right_aabb));
+        let try_spatial = depth < Self::MAX_SPATIAL_SPLIT_DEPTH
+            && overlap
+                .map(|o| !o.is_empty() && o.surface_area() > self.min_overlap)
+                .unwrap_or(true);
+        let spatial = iff!(try_spatial, self.spatial_split(&refs, &aabb), None);
+
+        let leaf_cost = refs.len() as Float;
+        let (left, right) = match (object, spatial) {
+            (_, Some(s))
+                if s.cost < leaf_cost && object.map(|o| s.cost < o.cost).unwrap_or(true) =>
+            {
+                self.split_references(refs, &s)
+            }
+            (Some(o), _) if o.cost < leaf_cost => {
+                let mid =
+                    partition(&mut refs, |pi| BVH::bucket(&center_bounds, o.dim, pi) <= o.bucket);
+                let right = refs.split_off(mid);
+                (refs, right)
+            }
+            // BVH cost same as just checking everything, so don't bother with a node.
+            _ => return BVH::new_leaf(aabb, &refs),
+        };
+        if left.is_empty() || right.is_empty() {
+            return BVH::new_leaf(aabb, &[left, right].concat());
+        }
+
+        let (left, right) = if left.len() + right.len() >= BVH::PARALLEL_THRESHOLD {
+            rayon::join(|| self.build(left, depth + 1), || self.build(right, depth + 1))
+        } else {
+            (self.build(left, depth + 1), self.build(right, depth + 1))
+        };
+        Box::new(BVH::Node { aabb, left, right })
+    }
+
+    /// Finds the cheapest plane to split references at, clipping references to the bins they overlap.
+    fn spatial_split(&self, refs: &[PrimitiveInfo], bounds: &AABB) -> Option<SpatialSplit> {
+        let c_bins = Self::SPATIAL_SPLIT_BINS;
+        let mut best: Option<SpatialSplit> = None;
+        for dim in 0..3 {
+            let (lo, hi) = (bounds.min[dim], bounds.max[dim]);
+            if hi <= lo {
+                continue;
+            }
+            let width = (hi - lo) / c_bins as Float;
+            let bin_of = |x: Float| clamp!(((x - lo) / width) as usize, 0, c_bins - 1);
+
+            let add = |bins: &mut SpatialBins, r: &PrimitiveInfo| {
+                let (first, last) = (bin_of(r.aabb.min[dim]), bin_of(r.aabb.max[dim]));
+                for (b, bin) in bins.iter_mut().enumerate().take(last + 1).skip(first) {
+                    let mut slab = r.aabb;
+                    slab.min[dim] = max!(slab.min[dim], lo + width * b as Float);
+                    slab.max[dim] = min!(
+                        slab.max[dim],
+                        iff!(b == c_bins - 1, hi, lo + width * (b + 1) as Float)
+                    );
+                    if let Some(clipped) = self.prims[r.index].clip_bounding_box(&slab) {
+                        bin.aabb = bin.aabb.union(&clipped);
+                    }
+                }
+                bins[first].entries += 1;
+                bins[last].exits += 1;
+            };
+            let empty = || {
+                [SpatialBin { aabb: AABB::empty(), entries: 0, exits: 0 }; Self::SPATIAL_SPLIT_BINS]
+            };
+            let bins = if refs.len() >= BVH::PARALLEL_THRESHOLD {
+                let add = |mut bins, r: &PrimitiveInfo| {
+                    add(&mut bins, r);
+                    bins
+                };
+                refs.par_iter().fold(empty, add).reduce(empty, |mut bins, other| {
+                    for (b, o) in bins.iter_mut().zip(other.iter()) {
+                        b.aabb = b.aabb.union(&o.aabb);
+                        b.entries += o.entries;
+                        b.exits += o.exits;
+                    }
+                    bins
+                })
+            } else {
+                let mut bins = empty();
+                refs.iter().for_each(|r| add(&mut bins, r));
+                bins
+            };
+
+            let mut right = [(AABB::empty(), 0); Self::SPATIAL_SPLIT_BINS];
+            let (mut aabb_right, mut count_right) = (AABB::empty(), 0);
+            for i in (1..c_bins).rev() {
+                aabb_right = aabb_right.union(&bins[i].aabb);
+                count_right += bins[i].exits;
+                right[i - 1] = (aabb_right, count_right);
+            }
+            let (mut aabb_left, mut count_left) = (AABB::empty(), 0);
+            for i in 0..c_bins - 1 {
+                aabb_left = aabb_left.union(&bins[i].aabb);
+                count_left += bins[i].entries;
+                let (right_aabb, right_count) = right[i];
+                if count_left == 0 || right_count == 0 {
+                    continue;
+                }
+                let cost = 1.0
+                    + (count_left as Float * aabb_left.surface_area()
+                        + right_count as Float * right_aabb.surface_area())
+                        / bounds.surface_area();
+                if best.map(|b| cost < b.cost).unwrap_or(true) {
+                    best = Some(SpatialSplit {
+                        cost,
+                        dim,
+                        pos: lo + width * (i + 1) as Float,
+                        left_aabb: aabb_left,
+                        left_count: count_left,
+                        right_aabb,
+                        right_count,
+                    });
+                }
+            }
+        }
+        best
+    }
+
+    fn split_references(
+        &self, refs: Vec<PrimitiveInfo>, split: &SpatialSplit,
+    ) -> (Vec<PrimitiveInfo>, Vec<PrimitiveInfo>) {
+        let (dim, pos) = (split.dim, split.pos);
+        let (area_left, area_right) =
+            (split.left_aabb.surface_area(), split.right_aabb.surface_area());
+        let (count_left, count_right) = (split.left_count as Float, split.right_count as Float);
+
+        let mut left = Vec::with_capacity(split.left_count);
+        let mut right = Vec::with_capacity(split.right_count);
+        for r in refs {
+            if r.aabb.max[dim] <= pos {
+                left.push(r);
+                continue;
+            } else if r.aabb.min[dim] >= pos {
+                right.push(r);
+                continue;
+            }
+            // Keep the reference whole on one side if that's cheaper than duplicating it ("unsplitting").
+            let cost_split = area_left * count_left + area_right * count_right;
+            let cost_left = split.left_aabb.union(&r.aabb).surface_area() * count_left
+                + area_right * (count_right - 1.0);
+            let cost_right = area_left * (count_left - 1.0)
+                + split.right_aabb.union(&r.aabb).surface_area() * count_right;
+            if cost_left < cost_split && cost_left <= cost_right {
+                left.push(r);
+                continue;
+            } else if cost_right < cost_split {
+                right.push(r);
+                continue;
+            }
+
+            let (mut left_slab, mut right_slab) = (r.aabb, r.aabb);
+            left_slab.max[dim] = pos;
+            right_slab.min[dim] = pos;
+            let prim = &self.prims[r.index];
+            match (prim.clip_bounding_box(&left_slab), prim.clip_bounding_box(&right_slab)) {
+                (Some(l), Some(rt)) => {
+                    left.push(PrimitiveInfo { index: r.index, aabb: l, center: l.center() });
+                    right.push(PrimitiveInfo { index: r.index, aabb: rt, center: rt.center() });
+                }
+                (Some(_), None) => left.push(r),
+                (None, _) => right.push(r),
+            }
+        }
+        (left, right)
+    }
+}
+
+/// Reorders `items` so that those matching `pred` come first, returning the number that matched.
+fn partition<T, F: Fn(&T) -> bool>(items: &mut [T], pred: F) -> usize {
+    let mut first = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(first, i);
+            first += 1;
+        }
+    }
+    first
+}
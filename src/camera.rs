@@ -1,10 +1,261 @@
+use cgmath::{Matrix3, Rad};
+
+use crate::aperture::*;
+use crate::exposure::*;
 use crate::geom::*;
+use crate::lens::*;
+use crate::stereo::*;
 use crate::types::*;
 use crate::util;
 
-pub struct Camera {
-    /// Center of the lens.
-    origin: Point3f,
+/// Turns positions on the film into rays into the scene.
+pub trait Camera: Send + Sync {
+    /// `n` rays through the pixel at `film_pos`. Samples that fall outside of what the camera sees are left out, so
+    /// there may be fewer than `n`.
+    fn get_rays(&self, n: usize, film_pos: Point2u) -> Vec<CameraRay>;
+
+    /// Where `p` lands on the film, in pixels from its lower left corner, as seen from the center of the lens; None if
+    /// the camera doesn't see it.
+    fn project(&self, p: Point3f) -> Option<Point2f>;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct CameraRay {
+    pub ray: Ray3f,
+    /// Where the ray starts within its pixel.
+    pub pixel_offset: Point2f,
+    /// How much the light along the ray counts toward its pixel; zero if the camera blocked it.
+    pub weight: Float,
+}
+
+impl CameraRay {
+    fn new(ray: Ray3f, pixel_offset: Point2f) -> CameraRay {
+        CameraRay { ray, pixel_offset, weight: 1.0 }
+    }
+}
+
+/// How a camera projects the scene onto its film.
+#[derive(Clone, Debug)]
+pub enum Projection {
+    /// A thin lens, with `fov` degrees across the width of the film. A camera's `Exposure` overrides `aperture`.
+    Perspective { fov: Float, aperture: Float, focus_dist: Float, lens: ThinLens },
+    /// Parallel rays, with `height` world units across the height of the film.
+    Orthographic { height: Float },
+    /// All directions around the camera: longitude across the film and latitude up it.
+    Equirectangular,
+    /// A circular image of `fov` degrees across, fit to the shorter side of the film.
+    Fisheye { fov: Float, mapping: FisheyeMapping },
+    /// A real lens, focused on the target, with its aperture stop closed to `aperture` millimeters across and film
+    /// `film_diagonal` millimeters across the corners. A camera's `Exposure` makes `exposure` physical.
+    Realistic {
+        lens: Vec<LensElement>,
+        aperture: Float,
+        film_diagonal: Float,
+        exposure: LensExposure,
+    },
+}
+
+/// A camera as a scene describes it, to be built once the size of the film is known.
+#[derive(Clone, Debug)]
+pub struct CameraDesc {
+    pub from: Point3f,
+    pub to: Point3f,
+    pub up: Vector3f,
+    pub projection: Projection,
+    /// Times at which the shutter opens and closes.
+    pub shutter: (Float, Float),
+    /// A view for each eye, rather than one from `from`.
+    pub stereo: Option<StereoDesc>,
+    /// Scales the film's response to light as a real camera's would, rather than passing radiance straight through.
+    pub exposure: Option<Exposure>,
+}
+
+impl CameraDesc {
+    /// The size of the film for views of `eye_size`: larger than that with stereo, to hold both views.
+    pub fn film_size(&self, eye_size: Point2u) -> Point2u {
+        self.stereo.map_or(eye_size, |stereo| stereo.layout.film_size(eye_size))
+    }
+
+    /// Builds the camera for views of `eye_size`.
+    pub fn build(&self, eye_size: Point2u) -> Result<Box<dyn Camera>, LensError> {
+        let camera: Box<dyn Camera> = match self.stereo {
+            None => self.build_eye(eye_size, 0.0, StereoRig::Parallel)?,
+            Some(stereo) => {
+                let left = self.build_eye(eye_size, -stereo.interaxial / 2.0, stereo.rig)?;
+                let right = self.build_eye(eye_size, stereo.interaxial / 2.0, stereo.rig)?;
+                Box::new(Stereo::new(left, right, stereo.layout, eye_size))
+            }
+        };
+        Ok(match self.exposure {
+            None => camera,
+            Some(exposure) => {
+                let (open, close) = self.shutter;
+                // Realistic lenses work out how much light reaches the film themselves.
+                let lens_scale = match self.projection {
+                    Projection::Realistic { .. } => 1.0,
+                    _ => exposure.lens_scale(),
+                };
+                Box::new(Exposed::new(camera, lens_scale * exposure.sensor_scale(close - open)))
+            }
+        })
+    }
+
+    /// Builds the camera for the eye `eye` to the right of `from`, set up as in `rig`.
+    fn build_eye(
+        &self, film_size: Point2u, eye: Float, rig: StereoRig,
+    ) -> Result<Box<dyn Camera>, LensError> {
+        let up = self.up;
+        let offset = up.cross(self.from - self.to).normalize() * eye;
+        let from = self.from + offset;
+        let to = iff!(rig == StereoRig::Parallel, self.to + offset, self.to);
+        let (open, close) = self.shutter;
+        Ok(match self.projection {
+            Projection::Perspective { fov, aperture, focus_dist, ref lens } => {
+                let aperture = self.exposure.map_or(aperture, |exposure| exposure.aperture(fov));
+                let camera = Perspective::new(from, to, up, fov, aperture, focus_dist, film_size)
+                    .with_lens(lens.clone())
+                    .with_shutter(open, close);
+                if rig == StereoRig::Parallel {
+                    // Shift the film back toward the middle, so that the target lines up in both views.
+                    Box::new(camera.with_convergence(-eye, (self.to - self.from).magnitude()))
+                } else {
+                    Box::new(camera)
+                }
+            }
+            Projection::Orthographic { height } => Box::new(
+                Orthographic::new(from, to, up, height, film_size).with_shutter(open, close),
+            ),
+            // Panoramas turn the eyes with each direction, rather than moving the whole camera.
+            Projection::Equirectangular => Box::new(
+                Equirectangular::new(self.from, self.to, up, film_size)
+                    .with_eye(eye)
+                    .with_shutter(open, close),
+            ),
+            Projection::Fisheye { fov, mapping } => Box::new(
+                Fisheye::new(from, to, up, fov, mapping, film_size).with_shutter(open, close),
+            ),
+            Projection::Realistic { ref lens, aperture, film_diagonal, exposure } => {
+                let focus_dist = (to - from).magnitude();
+                let exposure = iff!(self.exposure.is_some(), LensExposure::Physical, exposure);
+                Box::new(
+                    Realistic::new(
+                        from,
+                        to,
+                        up,
+                        LensSettings {
+                            elements: lens.clone(),
+                            aperture,
+                            focus_dist,
+                            film_diagonal,
+                            exposure,
+                        },
+                        film_size,
+                    )?
+                    .with_shutter(open, close),
+                )
+            }
+        })
+    }
+}
+
+/// Where a camera is and which way it faces, its film and its shutter.
+///
+/// Directions relative to the camera have x to the right, y up and z forward.
+#[derive(Copy, Clone, Debug)]
+pub struct View {
+    pub origin: Point3f,
+    pub u: Vector3f,
+    pub v: Vector3f,
+    pub w: Vector3f,
+    pub film_size: Point2f,
+    /// Times at which the shutter opens and closes; rays are spread evenly between them.
+    pub shutter_open: Float,
+    pub shutter_close: Float,
+}
+
+impl View {
+    pub fn new(origin: Point3f, target: Point3f, up: Vector3f, film_size: Point2u) -> View {
+        let w = (origin - target).normalize();
+        let u = up.cross(w).normalize();
+        let v = w.cross(u);
+        let film_size = film_size.map(|v| v as Float);
+        View { origin, u, v, w, film_size, shutter_open: 0.0, shutter_close: 0.0 }
+    }
+
+    pub fn with_shutter(self, open: Float, close: Float) -> View {
+        View { shutter_open: open, shutter_close: close, ..self }
+    }
+
+    /// `n` stratified samples within the pixel at `film_pos`: their positions on the film scaled to 0-1, their offsets
+    /// within the pixel, and their times.
+    pub fn samples(&self, n: usize, film_pos: Point2u) -> Vec<(Point2f, Point2f, Float)> {
+        let film_pos = film_pos.map(|v| v as Float);
+        let pixel_offsets = util::stratified_samples(n);
+        // The jittered coordinate of stratified samples is itself stratified, and comes shuffled.
+        let time_samples = util::stratified_samples(n);
+        pixel_offsets
+            .into_iter()
+            .zip(time_samples)
+            .map(|(offset, time_sample)| {
+                let time =
+                    self.shutter_open + (self.shutter_close - self.shutter_open) * time_sample.y;
+                ((film_pos + offset.to_vec()).div_element_wise(self.film_size), offset, time)
+            })
+            .collect()
+    }
+
+    /// Gives `ray`, the one through `film`, the rays that `ray_at` finds through the film a pixel to the right and a
+    /// pixel up as its differentials, if it finds both. They are a whole pixel apart however many samples the pixel
+    /// gets, so that each pass of a progressive render filters textures the same.
+    pub fn with_differentials(
+        &self, ray: Ray3f, film: Point2f, ray_at: impl Fn(Point2f) -> Option<Ray3f>,
+    ) -> Ray3f {
+        let (right, up) = (
+            Vector2f::new(1.0 / self.film_size.x, 0.0),
+            Vector2f::new(0.0, 1.0 / self.film_size.y),
+        );
+        match (ray_at(film + right), ray_at(film + up)) {
+            (Some(rx), Some(ry)) => ray.with_differentials(rx, ry),
+            _ => ray,
+        }
+    }
+
+    /// The world direction of `d`, relative to the camera.
+    pub fn to_world(&self, d: Vector3f) -> Vector3f {
+        self.u * d.x + self.v * d.y - self.w * d.z
+    }
+
+    /// Where `p` is relative to the camera.
+    pub fn to_camera(&self, p: Point3f) -> Vector3f {
+        let d = p - self.origin;
+        Vector3f::new(d.dot(self.u), d.dot(self.v), -d.dot(self.w))
+    }
+}
+
+/// How a `Perspective` camera's thin lens differs from an ideal one with a round aperture.
+#[derive(Clone, Debug)]
+pub struct ThinLens {
+    pub aperture: Aperture,
+    /// How many times taller than wide the aperture is, as with an anamorphic lens, whose bokeh is stretched
+    /// vertically once the image is unsqueezed.
+    pub squeeze: Float,
+    /// How far the opening of the lens barrel slides across the aperture toward the corners of the film, in aperture
+    /// radii at the corners. Light outside of the opening is blocked, which darkens the corners and clips their bokeh
+    /// into cat's eyes. Zero for none.
+    pub cats_eye: Float,
+    /// Degrees the plane of focus turns about the camera's horizontal and vertical axes, as with a tilt-shift lens
+    /// (the Scheimpflug principle).
+    pub tilt: Vector2f,
+}
+
+impl Default for ThinLens {
+    fn default() -> ThinLens {
+        ThinLens { aperture: Aperture::Circle, squeeze: 1.0, cats_eye: 0.0, tilt: Vector2f::zero() }
+    }
+}
+
+pub struct Perspective {
+    view: View,
     /// Lower left corner of the transformed image plane.
     lower_left: Point3f,
     /// Horizontal edge of the transformed image plane.
@@ -12,68 +263,330 @@ pub struct Camera {
     /// Vertical edge of the transformed image plane.
     vertical: Vector3f,
     lens_radius: Float,
-    u: Vector3f,
-    v: Vector3f,
-    w: Vector3f,
-    film_size: Point2f,
-    pixel_size: Point2f,
+    lens: ThinLens,
+    /// Normal of the plane of focus, which passes through the center of the image plane.
+    focus_normal: Vector3f,
 }
-impl Camera {
+
+impl Perspective {
     pub fn new(
         origin: Point3f, target: Point3f, up: Vector3f, fov: Float, aperture: Float,
         focus_dist: Float, film_size: Point2u,
-    ) -> Camera {
+    ) -> Perspective {
         let theta = fov * PI / 180.0;
 
         let aspect_ratio = film_size.x as Float / film_size.y as Float;
         let half_width = (theta / 2.0).tan();
         let half_height = half_width / aspect_ratio;
 
-        let w = (origin - target).normalize();
-        let u = up.cross(w).normalize();
-        let v = w.cross(u);
-        let film_size = film_size.map(|v| v as Float);
-        let pixel_size = film_size.map(|v| 1.0 / v);
-
-        Camera {
-            origin,
+        let view = View::new(origin, target, up, film_size);
+        let (u, v, w) = (view.u, view.v, view.w);
+        Perspective {
+            view,
             lower_left: origin - (u * half_width + v * half_height + w) * focus_dist,
             horizontal: u * (half_width + half_width) * focus_dist,
             vertical: v * (half_height + half_height) * focus_dist,
             lens_radius: aperture / 2.0,
-            u,
-            v,
-            w,
-            film_size,
-            pixel_size,
+            lens: ThinLens::default(),
+            focus_normal: w,
         }
     }
 
-    pub fn get_rays(&self, n: usize, film_pos: Point2u) -> Vec<(Ray3f, Point2f)> {
-        // scale film_pos to 0-1
-        let film_pos = film_pos.map(|v| v as Float).div_element_wise(self.film_size);
+    /// Shifts the film sideways so that the point `distance` ahead and `offset` to the right of the camera is in the
+    /// middle of the image, as for an eye of a parallel stereo rig.
+    pub fn with_convergence(self, offset: Float, distance: Float) -> Perspective {
+        let focus_dist = (self.view.origin - self.lower_left).dot(self.view.w);
+        let shift = self.view.u * (offset * focus_dist / distance);
+        Perspective { lower_left: self.lower_left + shift, ..self }
+    }
+
+    pub fn with_lens(self, lens: ThinLens) -> Perspective {
+        let (u, v) = (self.view.u, self.view.v);
+        let tilt = Matrix3::from_axis_angle(v, Rad(lens.tilt.y * PI / 180.0))
+            * Matrix3::from_axis_angle(u, Rad(lens.tilt.x * PI / 180.0));
+        Perspective { focus_normal: tilt * self.view.w, lens, ..self }
+    }
+
+    /// Keeps the shutter open from `open` to `close`, so that moving primitives are blurred over that interval.
+    pub fn with_shutter(self, open: Float, close: Float) -> Perspective {
+        Perspective { view: self.view.with_shutter(open, close), ..self }
+    }
+}
 
-        let mut lens_samples = util::stratified_samples_in_disk(n);
+impl Camera for Perspective {
+    fn get_rays(&self, n: usize, film_pos: Point2u) -> Vec<CameraRay> {
+        let mut lens_samples = util::stratified_samples(n);
         util::shuffle(&mut lens_samples);
-        let pixel_offsets = util::stratified_samples(n);
-        lens_samples
-            .iter()
-            .zip(pixel_offsets)
-            .map(|(lens_offset, pixel_offset)| {
-                let lens_offset = lens_offset * self.lens_radius;
-                let lens_pos = self.u * lens_offset.x + self.v * lens_offset.y;
-                let origin = self.origin + lens_pos;
-                (
-                    Ray3f::new(
-                        origin,
-                        (self.lower_left
-                            + self.horizontal * (film_pos.x + pixel_offset.x * self.pixel_size.x)
-                            + self.vertical * (film_pos.y + pixel_offset.y * self.pixel_size.y))
-                            - origin,
-                    ),
-                    pixel_offset,
-                )
+        let image_center = self.lower_left + (self.horizontal + self.vertical) / 2.0;
+        self.view
+            .samples(n, film_pos)
+            .into_iter()
+            .zip(lens_samples)
+            .map(|((film, pixel_offset, time), lens_sample)| {
+                let on_aperture = self.lens.aperture.sample(lens_sample);
+                // Where the film position is, scaled so the corners are 1 from the center.
+                let off_axis = (film - Point2f::new(0.5, 0.5))
+                    .mul_element_wise(self.view.film_size.to_vec())
+                    / (self.view.film_size.to_vec().magnitude() / 2.0);
+                // Only a cat's eye clips the aperture, as masks reach out to the corners of [-1,1]².
+                let blocked = self.lens.cats_eye > 0.0
+                    && (on_aperture.to_vec() - off_axis * self.lens.cats_eye).magnitude2() > 1.0;
+
+                let lens_offset = Vector2f::new(on_aperture.x / self.lens.squeeze, on_aperture.y)
+                    * self.lens_radius;
+                let lens_pos = self.view.u * lens_offset.x + self.view.v * lens_offset.y;
+                let origin = self.view.origin + lens_pos;
+                let ray_at = |film: Point2f| {
+                    // Aim through the center of the lens to find the point on the plane of focus that all the rays
+                    // for this film position converge on.
+                    let aim = self.lower_left + self.horizontal * film.x + self.vertical * film.y;
+                    let through_center = aim - self.view.origin;
+                    let t = (image_center - self.view.origin).dot(self.focus_normal)
+                        / through_center.dot(self.focus_normal);
+                    let focus =
+                        iff!(t > 0.0 && t.is_finite(), self.view.origin + through_center * t, aim);
+                    Ray3f::new_at(origin, focus - origin, time)
+                };
+                let ray =
+                    self.view.with_differentials(ray_at(film), film, |film| Some(ray_at(film)));
+                CameraRay { ray, pixel_offset, weight: iff!(blocked, 0.0, 1.0) }
             })
             .collect()
     }
+
+    fn project(&self, p: Point3f) -> Option<Point2f> {
+        let d = p - self.view.origin;
+        let depth = -d.dot(self.view.w);
+        if depth <= 0.0 {
+            return None;
+        }
+        // Scale d to reach the image plane, which is the focus distance in front of the lens.
+        let focus_dist = (self.view.origin - self.lower_left).dot(self.view.w);
+        let on_plane = self.view.origin + d * (focus_dist / depth) - self.lower_left;
+        Some(Point2f::new(
+            on_plane.dot(self.horizontal) / self.horizontal.magnitude2() * self.view.film_size.x,
+            on_plane.dot(self.vertical) / self.vertical.magnitude2() * self.view.film_size.y,
+        ))
+    }
+}
+
+/// Parallel rays from a rectangle centered on the camera's position, so that sizes don't shrink with distance.
+pub struct Orthographic {
+    view: View,
+    /// Size of the rectangle the rays start from.
+    size: Vector2f,
+}
+
+impl Orthographic {
+    pub fn new(
+        origin: Point3f, target: Point3f, up: Vector3f, height: Float, film_size: Point2u,
+    ) -> Orthographic {
+        let aspect_ratio = film_size.x as Float / film_size.y as Float;
+        Orthographic {
+            view: View::new(origin, target, up, film_size),
+            size: Vector2f::new(height * aspect_ratio, height),
+        }
+    }
+
+    pub fn with_shutter(self, open: Float, close: Float) -> Orthographic {
+        Orthographic { view: self.view.with_shutter(open, close), ..self }
+    }
+}
+
+impl Camera for Orthographic {
+    fn get_rays(&self, n: usize, film_pos: Point2u) -> Vec<CameraRay> {
+        self.view
+            .samples(n, film_pos)
+            .into_iter()
+            .map(|(film, pixel_offset, time)| {
+                let ray_at = |film: Point2f| {
+                    let offset = (film - Point2f::new(0.5, 0.5)).mul_element_wise(self.size);
+                    let origin = self.view.origin + self.view.to_world(offset.extend(0.0));
+                    Ray3f::new_at(origin, -self.view.w, time)
+                };
+                let ray =
+                    self.view.with_differentials(ray_at(film), film, |film| Some(ray_at(film)));
+                CameraRay::new(ray, pixel_offset)
+            })
+            .collect()
+    }
+
+    fn project(&self, p: Point3f) -> Option<Point2f> {
+        let d = self.view.to_camera(p);
+        if d.z <= 0.0 {
+            return None;
+        }
+        let film = d.truncate().div_element_wise(self.size) + Vector2f::new(0.5, 0.5);
+        Some(Point2f::from_vec(film).mul_element_wise(self.view.film_size))
+    }
+}
+
+/// Every direction around the camera, with longitude across the film and latitude up it, for environment maps and
+/// 360° video. The middle of the film looks at the target.
+pub struct Equirectangular {
+    view: View,
+    /// How far to the right of the origin, looking in each direction, rays start.
+    eye: Float,
+}
+
+impl Equirectangular {
+    pub fn new(
+        origin: Point3f, target: Point3f, up: Vector3f, film_size: Point2u,
+    ) -> Equirectangular {
+        Equirectangular { view: View::new(origin, target, up, film_size), eye: 0.0 }
+    }
+
+    /// Starts each ray `eye` to the right of the origin, as seen looking along it, for one eye of an omni-directional
+    /// stereo panorama (Peleg et al., "Omnistereo: Panoramic Stereo Imaging"). The eyes come together toward the poles,
+    /// where there's no telling left from right.
+    pub fn with_eye(self, eye: Float) -> Equirectangular {
+        Equirectangular { eye, ..self }
+    }
+
+    pub fn with_shutter(self, open: Float, close: Float) -> Equirectangular {
+        Equirectangular { view: self.view.with_shutter(open, close), ..self }
+    }
+}
+
+impl Camera for Equirectangular {
+    fn get_rays(&self, n: usize, film_pos: Point2u) -> Vec<CameraRay> {
+        self.view
+            .samples(n, film_pos)
+            .into_iter()
+            .map(|(film, pixel_offset, time)| {
+                let ray_at = |film: Point2f| {
+                    let longitude = (film.x - 0.5) * 2.0 * PI;
+                    let latitude = (film.y - 0.5) * PI;
+                    let d = Vector3f::new(
+                        latitude.cos() * longitude.sin(),
+                        latitude.sin(),
+                        latitude.cos() * longitude.cos(),
+                    );
+                    let right = Vector3f::new(longitude.cos(), 0.0, -longitude.sin());
+                    let origin =
+                        self.view.origin + self.view.to_world(right * (self.eye * latitude.cos()));
+                    Ray3f::new_at(origin, self.view.to_world(d), time)
+                };
+                let ray =
+                    self.view.with_differentials(ray_at(film), film, |film| Some(ray_at(film)));
+                CameraRay::new(ray, pixel_offset)
+            })
+            .collect()
+    }
+
+    fn project(&self, p: Point3f) -> Option<Point2f> {
+        let d = self.view.to_camera(p);
+        let distance = d.magnitude();
+        if distance == 0.0 {
+            return None;
+        }
+        let longitude = Float::atan2(d.x, d.z);
+        let latitude = clamp!(d.y / distance, -1.0, 1.0).asin();
+        Some(Point2f::new(
+            (longitude / (2.0 * PI) + 0.5) * self.view.film_size.x,
+            (latitude / PI + 0.5) * self.view.film_size.y,
+        ))
+    }
+}
+
+/// How far from the center of a fisheye image a direction lands, given its angle from the optical axis.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance in proportion to the angle, so angles measure evenly across the image.
+    Equidistant,
+    /// Distance in proportion to sin(angle/2), so equal solid angles cover equal areas of the image.
+    Equisolid,
+}
+
+impl FisheyeMapping {
+    /// The distance from the center of the image, as a fraction of its radius, of a direction at `angle` from the
+    /// axis, where the edge of the image is at `max_angle`.
+    fn radius(self, angle: Float, max_angle: Float) -> Float {
+        match self {
+            FisheyeMapping::Equidistant => angle / max_angle,
+            FisheyeMapping::Equisolid => (angle / 2.0).sin() / (max_angle / 2.0).sin(),
+        }
+    }
+
+    /// The inverse of `radius`.
+    fn angle(self, radius: Float, max_angle: Float) -> Float {
+        match self {
+            FisheyeMapping::Equidistant => radius * max_angle,
+            FisheyeMapping::Equisolid => {
+                2.0 * clamp!(radius * (max_angle / 2.0).sin(), -1.0, 1.0).asin()
+            }
+        }
+    }
+}
+
+/// A circular fisheye image, inscribed in the film, of up to 360° across. The film outside of the circle stays black.
+pub struct Fisheye {
+    view: View,
+    /// Half the field of view, in radians: the angle from the axis at the edge of the image.
+    max_angle: Float,
+    mapping: FisheyeMapping,
+}
+
+impl Fisheye {
+    pub fn new(
+        origin: Point3f, target: Point3f, up: Vector3f, fov: Float, mapping: FisheyeMapping,
+        film_size: Point2u,
+    ) -> Fisheye {
+        let max_angle = min!(fov, 360.0) * PI / 360.0;
+        Fisheye { view: View::new(origin, target, up, film_size), max_angle, mapping }
+    }
+
+    pub fn with_shutter(self, open: Float, close: Float) -> Fisheye {
+        Fisheye { view: self.view.with_shutter(open, close), ..self }
+    }
+
+    /// The radius of the image circle, in pixels.
+    fn image_radius(&self) -> Float {
+        min!(self.view.film_size.x, self.view.film_size.y) / 2.0
+    }
+}
+
+impl Camera for Fisheye {
+    fn get_rays(&self, n: usize, film_pos: Point2u) -> Vec<CameraRay> {
+        let center = self.view.film_size / 2.0;
+        self.view
+            .samples(n, film_pos)
+            .into_iter()
+            .filter_map(|(film, pixel_offset, time)| {
+                let ray_at = |film: Point2f| {
+                    let offset = (film.mul_element_wise(self.view.film_size) - center.to_vec())
+                        / self.image_radius();
+                    let radius = offset.to_vec().magnitude();
+                    if radius > 1.0 {
+                        return None;
+                    }
+                    let angle = self.mapping.angle(radius, self.max_angle);
+                    let azimuth = Float::atan2(offset.y, offset.x);
+                    let d = Vector3f::new(
+                        angle.sin() * azimuth.cos(),
+                        angle.sin() * azimuth.sin(),
+                        angle.cos(),
+                    );
+                    Some(Ray3f::new_at(self.view.origin, self.view.to_world(d), time))
+                };
+                let ray = self.view.with_differentials(ray_at(film)?, film, ray_at);
+                Some(CameraRay::new(ray, pixel_offset))
+            })
+            .collect()
+    }
+
+    fn project(&self, p: Point3f) -> Option<Point2f> {
+        let d = self.view.to_camera(p);
+        let distance = d.magnitude();
+        if distance == 0.0 {
+            return None;
+        }
+        let angle = clamp!(d.z / distance, -1.0, 1.0).acos();
+        if angle > self.max_angle {
+            return None;
+        }
+        let radius = self.mapping.radius(angle, self.max_angle) * self.image_radius();
+        let azimuth = Float::atan2(d.y, d.x);
+        Some(self.view.film_size / 2.0 + Vector2f::new(azimuth.cos(), azimuth.sin()) * radius)
+    }
 }
@@ -0,0 +1,126 @@
+use crate::bsdf::*;
+use crate::geom::*;
+use crate::material::*;
+use crate::microfacet::*;
+use crate::prims::*;
+use crate::texture::*;
+use crate::types::*;
+
+/// A metal, reflecting off GGX microfacets as much light as the Fresnel equations give for its complex index of
+/// refraction.
+#[derive(Copy, Clone, Debug)]
+pub struct Conductor<R = Vector2f> {
+    /// The real part of the index of refraction, for red, green and blue.
+    pub eta: Vector3f,
+    /// The imaginary part, which is how strongly the metal absorbs light.
+    pub k: Vector3f,
+    /// The GGX roughness along the surface's `dpdu`, and across it. 0 is a mirror.
+    pub roughness: R,
+}
+
+impl<R> Conductor<R> {
+    pub fn new(metal: MeasuredMetal, roughness: R) -> Conductor<R> {
+        let (eta, k) = metal.ior();
+        Conductor { eta, k, roughness }
+    }
+}
+
+impl Conductor {
+    fn fresnel(&self, cos_theta_i: Float) -> Vector3f {
+        fresnel_conductor(cos_theta_i, self.eta, self.k)
+    }
+}
+
+/// Common metals whose indices of refraction have been measured.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MeasuredMetal {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl MeasuredMetal {
+    /// The real and imaginary parts of the index of refraction, for red, green and blue. These are fitted to the
+    /// measured spectra.
+    pub fn ior(self) -> (Vector3f, Vector3f) {
+        match self {
+            MeasuredMetal::Gold => {
+                (Vector3f::new(0.143, 0.374, 1.442), Vector3f::new(3.983, 2.385, 1.603))
+            }
+            MeasuredMetal::Copper => {
+                (Vector3f::new(0.2, 0.924, 1.102), Vector3f::new(3.912, 2.452, 2.142))
+            }
+            MeasuredMetal::Aluminium => {
+                (Vector3f::new(1.657, 0.88, 0.521), Vector3f::new(9.224, 6.27, 4.837))
+            }
+            MeasuredMetal::Silver => {
+                (Vector3f::new(0.155, 0.117, 0.138), Vector3f::new(4.828, 3.122, 2.147))
+            }
+        }
+    }
+}
+
+/// Fresnel reflectance of unpolarized light off a conductor whose index of refraction, relative to the outside, is
+/// `eta` + i`k` (PBRT 8.2.1).
+fn fresnel_conductor(cos_theta_i: Float, eta: Vector3f, k: Vector3f) -> Vector3f {
+    let cos2_theta = clamp!(cos_theta_i, 0.0, 1.0).powi(2);
+    let sin2_theta = 1.0 - cos2_theta;
+    let channel = |eta: Float, k: Float| {
+        let t0 = eta * eta - k * k - sin2_theta;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2_theta;
+        let a = max!(0.0, (a2_plus_b2 + t0) / 2.0).sqrt();
+        let t2 = 2.0 * cos2_theta.sqrt() * a;
+        let r_perp = (t1 - t2) / (t1 + t2);
+        let t3 = cos2_theta * a2_plus_b2 + sin2_theta * sin2_theta;
+        let t4 = t2 * sin2_theta;
+        let r_parl = r_perp * (t3 - t4) / (t3 + t4);
+        (r_parl + r_perp) / 2.0
+    };
+    Vector3f::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
+
+impl Bsdf for Conductor {
+    fn eval(&self, wo: Vector3f, wi: Vector3f) -> Vector3f {
+        let distribution = TrowbridgeReitz::new(self.roughness);
+        if distribution.is_smooth() {
+            return Vector3f::zero();
+        }
+        match distribution.reflection(wo, wi) {
+            Some((wm, mirrors)) => self.fresnel(wo.dot(wm).abs()) * mirrors,
+            None => Vector3f::zero(),
+        }
+    }
+
+    fn pdf(&self, wo: Vector3f, wi: Vector3f) -> Float {
+        let distribution = TrowbridgeReitz::new(self.roughness);
+        iff!(distribution.is_smooth(), 0.0, distribution.reflection_pdf(wo, wi))
+    }
+
+    fn sample(&self, wo: Vector3f, u: [Float; 3]) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        let distribution = TrowbridgeReitz::new(self.roughness);
+        if distribution.is_smooth() {
+            let wi = Vector3f::new(-wo.x, -wo.y, wo.z);
+            let weight = self.fresnel(wo.z.abs());
+            return Some(BsdfSample { wi, weight, specular: true, eta: 1.0 });
+        }
+        let wi = distribution.sample_reflection(wo, Point2f::new(u[1], u[2]))?;
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let weight = self.eval(wo, wi) * (wi.z.abs() / pdf);
+        Some(BsdfSample { wi, weight, specular: false, eta: 1.0 })
+    }
+}
+
+impl<R: Texture<Vector2f>> Material for Conductor<R> {
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        let metal = Conductor { eta: self.eta, k: self.k, roughness: self.roughness.evaluate(hit) };
+        scatter_bsdf(&metal, in_, hit)
+    }
+}
@@ -0,0 +1,101 @@
+use crate::geom::*;
+use crate::shape::*;
+use crate::types::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// Everything in the first solid that isn't in the second.
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOp::Union => in_a || in_b,
+            CsgOp::Intersection => in_a && in_b,
+            CsgOp::Difference => in_a && !in_b,
+        }
+    }
+}
+
+/// Two solids combined by constructive solid geometry. A `Csg` is itself a `Solid`, so they nest into trees.
+///
+/// Hits keep the uv and dpdu of whichever solid's surface they are on.
+pub struct Csg<A: Solid, B: Solid> {
+    op: CsgOp,
+    a: A,
+    b: B,
+}
+
+impl<A: Solid, B: Solid> Csg<A, B> {
+    pub fn union(a: A, b: B) -> Csg<A, B> {
+        Csg { op: CsgOp::Union, a, b }
+    }
+
+    pub fn intersection(a: A, b: B) -> Csg<A, B> {
+        Csg { op: CsgOp::Intersection, a, b }
+    }
+
+    /// Cuts `b` out of `a`.
+    pub fn difference(a: A, b: B) -> Csg<A, B> {
+        Csg { op: CsgOp::Difference, a, b }
+    }
+}
+
+impl<A: Solid, B: Solid> Solid for Csg<A, B> {
+    fn crossings(&self, r: Ray3f) -> Vec<Crossing> {
+        // Sweep along the line through both solids' crossings in order, keeping the ones where the result's inside
+        // changes.
+        let (a, b) = (self.a.crossings(r), self.b.crossings(r));
+        let mut crossings = Vec::with_capacity(a.len() + b.len());
+        let (mut i, mut j) = (0, 0);
+        let (mut in_a, mut in_b) = (false, false);
+        while i < a.len() || j < b.len() {
+            let from_a = j == b.len() || (i < a.len() && a[i].t <= b[j].t);
+            let was_inside = self.op.inside(in_a, in_b);
+            let mut crossing = if from_a {
+                in_a = !in_a;
+                i += 1;
+                a[i - 1]
+            } else {
+                in_b = !in_b;
+                j += 1;
+                b[j - 1]
+            };
+            if self.op.inside(in_a, in_b) != was_inside {
+                // Where the result's surface is the second solid's, its outside is the second solid's inside.
+                if !from_a && self.op == CsgOp::Difference {
+                    crossing.hit.normal = -crossing.hit.normal;
+                }
+                crossings.push(crossing);
+            }
+        }
+        crossings
+    }
+}
+
+impl<A: Solid, B: Solid> Shape for Csg<A, B> {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        if !self.bounding_box()?.intersect(r) {
+            return None;
+        }
+        self.crossings(r).into_iter().find(|c| c.t > 0.000_001).map(|c| c.hit)
+    }
+
+    fn point_error(&self, r: &Ray3f, hit: &Hit) -> Vector3f {
+        // The hit could be on either solid.
+        let (a, b) = (self.a.point_error(r, hit), self.b.point_error(r, hit));
+        Vector3f::new(max!(a.x, b.x), max!(a.y, b.y), max!(a.z, b.z))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let (a, b) = (self.a.bounding_box()?, self.b.bounding_box()?);
+        match self.op {
+            CsgOp::Union => Some(a.union(&b)),
+            CsgOp::Intersection => Some(a.intersection(&b)),
+            CsgOp::Difference => Some(a),
+        }
+    }
+}
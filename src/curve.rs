@@ -0,0 +1,218 @@
+use crate::geom::*;
+use crate::shape::*;
+use crate::types::*;
+use crate::util::*;
+
+/// How a curve is shaded across its width.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CurveType {
+    /// A ribbon that always faces the ray, with its normal pointing back along it.
+    Flat,
+    /// A ribbon that always faces the ray, with its normal bent across the width as if it were a tube.
+    Cylinder,
+}
+
+/// A cubic Bézier curve swept with a width that changes linearly along it, for hair and fur.
+///
+/// A single box bounds a long curve poorly, so `split` cuts it into segments, each of which is a separate primitive in
+/// the BVH. u runs along the whole curve and v across it, both from 0 to 1.
+#[derive(Copy, Clone, Debug)]
+pub struct Curve {
+    /// Control points of this segment.
+    control: [Point3f; 4],
+    /// The range of the whole curve's u that this segment covers.
+    u: [Float; 2],
+    /// Widths at the two ends of the whole curve.
+    width: [Float; 2],
+    kind: CurveType,
+}
+
+/// Deepest the intersection test subdivides a segment, however curved it is.
+const MAX_DEPTH: i32 = 10;
+
+impl Curve {
+    pub fn new(control: [Point3f; 4], width: [Float; 2], kind: CurveType) -> Curve {
+        Curve { control, u: [0.0, 1.0], width, kind }
+    }
+
+    /// Cuts the curve into `n` segments of equal length in u.
+    pub fn split(&self, n: usize) -> Vec<Curve> {
+        (0..n)
+            .map(|i| {
+                let (u0, u1) = (i as Float / n as Float, (i + 1) as Float / n as Float);
+                let (w0, w1) = (self.local(u0), self.local(u1));
+                let cp = &self.control;
+                Curve {
+                    control: [
+                        blossom(cp, w0, w0, w0),
+                        blossom(cp, w0, w0, w1),
+                        blossom(cp, w0, w1, w1),
+                        blossom(cp, w1, w1, w1),
+                    ],
+                    u: [self.global(w0), self.global(w1)],
+                    ..*self
+                }
+            })
+            .collect()
+    }
+
+    /// Maps the whole curve's u to this segment's Bézier parameter.
+    fn local(&self, u: Float) -> Float {
+        (u - self.u[0]) / (self.u[1] - self.u[0])
+    }
+
+    fn global(&self, w: Float) -> Float {
+        self.u[0] + (self.u[1] - self.u[0]) * w
+    }
+
+    fn width_at(&self, u: Float) -> Float {
+        self.width[0] + (self.width[1] - self.width[0]) * u
+    }
+
+    /// Looks for the closest hit along a ray on the part of the curve between `u0` and `u1`, whose control points
+    /// `cp` have been moved to a space where the ray starts at the origin and runs along +z (Nakamura and Tanaka,
+    /// "Ray Tracing of Hair Curves", as in PBRT). `best` holds the closest hit so far, as the ray distance and u.
+    fn recursive_intersect(
+        &self, cp: &[Point3f; 4], u0: Float, u1: Float, depth: i32,
+        best: &mut Option<(Float, Float)>,
+    ) {
+        let z_max = best.map(|(t, _)| t).unwrap_or(FLOAT_MAX);
+        if depth > 0 {
+            let halves = split_bezier(cp);
+            let u_mid = (u0 + u1) / 2.0;
+            for &(start, u0, u1) in [(0, u0, u_mid), (3, u_mid, u1)].iter() {
+                let half = [halves[start], halves[start + 1], halves[start + 2], halves[start + 3]];
+                let half_width = max!(self.width_at(u0), self.width_at(u1)) / 2.0;
+                let b = half.iter().fold(AABB::empty(), |b, p| b.union_p(p)).padded(half_width);
+                // Only halves whose bounds the ray passes through can hold a hit.
+                if b.max.x < 0.0 || b.min.x > 0.0 || b.max.y < 0.0 || b.min.y > 0.0 {
+                    continue;
+                }
+                if b.max.z < 0.0 || b.min.z > z_max {
+                    continue;
+                }
+                self.recursive_intersect(&half, u0, u1, depth - 1, best);
+            }
+            return;
+        }
+
+        // The segment is close enough to a line now. The ray must pass between the lines perpendicular to the curve
+        // at its ends.
+        let edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        if edge < 0.0 {
+            return;
+        }
+        let edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if edge < 0.0 {
+            return;
+        }
+        // Find the closest point on the line to the ray, in the projection onto the xy plane.
+        let segment = Vector2f::new(cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+        let denom = segment.magnitude2();
+        if denom == 0.0 {
+            return;
+        }
+        let w = clamp!(Vector2f::new(-cp[0].x, -cp[0].y).dot(segment) / denom, 0.0, 1.0);
+        let u = u0 + (u1 - u0) * w;
+        let width = self.width_at(u);
+        let (pc, _) = eval_bezier(cp, w);
+        if pc.x * pc.x + pc.y * pc.y > width * width / 4.0 {
+            return;
+        }
+        if pc.z <= 0.000_001 || pc.z > z_max {
+            return;
+        }
+        *best = Some((pc.z, u));
+    }
+}
+
+impl Shape for Curve {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        // Move the control points to a space where the ray runs along +z from the origin.
+        let (x, y) = coordinate_system(r.direction);
+        let mut cp = [Point3f::origin(); 4];
+        for (ray_p, p) in cp.iter_mut().zip(self.control.iter()) {
+            let d = p - r.origin;
+            *ray_p = Point3f::new(d.dot(x), d.dot(y), d.dot(r.direction));
+        }
+
+        // Subdivide until the segments are flat to within a small fraction of the curve's width.
+        let l0 = (0..2).fold(0.0, |l: Float, i| {
+            let d = cp[i].to_vec() - cp[i + 1].to_vec() * 2.0 + cp[i + 2].to_vec();
+            max!(l, d.x.abs(), d.y.abs(), d.z.abs())
+        });
+        let eps = max!(self.width_at(self.u[0]), self.width_at(self.u[1])) * 0.05;
+        let depth = iff!(
+            l0 > 0.0,
+            clamp!(
+                (Float::sqrt(2.0) * 6.0 * l0 / (8.0 * eps)).log2().floor() as i32 / 2,
+                0,
+                MAX_DEPTH
+            ),
+            0
+        );
+
+        let mut best = None;
+        self.recursive_intersect(&cp, self.u[0], self.u[1], depth, &mut best);
+        let (t, u) = best?;
+
+        let point = r.origin + r.direction * t;
+        let (center, tangent) = eval_bezier(&self.control, self.local(u));
+        let along = tangent.normalize();
+        // The ribbon faces back along the ray, turned about the curve.
+        let facing = -r.direction;
+        let flat = (facing - along * facing.dot(along)).normalize();
+        let side = along.cross(flat);
+        let offset = clamp!((point - center).dot(side) / (self.width_at(u) / 2.0), -1.0, 1.0);
+        let normal = match self.kind {
+            CurveType::Flat => flat,
+            CurveType::Cylinder => {
+                let angle = offset * PI / 2.0;
+                flat * angle.cos() + side * angle.sin()
+            }
+        };
+        Some(Hit {
+            point,
+            normal,
+            uv: Point2f::new(u, (offset + 1.0) / 2.0),
+            dpdu: tangent / (self.u[1] - self.u[0]),
+            dpdv: side * self.width_at(u),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let half_width = max!(self.width_at(self.u[0]), self.width_at(self.u[1])) / 2.0;
+        Some(self.control.iter().fold(AABB::empty(), |b, p| b.union_p(p)).padded(half_width))
+    }
+}
+
+/// Returns the point on the Bézier curve with control points `cp` at `t`, and the derivative there.
+fn eval_bezier(cp: &[Point3f; 4], t: Float) -> (Point3f, Vector3f) {
+    let lerp = |a: Point3f, b: Point3f| a + (b - a) * t;
+    let cp1 = [lerp(cp[0], cp[1]), lerp(cp[1], cp[2]), lerp(cp[2], cp[3])];
+    let cp2 = [lerp(cp1[0], cp1[1]), lerp(cp1[1], cp1[2])];
+    let derivative = iff!(
+        (cp2[1] - cp2[0]).magnitude2() > 0.0,
+        (cp2[1] - cp2[0]) * 3.0,
+        // The curve's end touches a coincident control point.
+        cp[3] - cp[0]
+    );
+    (lerp(cp2[0], cp2[1]), derivative)
+}
+
+/// Splits the curve at its middle, returning the two halves' control points, which share the middle one.
+fn split_bezier(cp: &[Point3f; 4]) -> [Point3f; 7] {
+    let mid = |a: Point3f, b: Point3f| a.midpoint(b);
+    let (m01, m12, m23) = (mid(cp[0], cp[1]), mid(cp[1], cp[2]), mid(cp[2], cp[3]));
+    let (m012, m123) = (mid(m01, m12), mid(m12, m23));
+    [cp[0], m01, m012, mid(m012, m123), m123, m23, cp[3]]
+}
+
+/// The blossom of the curve at (a, b, c); the control points of the part of the curve between t0 and t1 are the
+/// blossoms at (t0, t0, t0), (t0, t0, t1), (t0, t1, t1) and (t1, t1, t1).
+fn blossom(cp: &[Point3f; 4], a: Float, b: Float, c: Float) -> Point3f {
+    let lerp = |t: Float, p: Point3f, q: Point3f| p + (q - p) * t;
+    let a1 = [lerp(a, cp[0], cp[1]), lerp(a, cp[1], cp[2]), lerp(a, cp[2], cp[3])];
+    let b2 = [lerp(b, a1[0], a1[1]), lerp(b, a1[1], a1[2])];
+    lerp(c, b2[0], b2[1])
+}
@@ -0,0 +1,208 @@
+use crate::bsdf::*;
+use crate::geom::*;
+use crate::material::*;
+use crate::microfacet::*;
+use crate::prims::*;
+use crate::texture::*;
+use crate::types::*;
+use crate::util::*;
+
+/// Glass whose surface may be rough, reflecting off and refracting through GGX microfacets (Walter et al.,
+/// "Microfacet Models for Refraction through Rough Surfaces", as in PBRT v4), and which may absorb light inside.
+///
+/// It expects normals to point out of the glass.
+#[derive(Copy, Clone, Debug)]
+pub struct RoughDielectric<E = Float, R = Vector2f, A = Vector3f> {
+    /// The index of refraction of the inside, relative to the outside.
+    pub eta: E,
+    /// The GGX roughness along the surface's `dpdu`, and across it. 0 is polished glass.
+    pub roughness: R,
+    /// How much of each of red, green and blue the inside absorbs, per unit of distance (Beer-Lambert). It is looked
+    /// up where light leaves the inside.
+    pub absorption: A,
+}
+
+impl<E, R> RoughDielectric<E, R> {
+    /// Clear glass.
+    pub fn new(eta: E, roughness: R) -> RoughDielectric<E, R> {
+        RoughDielectric { eta, roughness, absorption: Vector3f::zero() }
+    }
+
+    /// Tints the glass so that `color` is the fraction of the light that makes it through `distance` of it, which is
+    /// easier to pick than absorption.
+    pub fn with_transmittance(self, color: Vector3f, distance: Float) -> RoughDielectric<E, R> {
+        let absorption = color.map(|c| -max!(c, 1e-6).ln() / distance);
+        RoughDielectric { absorption, ..self }
+    }
+}
+
+impl RoughDielectric {
+    /// The microfacet normal that scatters `wo` into `wi`, facing the outside, and the ratio of the indices of
+    /// refraction across the boundary when `wi` is transmitted; or None if no microfacet does, or it faces away.
+    fn half_vector(&self, wo: Vector3f, wi: Vector3f) -> Option<(Vector3f, Float)> {
+        let (cos_theta_o, cos_theta_i) = (wo.z, wi.z);
+        let reflect = cos_theta_o * cos_theta_i > 0.0;
+        let etap = iff!(reflect, 1.0, iff!(cos_theta_o > 0.0, self.eta, 1.0 / self.eta));
+        let wm = wi * etap + wo;
+        if cos_theta_i == 0.0 || cos_theta_o == 0.0 || wm.magnitude2() == 0.0 {
+            return None;
+        }
+        let wm = wm.normalize();
+        let wm = iff!(wm.z < 0.0, -wm, wm);
+        // Microfacets facing away from either direction can't scatter between them.
+        if wm.dot(wi) * cos_theta_i < 0.0 || wm.dot(wo) * cos_theta_o < 0.0 {
+            return None;
+        }
+        Some((wm, etap))
+    }
+}
+
+/// Refracts `w` through a boundary with normal `n`, from the side `w` is on, into a medium `eta` times as dense as the
+/// side `n` points to; or None for total internal reflection. Also returns the ratio of the indices across the
+/// boundary, in the direction the light travels.
+fn transmit(w: Vector3f, n: Vector3f, eta: Float) -> Option<(Vector3f, Float)> {
+    let cos_theta_i = w.dot(n);
+    let (n, eta, cos_theta_i) =
+        iff!(cos_theta_i < 0.0, (-n, 1.0 / eta, -cos_theta_i), (n, eta, cos_theta_i));
+    let sin2_theta_t = max!(0.0, 1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = safe_sqrt(1.0 - sin2_theta_t);
+    Some((-w / eta + n * (cos_theta_i / eta - cos_theta_t), eta))
+}
+
+impl Bsdf for RoughDielectric {
+    fn eval(&self, wo: Vector3f, wi: Vector3f) -> Vector3f {
+        let distribution = TrowbridgeReitz::new(self.roughness);
+        if self.eta == 1.0 || distribution.is_smooth() {
+            return Vector3f::zero();
+        }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(half) => half,
+            None => return Vector3f::zero(),
+        };
+        let f = fresnel_dielectric(wo.dot(wm), self.eta);
+        let dg = distribution.d(wm) * distribution.g(wo, wi);
+        let value = if wo.z * wi.z > 0.0 {
+            dg * f / (4.0 * wo.z * wi.z).abs()
+        } else {
+            let denom = (wi.dot(wm) + wo.dot(wm) / etap).powi(2) * wi.z * wo.z;
+            // Radiance is squeezed into a narrower cone as it enters denser glass.
+            dg * (1.0 - f) * (wi.dot(wm) * wo.dot(wm) / denom).abs() / (etap * etap)
+        };
+        Vector3f::from_value(value)
+    }
+
+    fn pdf(&self, wo: Vector3f, wi: Vector3f) -> Float {
+        let distribution = TrowbridgeReitz::new(self.roughness);
+        if self.eta == 1.0 || distribution.is_smooth() {
+            return 0.0;
+        }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(half) => half,
+            None => return 0.0,
+        };
+        // Reflection and transmission are picked in proportion to the Fresnel reflectance off the microfacet.
+        let r = fresnel_dielectric(wo.dot(wm), self.eta);
+        if wo.z * wi.z > 0.0 {
+            distribution.visible_d(wo, wm) / (4.0 * wo.dot(wm).abs()) * r
+        } else {
+            let dwm_dwi = wi.dot(wm).abs() / (wi.dot(wm) + wo.dot(wm) / etap).powi(2);
+            distribution.visible_d(wo, wm) * dwm_dwi * (1.0 - r)
+        }
+    }
+
+    fn sample(&self, wo: Vector3f, u: [Float; 3]) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        let distribution = TrowbridgeReitz::new(self.roughness);
+        if self.eta == 1.0 || distribution.is_smooth() {
+            let r = fresnel_dielectric(wo.z, self.eta);
+            return if u[0] < r {
+                Some(BsdfSample {
+                    wi: Vector3f::new(-wo.x, -wo.y, wo.z),
+                    weight: Vector3f::from_value(1.0),
+                    specular: true,
+                    eta: 1.0,
+                })
+            } else {
+                let (wi, etap) = transmit(wo, Vector3f::unit_z(), self.eta)?;
+                let weight = Vector3f::from_value(1.0 / (etap * etap));
+                Some(BsdfSample { wi, weight, specular: true, eta: etap })
+            };
+        }
+        let wm = distribution.sample_visible(wo, Point2f::new(u[1], u[2]));
+        let r = fresnel_dielectric(wo.dot(wm), self.eta);
+        let reflect = u[0] < r;
+        let wi = iff!(reflect, wm * (2.0 * wo.dot(wm)) - wo, transmit(wo, wm, self.eta)?.0);
+        // Off a steep microfacet, the light can end up on the wrong side of the surface.
+        if (wo.z * wi.z > 0.0) != reflect {
+            return None;
+        }
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let weight = self.eval(wo, wi) * (wi.z.abs() / pdf);
+        let eta = iff!(reflect, 1.0, iff!(wo.z > 0.0, self.eta, 1.0 / self.eta));
+        Some(BsdfSample { wi, weight, specular: false, eta })
+    }
+}
+
+impl<E, R, A> Material for RoughDielectric<E, R, A>
+where
+    E: Texture<Float>,
+    R: Texture<Vector2f>,
+    A: Texture<Vector3f>,
+{
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        let glass = RoughDielectric {
+            eta: self.eta.evaluate(hit),
+            roughness: self.roughness.evaluate(hit),
+            absorption: self.absorption.evaluate(hit),
+        };
+        let (ray, weight) = scatter_bsdf(&glass, in_, hit)?;
+        // A ray arriving at the inside of the surface has been crossing the glass since it last scattered.
+        if in_.direction.dot(hit.normal) > 0.0 {
+            let distance = hit.t * in_.direction.magnitude();
+            let transmittance = (-glass.absorption * distance).map(Float::exp);
+            return Some((ray, weight.mul_element_wise(transmittance)));
+        }
+        Some((ray, weight))
+    }
+}
+
+/// A thin sheet of glass, like a window pane, whose two sides are so close that light leaves it going the way it
+/// came in. The light bouncing between the sides is summed up in how much it reflects.
+#[derive(Copy, Clone, Debug)]
+pub struct ThinDielectric<E = Float> {
+    /// The index of refraction of the glass, relative to around it.
+    pub eta: E,
+}
+
+impl Bsdf for ThinDielectric {
+    fn eval(&self, _wo: Vector3f, _wi: Vector3f) -> Vector3f {
+        Vector3f::zero()
+    }
+
+    fn pdf(&self, _wo: Vector3f, _wi: Vector3f) -> Float {
+        0.0
+    }
+
+    fn sample(&self, wo: Vector3f, u: [Float; 3]) -> Option<BsdfSample> {
+        let r = fresnel_dielectric(wo.z.abs(), self.eta);
+        // Each pass through reflects r of the light and transmits the rest, which sums to this over all the bounces.
+        let r = iff!(r < 1.0, r + (1.0 - r).powi(2) * r / (1.0 - r * r), r);
+        let wi = iff!(u[0] < r, Vector3f::new(-wo.x, -wo.y, wo.z), -wo);
+        // Light passing through comes out parallel to how it went in, as if there were no glass.
+        Some(BsdfSample { wi, weight: Vector3f::from_value(1.0), specular: true, eta: 1.0 })
+    }
+}
+
+impl<E: Texture<Float>> Material for ThinDielectric<E> {
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        scatter_bsdf(&ThinDielectric { eta: self.eta.evaluate(hit) }, in_, hit)
+    }
+}
@@ -0,0 +1,62 @@
+use crate::camera::*;
+use crate::types::*;
+
+/// Width of 35mm film, in meters, which perspective cameras' fields of view are taken to be across.
+const FILM_WIDTH: Float = 0.036;
+
+/// Photographic exposure settings, which scale the film's response to light as a real camera's would. Radiance is
+/// taken to be in cd/m², and the camera's shutter interval in seconds.
+#[derive(Copy, Clone, Debug)]
+pub struct Exposure {
+    /// The focal length over the diameter of the aperture. This also sets the aperture of perspective cameras, and so
+    /// their depth of field; realistic cameras keep their own aperture stop.
+    pub f_number: Float,
+    /// The film's sensitivity, as an ISO speed.
+    pub iso: Float,
+}
+
+impl Exposure {
+    /// The diameter of the aperture, in meters, of a lens with `fov` degrees across 35mm film.
+    pub fn aperture(&self, fov: Float) -> Float {
+        let focal_length = FILM_WIDTH / 2.0 / (fov * PI / 360.0).tan();
+        focal_length / self.f_number
+    }
+
+    /// From the irradiance on the film, in lux, to pixel values, with the shutter open for `duration` seconds. With a
+    /// saturation based speed rating (ISO 12232), pixels saturate at 78/iso lux seconds.
+    pub fn sensor_scale(&self, duration: Float) -> Float {
+        duration * self.iso / 78.0
+    }
+
+    /// From the luminance of the scene to the irradiance on the film: π/4 over the square of the f-number, times the
+    /// transmission and vignetting of a typical lens, which ISO 12232 takes to be 0.65 altogether.
+    pub fn lens_scale(&self) -> Float {
+        0.65 / (self.f_number * self.f_number)
+    }
+}
+
+/// Scales the weight of another camera's rays, to expose its film.
+pub struct Exposed {
+    camera: Box<dyn Camera>,
+    scale: Float,
+}
+
+impl Exposed {
+    pub fn new(camera: Box<dyn Camera>, scale: Float) -> Exposed {
+        Exposed { camera, scale }
+    }
+}
+
+impl Camera for Exposed {
+    fn get_rays(&self, n: usize, film_pos: Point2u) -> Vec<CameraRay> {
+        let mut rays = self.camera.get_rays(n, film_pos);
+        for ray in rays.iter_mut() {
+            ray.weight *= self.scale;
+        }
+        rays
+    }
+
+    fn project(&self, p: Point3f) -> Option<Point2f> {
+        self.camera.project(p)
+    }
+}
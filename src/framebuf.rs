@@ -1,3 +1,5 @@
+use num::traits::AsPrimitive;
+
 use crate::types::*;
 
 #[derive(Copy, Clone)]
@@ -23,20 +25,23 @@ impl Pixel {
     }
 
     pub fn to_rgb(&self) -> [u8; 3] {
-        let mut rgb = self.rgb;
-        rgb /= self.count as Float;
-        let v = rgb.map(|v| (v * 255.99) as u8);
+        let v = self.mean().map(|v| (v * 255.99) as u8);
         [v.x, v.y, v.z]
     }
 
     pub fn x(&self) -> f32 {
-        (self.rgb.x / self.count as Float) as f32
+        self.mean().x.as_()
     }
     pub fn y(&self) -> f32 {
-        (self.rgb.y / self.count as Float) as f32
+        self.mean().y.as_()
     }
     pub fn z(&self) -> f32 {
-        (self.rgb.z / self.count as Float) as f32
+        self.mean().z.as_()
+    }
+
+    /// Black for pixels that got no samples, such as those outside a fisheye's image circle.
+    fn mean(&self) -> Vector3f {
+        iff!(self.count == 0, Vector3f::zero(), self.rgb / self.count as Float)
     }
 }
 
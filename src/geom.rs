@@ -5,11 +5,46 @@ pub struct Ray3f {
     pub origin: Point3f,
     pub direction: Vector3f,
     pub inv_d: Vector3f,
+    /// The moment within the camera's shutter interval that the ray samples.
+    pub time: Float,
+    /// The rays through the neighbouring pixels, for working out how much of the scene a pixel sees, if the ray has
+    /// only been scattered specularly since it left the camera.
+    pub differentials: Option<Differentials>,
 }
 
-impl Ray3f where {
+/// The rays a pixel to the right of a ray and a pixel above it on the film, followed through the same bounces.
+#[derive(Copy, Clone, Debug)]
+pub struct Differentials {
+    pub rx_origin: Point3f,
+    pub rx_direction: Vector3f,
+    pub ry_origin: Point3f,
+    pub ry_direction: Vector3f,
+}
+
+impl Ray3f {
     pub fn new(origin: Point3f, direction: Vector3f) -> Self {
+        Self::new_at(origin, direction, 0.0)
+    }
+
+    pub fn new_at(origin: Point3f, direction: Vector3f, time: Float) -> Self {
         let direction = direction.normalize();
-        Self { origin, direction, inv_d: Vector3f::from_value(1.0).div_element_wise(direction) }
+        Self {
+            origin,
+            direction,
+            inv_d: Vector3f::from_value(1.0).div_element_wise(direction),
+            time,
+            differentials: None,
+        }
+    }
+
+    /// The same ray, with `rx` and `ry` as the rays through the neighbouring pixels.
+    pub fn with_differentials(self, rx: Ray3f, ry: Ray3f) -> Self {
+        let differentials = Differentials {
+            rx_origin: rx.origin,
+            rx_direction: rx.direction,
+            ry_origin: ry.origin,
+            ry_direction: ry.direction,
+        };
+        Self { differentials: Some(differentials), ..self }
     }
 }
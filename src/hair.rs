@@ -0,0 +1,355 @@
+use crate::bsdf::*;
+use crate::geom::*;
+use crate::material::*;
+use crate::prims::*;
+use crate::types::*;
+use crate::util::*;
+
+/// Number of scattering lobes modeled separately: R, TT and TRT. The light leaving after more bounces inside the
+/// fiber is lumped into one more lobe.
+const P_MAX: usize = 3;
+
+/// Scattering from hair fibers (Chiang et al., "A Practical and Controllable Hair and Fur Model for Production Path
+/// Tracing", as in PBRT).
+///
+/// It expects the hit to be on a `Curve`, whose `dpdu` runs along the fiber and whose v goes across it.
+#[derive(Copy, Clone, Debug)]
+pub struct Hair {
+    /// Absorption coefficient inside the fiber, per unit of the fiber's diameter.
+    pub sigma_a: Vector3f,
+    /// Index of refraction of the fiber.
+    pub eta: Float,
+    /// Longitudinal roughness, from 0 to 1.
+    pub beta_m: Float,
+    /// Azimuthal roughness, from 0 to 1.
+    pub beta_n: Float,
+    /// Tilt of the cuticle scales, in degrees.
+    pub alpha: Float,
+}
+
+impl Hair {
+    /// Hair colored by the concentrations of the two melanin pigments; eumelanin from 0 (blonde) through 1.3 (brown)
+    /// to 8 (black), with pheomelanin adding red.
+    pub fn from_melanin(eumelanin: Float, pheomelanin: Float) -> Hair {
+        let eumelanin_sigma_a = Vector3f::new(0.419, 0.697, 1.37);
+        let pheomelanin_sigma_a = Vector3f::new(0.187, 0.4, 1.05);
+        Hair {
+            sigma_a: eumelanin_sigma_a * eumelanin + pheomelanin_sigma_a * pheomelanin,
+            ..Hair::default()
+        }
+    }
+
+    /// Hair whose multiple scattering comes out roughly `color`, which is easier to pick than absorption. It depends
+    /// on the azimuthal roughness, so `beta_n` shouldn't change afterwards.
+    pub fn from_color(color: Vector3f, beta_n: Float) -> Hair {
+        let b = beta_n;
+        let k = 5.969 - 0.215 * b + 2.532 * b.powi(2) - 10.73 * b.powi(3)
+            + 5.574 * b.powi(4)
+            + 0.245 * b.powi(5);
+        let sigma_a = color.map(|c| (c.ln() / k).powi(2));
+        Hair { sigma_a, beta_n, ..Hair::default() }
+    }
+}
+
+impl Default for Hair {
+    fn default() -> Hair {
+        Hair {
+            sigma_a: Vector3f::new(0.06, 0.1, 0.2),
+            eta: 1.55,
+            beta_m: 0.3,
+            beta_n: 0.3,
+            alpha: 2.0,
+        }
+    }
+}
+
+impl Material for Hair {
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        let ([x, y, z], h) = fiber_frame(-in_.direction, hit);
+        let to_local = |v: Vector3f| Vector3f::new(v.dot(x), v.dot(y), v.dot(z));
+        let wo = to_local(-in_.direction);
+
+        let lobes = HairLobes::new(self, h);
+        let (wi, weight) = lobes.sample(wo, [random(), random(), random(), random()])?;
+        let wi_world = x * wi.x + y * wi.y + z * wi.z;
+        Some((hit.spawn_ray(wi_world, in_.time), weight))
+    }
+}
+
+/// The frame a hit on a fiber scatters in, which has x along the fiber and z facing `wo` across it, and the hit's
+/// offset h from the fiber's axis along y, from -1 to 1.
+fn fiber_frame(wo: Vector3f, hit: &SurfaceInteraction) -> ([Vector3f; 3], Float) {
+    let x = hit.dpdu.normalize();
+    let z = (wo - x * wo.dot(x)).normalize();
+    let y = z.cross(x);
+    // A curve's v goes across it along x × z, which is -y.
+    ([x, y, z], 1.0 - 2.0 * hit.uv.y)
+}
+
+/// The parts of the hair model that depend on where across the fiber it was hit, but not on the directions.
+struct HairLobes<'a> {
+    hair: &'a Hair,
+    /// Offset of the hit from the fiber's axis, from -1 to 1.
+    h: Float,
+    gamma_o: Float,
+    /// Longitudinal variance of each lobe.
+    v: [Float; P_MAX + 1],
+    /// Logistic scale of the azimuthal distribution.
+    s: Float,
+    /// Sines and cosines of 2^k alpha, by which the scales tilt the lobes.
+    sin_2k_alpha: [Float; 3],
+    cos_2k_alpha: [Float; 3],
+}
+
+impl<'a> HairLobes<'a> {
+    fn new(hair: &'a Hair, h: Float) -> HairLobes<'a> {
+        let (beta_m, beta_n) = (hair.beta_m, hair.beta_n);
+        let v0 = (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20)).powi(2);
+        let s =
+            (PI / 8.0).sqrt() * (0.265 * beta_n + 1.194 * beta_n.powi(2) + 5.372 * beta_n.powi(22));
+        let mut sin_2k_alpha = [(hair.alpha * PI / 180.0).sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [safe_sqrt(1.0 - sin_2k_alpha[0].powi(2)), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+        HairLobes {
+            hair,
+            h,
+            gamma_o: safe_asin(h),
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    /// The angle of refraction into the fiber, seen across it, and the transmittance of one pass through it.
+    fn refraction(&self, sin_theta_o: Float, cos_theta_o: Float) -> (Float, Vector3f) {
+        let eta = self.hair.eta;
+        let sin_theta_t = sin_theta_o / eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        // The modified index of refraction for the projection onto the plane across the fiber.
+        let etap = (eta * eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let sin_gamma_t = self.h / etap;
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+        let transmittance =
+            (-self.hair.sigma_a * (2.0 * cos_gamma_t / cos_theta_t)).map(Float::exp);
+        (safe_asin(sin_gamma_t), transmittance)
+    }
+
+    /// The fraction of light in each lobe.
+    fn attenuation(&self, cos_theta_o: Float, transmittance: Vector3f) -> [Vector3f; P_MAX + 1] {
+        let cos_gamma_o = safe_sqrt(1.0 - self.h * self.h);
+        let f = fresnel_dielectric(cos_theta_o * cos_gamma_o, self.hair.eta);
+        let mut ap = [Vector3f::zero(); P_MAX + 1];
+        ap[0] = Vector3f::from_value(f);
+        ap[1] = transmittance * (1.0 - f).powi(2);
+        for p in 2..P_MAX {
+            ap[p] = ap[p - 1].mul_element_wise(transmittance) * f;
+        }
+        let tf = transmittance * f;
+        ap[P_MAX] =
+            ap[P_MAX - 1].mul_element_wise(tf).div_element_wise(Vector3f::from_value(1.0) - tf);
+        ap
+    }
+
+    /// The outgoing elevation of lobe `p`, tilted by the scales.
+    fn tilted(&self, p: usize, sin_theta_o: Float, cos_theta_o: Float) -> (Float, Float) {
+        let (sin_a, cos_a, sign) = match p {
+            0 => (self.sin_2k_alpha[1], self.cos_2k_alpha[1], -1.0),
+            1 => (self.sin_2k_alpha[0], self.cos_2k_alpha[0], 1.0),
+            2 => (self.sin_2k_alpha[2], self.cos_2k_alpha[2], 1.0),
+            _ => return (sin_theta_o, cos_theta_o),
+        };
+        let sin_theta_op = sin_theta_o * cos_a + sign * cos_theta_o * sin_a;
+        let cos_theta_op = cos_theta_o * cos_a - sign * sin_theta_o * sin_a;
+        (sin_theta_op, cos_theta_op.abs())
+    }
+
+    /// The sum over the lobes of `weights` times each lobe's distribution of directions. Without the cosine, the
+    /// weights are the BSDF; normalized to sum to one, they are the sampling density.
+    fn lobes_sum(&self, wo: Vector3f, wi: Vector3f, weights: &[Vector3f; P_MAX + 1]) -> Vector3f {
+        let (sin_theta_o, sin_theta_i) = (wo.x, wi.x);
+        let (cos_theta_o, cos_theta_i) =
+            (safe_sqrt(1.0 - sin_theta_o.powi(2)), safe_sqrt(1.0 - sin_theta_i.powi(2)));
+        let phi = Float::atan2(wi.z, wi.y) - Float::atan2(wo.z, wo.y);
+        let (gamma_t, _) = self.refraction(sin_theta_o, cos_theta_o);
+        let mut sum = Vector3f::zero();
+        for (p, weight) in weights[..P_MAX].iter().enumerate() {
+            let (sin_theta_op, cos_theta_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+            let mp = longitudinal(cos_theta_i, cos_theta_op, sin_theta_i, sin_theta_op, self.v[p]);
+            sum += weight * (mp * self.azimuthal(phi, p, gamma_t));
+        }
+        let mp = longitudinal(cos_theta_i, cos_theta_o, sin_theta_i, sin_theta_o, self.v[P_MAX]);
+        sum + weights[P_MAX] * (mp / (2.0 * PI))
+    }
+
+    /// The distribution of the change in azimuth for lobe `p`, centered on the direction of perfect specular
+    /// transport through the fiber.
+    fn azimuthal(&self, phi: Float, p: usize, gamma_t: Float) -> Float {
+        let p = p as Float;
+        let mut dphi = phi - (2.0 * p * gamma_t - 2.0 * self.gamma_o + p * PI);
+        while dphi > PI {
+            dphi -= 2.0 * PI;
+        }
+        while dphi < -PI {
+            dphi += 2.0 * PI;
+        }
+        trimmed_logistic(dphi, self.s, -PI, PI)
+    }
+
+    /// Samples an incident direction for `wo` in the local frame, returning it with the BSDF times cosine over the
+    /// density.
+    fn sample(&self, wo: Vector3f, u: [Float; 4]) -> Option<(Vector3f, Vector3f)> {
+        let sin_theta_o = wo.x;
+        let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+        let (gamma_t, transmittance) = self.refraction(sin_theta_o, cos_theta_o);
+        let ap = self.attenuation(cos_theta_o, transmittance);
+        let total: Float = ap.iter().map(luminance).sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut ap_pdf = [0.0; P_MAX + 1];
+        for (pdf, ap) in ap_pdf.iter_mut().zip(ap.iter()) {
+            *pdf = luminance(ap) / total;
+        }
+
+        // Pick a lobe in proportion to its share of the light.
+        let mut p = 0;
+        let mut u0 = u[0];
+        while p < P_MAX && u0 >= ap_pdf[p] {
+            u0 -= ap_pdf[p];
+            p += 1;
+        }
+
+        // Sample the longitudinal distribution around the tilted specular direction.
+        let (sin_theta_op, cos_theta_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+        let v = self.v[p];
+        let u1 = max!(u[1], 1e-5);
+        let cos_theta = 1.0 + v * (u1 + (1.0 - u1) * (-2.0 / v).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (2.0 * PI * u[2]).cos();
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+
+        // And the azimuthal one.
+        let dphi = if p < P_MAX {
+            let pf = p as Float;
+            (2.0 * pf * gamma_t - 2.0 * self.gamma_o + pf * PI)
+                + sample_trimmed_logistic(u[3], self.s, -PI, PI)
+        } else {
+            2.0 * PI * u[3]
+        };
+        let phi_i = Float::atan2(wo.z, wo.y) + dphi;
+        let wi = Vector3f::new(sin_theta_i, cos_theta_i * phi_i.cos(), cos_theta_i * phi_i.sin());
+
+        let mut pdf_weights = [Vector3f::zero(); P_MAX + 1];
+        for (w, &pdf) in pdf_weights.iter_mut().zip(ap_pdf.iter()) {
+            *w = Vector3f::from_value(pdf);
+        }
+        let pdf = self.lobes_sum(wo, wi, &pdf_weights).x;
+        if pdf <= 0.0 {
+            return None;
+        }
+        // The cosine in the rendering equation cancels the one the model divides by.
+        Some((wi, self.lobes_sum(wo, wi, &ap) / pdf))
+    }
+}
+
+fn safe_asin(x: Float) -> Float {
+    clamp!(x, -1.0, 1.0).asin()
+}
+
+/// The longitudinal scattering function: the distribution of the incident elevation around the reflection of the
+/// outgoing one, with variance `v`.
+fn longitudinal(
+    cos_theta_i: Float, cos_theta_o: Float, sin_theta_i: Float, sin_theta_o: Float, v: Float,
+) -> Float {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // Low roughness overflows the direct formula; work with logarithms.
+        (log_i0(a) - b - 1.0 / v + LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        ((-b).exp() * i0(a)) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+/// The modified Bessel function of the first kind, of order 0.
+fn i0(x: Float) -> Float {
+    let mut val = 0.0;
+    let mut x2i = 1.0;
+    let mut ifact = 1.0;
+    let mut i4 = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as Float;
+        }
+        val += x2i / (i4 * ifact * ifact);
+        x2i *= x * x;
+        i4 *= 4.0;
+    }
+    val
+}
+
+fn log_i0(x: Float) -> Float {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+fn logistic(x: Float, s: Float) -> Float {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: Float, s: Float) -> Float {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+/// The logistic distribution restricted to [a, b].
+fn trimmed_logistic(x: Float, s: Float, a: Float, b: Float) -> Float {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: Float, s: Float, a: Float, b: Float) -> Float {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    clamp!(x, a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::*;
+
+    #[test]
+    fn offset_follows_the_frame() {
+        let curve = Curve::new(
+            [
+                Point3f::new(-1.0, 0.0, 0.0),
+                Point3f::new(-0.3, 0.0, 0.0),
+                Point3f::new(0.3, 0.0, 0.0),
+                Point3f::new(1.0, 0.0, 0.0),
+            ],
+            [0.2, 0.2],
+            CurveType::Cylinder,
+        );
+        let fiber = ShapePrimitive::new(curve, Hair::default());
+        for &(offset, direction) in &[
+            (0.05, Vector3f::new(0.0, 0.0, -1.0)),
+            (-0.05, Vector3f::new(0.0, 0.0, -1.0)),
+            (0.05, Vector3f::new(0.0, 0.0, 1.0)),
+            (-0.05, Vector3f::new(0.3, 0.2, -1.0)),
+        ] {
+            let origin = Point3f::new(0.0, offset, 0.0) - direction * 5.0;
+            let hit = fiber.intersect(Ray3f::new(origin, direction)).expect("hits the fiber");
+            let ([_, y, _], h) = fiber_frame(-direction, &hit);
+            let across = (hit.point - Point3f::new(hit.point.x, 0.0, 0.0)).dot(y);
+            assert!(across * h > 0.0, "offset {} along y but h = {}", across, h);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::bsdf::*;
+use crate::dielectric::*;
+use crate::geom::*;
+use crate::material::*;
+use crate::prims::*;
+use crate::texture::*;
+use crate::types::*;
+use crate::util::*;
+
+/// The most times light goes back down into the coat before it is taken to be absorbed.
+const MAX_BOUNCES: usize = 32;
+
+/// A base material under a coat of dielectric, like car paint or lacquered wood. The light is followed on a random
+/// walk between the coat's surface and the base, which takes the layers to be infinitely wide and infinitely close,
+/// so that it leaves where it came in (Guo et al., "Position-Free Monte Carlo Simulation for Arbitrary Layered
+/// BSDFs").
+pub struct Coated<M: Material, E = Float, R = Vector2f, A = Vector3f, T = Float> {
+    /// The coat's surface, and its index of refraction, roughness and absorption.
+    pub coat: RoughDielectric<E, R, A>,
+    /// How thick the coat is, in the units of its absorption. Light crossing it at an angle goes further.
+    pub thickness: T,
+    /// What the coat is on, which is hit from inside the coat.
+    pub base: M,
+}
+
+impl<M, E, R, A, T> Material for Coated<M, E, R, A, T>
+where
+    M: Material,
+    E: Texture<Float>,
+    R: Texture<Vector2f>,
+    A: Texture<Vector3f>,
+    T: Texture<Float>,
+{
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        let coat = RoughDielectric {
+            eta: self.coat.eta.evaluate(hit),
+            roughness: self.coat.roughness.evaluate(hit),
+            absorption: self.coat.absorption.evaluate(hit),
+        };
+        let thickness = self.thickness.evaluate(hit);
+        // The fraction of the light that makes it across the coat, at an angle with cosine `cos_theta` to the normal.
+        let transmittance =
+            |cos_theta: Float| (-coat.absorption * (thickness / cos_theta.abs())).map(Float::exp);
+        // The coat is on whichever side the ray comes from.
+        let normal = hit.shading.normal;
+        let normal = iff!(in_.direction.dot(normal) > 0.0, -normal, normal);
+        let frame = Frame::new(normal, hit.shading.dpdu);
+        let leave = |wi: Vector3f, weight: Vector3f| {
+            Some((hit.spawn_ray(frame.to_world(wi), in_.time), weight))
+        };
+
+        let wo = frame.to_local(-in_.direction.normalize());
+        let sample = coat.sample(wo, [random(), random(), random()])?;
+        if sample.wi.z > 0.0 {
+            // Off the coat's surface without reaching the base, as a mirror would when the coat is smooth.
+            if sample.specular {
+                let ray = hit.spawn_specular_ray(&in_, frame.to_world(sample.wi), sample.eta);
+                return Some((ray, sample.weight));
+            }
+            return leave(sample.wi, sample.weight);
+        }
+        let mut weight = sample.weight;
+        let mut down = sample.wi;
+        for bounce in 0..MAX_BOUNCES {
+            // Down across the coat, off the base and back up across it.
+            weight.mul_assign_element_wise(transmittance(down.z));
+            let (ray, base_weight) =
+                self.base.scatter(Ray3f::new_at(hit.point, frame.to_world(down), in_.time), hit)?;
+            let up = frame.to_local(ray.direction.normalize());
+            if up.z <= 0.0 {
+                // Lost through the base.
+                return None;
+            }
+            weight.mul_assign_element_wise(base_weight.mul_element_wise(transmittance(up.z)));
+
+            // And out through the coat's surface, or reflected back down off its inside.
+            let sample = coat.sample(-up, [random(), random(), random()])?;
+            weight.mul_assign_element_wise(sample.weight);
+            if sample.wi.z > 0.0 {
+                return leave(sample.wi, weight);
+            }
+            down = sample.wi;
+            if bounce > 3 {
+                let p = min!(max!(weight.x, weight.y, weight.z), 0.95);
+                if random() > p {
+                    return None;
+                }
+                weight /= p;
+            }
+        }
+        None
+    }
+}
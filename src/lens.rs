@@ -0,0 +1,502 @@
+use rayon::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::camera::*;
+use crate::geom::*;
+use crate::material::refract;
+use crate::types::*;
+use crate::util;
+
+/// Lens prescriptions are in millimeters, and scenes are in meters.
+const MM: Float = 0.001;
+/// How many rings of the film, out to its corners, get their own bounds on the exit pupil.
+const PUPIL_RINGS: usize = 64;
+/// How many rays are traced through the lens to find the exit pupil of each ring.
+const PUPIL_SAMPLES: usize = 1 << 16;
+
+/// One interface of a lens system: a spherical surface between two media, or the aperture stop.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LensElement {
+    /// Positive when the center of the sphere is toward the film, and zero for the aperture stop.
+    pub curvature_radius: Float,
+    /// Distance along the axis to the next interface, or to the film after the last one.
+    pub thickness: Float,
+    /// Index of refraction of the medium between this interface and the next; zero for air after the aperture stop.
+    pub eta: Float,
+    pub aperture_radius: Float,
+}
+
+#[derive(Debug)]
+pub enum LensError {
+    Io(io::Error),
+    /// The lens file has no elements.
+    Empty,
+    /// This line of the lens file isn't four numbers.
+    Parse(usize),
+    /// The lens can't bring anything at this distance into focus.
+    CantFocus(Float),
+}
+
+impl fmt::Display for LensError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LensError::Io(err) => write!(f, "can't read lens file: {}", err),
+            LensError::Empty => write!(f, "lens file has no elements"),
+            LensError::Parse(line) => {
+                write!(f, "lens file line {} isn't radius, thickness, eta and aperture", line)
+            }
+            LensError::CantFocus(distance) => {
+                write!(f, "lens can't focus at distance {}", distance)
+            }
+        }
+    }
+}
+
+impl Error for LensError {}
+
+impl From<io::Error> for LensError {
+    fn from(err: io::Error) -> LensError {
+        LensError::Io(err)
+    }
+}
+
+/// Reads a lens prescription in the format of PBRT's lens files: a line per interface, from the front of the lens to
+/// the back, of its radius of curvature, thickness, index of refraction and aperture diameter, with lengths in
+/// millimeters. Lines starting with `#` are comments.
+pub fn load_lens<P: AsRef<Path>>(path: P) -> Result<Vec<LensElement>, LensError> {
+    parse_lens(&fs::read_to_string(path)?)
+}
+
+fn parse_lens(text: &str) -> Result<Vec<LensElement>, LensError> {
+    let elements: Vec<LensElement> = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .map(|(i, line)| {
+            let values: Vec<Float> = line
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| LensError::Parse(i + 1))?;
+            match values[..] {
+                [radius, thickness, eta, aperture] => Ok(LensElement {
+                    curvature_radius: radius * MM,
+                    thickness: thickness * MM,
+                    eta,
+                    aperture_radius: aperture * MM / 2.0,
+                }),
+                _ => Err(LensError::Parse(i + 1)),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    iff!(elements.is_empty(), Err(LensError::Empty), Ok(elements))
+}
+
+/// How the weight of a ray through a `Realistic` camera's lens is scaled.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LensExposure {
+    /// Relative to the middle of the film, so only vignetting darkens the image.
+    Relative,
+    /// By the irradiance on the film, so the image also darkens as the aperture closes or the film moves back.
+    Physical,
+}
+
+/// A lens prescription and how it is set up, for a `Realistic` camera.
+#[derive(Clone, Debug)]
+pub struct LensSettings {
+    pub elements: Vec<LensElement>,
+    /// How far across the aperture stop is opened, in millimeters, if that's smaller than the prescription's.
+    pub aperture: Float,
+    /// The distance from the film that is in focus.
+    pub focus_dist: Float,
+    /// The film's diagonal, in millimeters; 35mm film is 43.3.
+    pub film_diagonal: Float,
+    pub exposure: LensExposure,
+}
+
+/// Where rays leave the rear element, on the plane of its vertex.
+#[derive(Copy, Clone, Debug)]
+struct PupilBounds {
+    min: Point2f,
+    max: Point2f,
+}
+
+impl PupilBounds {
+    fn empty() -> PupilBounds {
+        PupilBounds {
+            min: Point2f::new(FLOAT_MAX, FLOAT_MAX),
+            max: Point2f::new(FLOAT_MIN, FLOAT_MIN),
+        }
+    }
+
+    fn union(self, p: Point2f) -> PupilBounds {
+        PupilBounds {
+            min: Point2f::new(min!(self.min.x, p.x), min!(self.min.y, p.y)),
+            max: Point2f::new(max!(self.max.x, p.x), max!(self.max.y, p.y)),
+        }
+    }
+
+    fn contains(&self, p: Point2f) -> bool {
+        p.x >= self.min.x && p.x <= self.max.x && p.y >= self.min.y && p.y <= self.max.y
+    }
+
+    fn area(&self) -> Float {
+        let d = self.max - self.min;
+        d.x * d.y
+    }
+
+    fn lerp(&self, t: Point2f) -> Point2f {
+        self.min + (self.max - self.min).mul_element_wise(t.to_vec())
+    }
+}
+
+/// A ray relative to the camera, with z forward from the film at z = 0 toward the lens.
+#[derive(Copy, Clone, Debug)]
+struct LensRay {
+    origin: Point3f,
+    direction: Vector3f,
+}
+
+impl LensRay {
+    fn at(&self, t: Float) -> Point3f {
+        self.origin + self.direction * t
+    }
+
+    /// The same ray with z pointing the other way, as the elements are laid out with the scene toward -z.
+    fn flip(self) -> LensRay {
+        let (o, d) = (self.origin, self.direction);
+        LensRay { origin: Point3f::new(o.x, o.y, -o.z), direction: Vector3f::new(d.x, d.y, -d.z) }
+    }
+}
+
+/// A camera that traces rays from its film through each element of a real lens prescription (Kolb et al., "A
+/// Realistic Camera Model for Computer Graphics", following PBRT 6.4). Rays that hit an element's rim or the aperture
+/// stop are blocked, which vignettes the image and shapes its bokeh as the real lens would.
+pub struct Realistic {
+    view: View,
+    elements: Vec<LensElement>,
+    /// Size of the film, in meters.
+    film_extent: Vector2f,
+    /// Bounds on where rays leave the rear element, for each ring of the film from its center out to its corners.
+    pupil_bounds: Vec<PupilBounds>,
+    /// Where the principal planes are, on the film side and then the scene side.
+    principal_planes: [Float; 2],
+    exposure: LensExposure,
+}
+
+impl Realistic {
+    /// A camera with the lens set up as in `settings`, aimed from `origin` at `target`.
+    pub fn new(
+        origin: Point3f, target: Point3f, up: Vector3f, settings: LensSettings, film_size: Point2u,
+    ) -> Result<Realistic, LensError> {
+        let LensSettings { mut elements, aperture, focus_dist, film_diagonal, exposure } = settings;
+        if elements.is_empty() {
+            return Err(LensError::Empty);
+        }
+        for element in elements.iter_mut().filter(|e| e.curvature_radius == 0.0) {
+            element.aperture_radius = min!(element.aperture_radius, aperture * MM / 2.0);
+        }
+        let film_diagonal = film_diagonal * MM;
+        let aspect = Vector2f::new(film_size.x as Float, film_size.y as Float);
+        let mut camera = Realistic {
+            view: View::new(origin, target, up, film_size),
+            elements,
+            film_extent: aspect * (film_diagonal / aspect.magnitude()),
+            pupil_bounds: Vec::new(),
+            principal_planes: [0.0; 2],
+            exposure,
+        };
+
+        let rear = camera.elements.len() - 1;
+        camera.elements[rear].thickness = camera.focus(focus_dist)?;
+        let (principal_planes, _) = camera.thick_lens()?;
+        camera.principal_planes = principal_planes;
+        camera.pupil_bounds = (0..PUPIL_RINGS)
+            .into_par_iter()
+            .map(|i| {
+                let radius = film_diagonal / 2.0;
+                camera.bound_exit_pupil(
+                    radius * i as Float / PUPIL_RINGS as Float,
+                    radius * (i + 1) as Float / PUPIL_RINGS as Float,
+                )
+            })
+            .collect();
+        Ok(camera)
+    }
+
+    pub fn with_shutter(self, open: Float, close: Float) -> Realistic {
+        Realistic { view: self.view.with_shutter(open, close), ..self }
+    }
+
+    fn rear_z(&self) -> Float {
+        self.elements[self.elements.len() - 1].thickness
+    }
+
+    fn front_z(&self) -> Float {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    fn rear_radius(&self) -> Float {
+        self.elements[self.elements.len() - 1].aperture_radius
+    }
+
+    /// Follows `r` from the film out through the lens; None if an element blocks it.
+    fn trace_from_film(&self, r: LensRay) -> Option<LensRay> {
+        let mut r = r.flip();
+        let mut element_z = 0.0;
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            element_z -= element.thickness;
+            let (t, normal) = intersect_element(element, element_z, &r)?;
+            let p = r.at(t);
+            if p.x * p.x + p.y * p.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+            r.origin = p;
+            if let Some(normal) = normal {
+                let eta_t =
+                    iff!(i > 0 && self.elements[i - 1].eta != 0.0, self.elements[i - 1].eta, 1.0);
+                r.direction = refract(r.direction, normal, element.eta / eta_t)?;
+            }
+        }
+        Some(r.flip())
+    }
+
+    /// Follows `r` from the scene in through the lens to the film; None if an element blocks it.
+    fn trace_from_scene(&self, r: LensRay) -> Option<LensRay> {
+        let mut r = r.flip();
+        let mut element_z = -self.front_z();
+        for (i, element) in self.elements.iter().enumerate() {
+            let (t, normal) = intersect_element(element, element_z, &r)?;
+            let p = r.at(t);
+            if p.x * p.x + p.y * p.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+            r.origin = p;
+            if let Some(normal) = normal {
+                let eta_i =
+                    iff!(i == 0 || self.elements[i - 1].eta == 0.0, 1.0, self.elements[i - 1].eta);
+                let eta_t = iff!(element.eta != 0.0, element.eta, 1.0);
+                r.direction = refract(r.direction, normal, eta_i / eta_t)?;
+            }
+            element_z += element.thickness;
+        }
+        Some(r.flip())
+    }
+
+    /// The principal planes and focal points of the lens, each on the film side and then the scene side, found by
+    /// tracing rays parallel to the axis through it from each side.
+    fn thick_lens(&self) -> Result<([Float; 2], [Float; 2]), LensError> {
+        // Close enough to the axis to be paraxial, but not so close that rounding swamps the bending.
+        let x = 0.001 * self.film_extent.magnitude();
+        let from_scene = LensRay {
+            origin: Point3f::new(x, 0.0, self.front_z() + 1.0),
+            direction: -Vector3f::unit_z(),
+        };
+        let from_film = LensRay {
+            origin: Point3f::new(x, 0.0, self.rear_z() - 1.0),
+            direction: Vector3f::unit_z(),
+        };
+        let cant_focus = LensError::CantFocus(FLOAT_MAX);
+        let (p0, f0) =
+            cardinal_points(&from_scene, &self.trace_from_scene(from_scene).ok_or(cant_focus)?);
+        let cant_focus = LensError::CantFocus(FLOAT_MAX);
+        let (p1, f1) =
+            cardinal_points(&from_film, &self.trace_from_film(from_film).ok_or(cant_focus)?);
+        Ok(([p0, p1], [f0, f1]))
+    }
+
+    /// The distance from the rear element to the film that focuses the lens at `focus_dist` from the film, taking the
+    /// lens as a thick lens.
+    fn focus(&self, focus_dist: Float) -> Result<Float, LensError> {
+        let (p, f) = self.thick_lens()?;
+        let focal_length = p[0] - f[0];
+        // Moving the lens away from the film by delta puts the principal planes at p + delta, where the thin lens
+        // equation has to hold across the distance between them.
+        let gap = focus_dist - p[1] + p[0];
+        let c = gap * (gap - 4.0 * focal_length);
+        if c < 0.0 {
+            return Err(LensError::CantFocus(focus_dist));
+        }
+        let delta = 0.5 * (focus_dist - p[1] - p[0] - c.sqrt());
+        Ok(self.rear_z() + delta)
+    }
+
+    /// Bounds where rays from the film between `x0` and `x1` from its center, along the x axis, leave the rear
+    /// element.
+    fn bound_exit_pupil(&self, x0: Float, x1: Float) -> PupilBounds {
+        let rear = 1.5 * self.rear_radius();
+        let rear_bounds =
+            PupilBounds { min: Point2f::new(-rear, -rear), max: Point2f::new(rear, rear) };
+        let mut bounds = PupilBounds::empty();
+        let mut exiting = 0;
+        for i in 0..PUPIL_SAMPLES {
+            let film = Point3f::new(
+                x0 + (x1 - x0) * (i as Float + 0.5) / PUPIL_SAMPLES as Float,
+                0.0,
+                0.0,
+            );
+            let on_rear =
+                rear_bounds.lerp(Point2f::new(radical_inverse(2, i), radical_inverse(3, i)));
+            let p_rear = Point3f::new(on_rear.x, on_rear.y, self.rear_z());
+            if bounds.contains(on_rear)
+                || self
+                    .trace_from_film(LensRay { origin: film, direction: p_rear - film })
+                    .is_some()
+            {
+                bounds = bounds.union(on_rear);
+                exiting += 1;
+            }
+        }
+        if exiting == 0 {
+            return rear_bounds;
+        }
+        // Grow the bounds by about the spacing between samples, which could have just missed the edges.
+        let margin = Vector2f::from_value(
+            2.0 * (rear_bounds.max - rear_bounds.min).magnitude() / (PUPIL_SAMPLES as Float).sqrt(),
+        );
+        PupilBounds { min: bounds.min - margin, max: bounds.max + margin }
+    }
+
+    /// A point on the rear element's plane, within the exit pupil for `film`, for the sample `u` in [0,1)², and the
+    /// area of the bounds it was picked from.
+    fn sample_exit_pupil(&self, film: Point2f, u: Point2f) -> (Point3f, Float) {
+        let r = film.to_vec().magnitude();
+        let ring = r / (self.film_extent.magnitude() / 2.0) * PUPIL_RINGS as Float;
+        let bounds = self.pupil_bounds[min!(ring as usize, PUPIL_RINGS - 1)];
+        // The bounds are for film along the x axis, so rotate them around to where the film point is.
+        let p = bounds.lerp(u);
+        let (sin, cos) = iff!(r != 0.0, (film.y / r, film.x / r), (0.0, 1.0));
+        (Point3f::new(cos * p.x - sin * p.y, sin * p.x + cos * p.y, self.rear_z()), bounds.area())
+    }
+
+    /// Where `film`, scaled to 0-1, is on the film, relative to the camera. The lens inverts the image.
+    fn film_point(&self, film: Point2f) -> Point2f {
+        Point2f::from_vec(
+            (Vector2f::new(0.5, 0.5) - film.to_vec()).mul_element_wise(self.film_extent),
+        )
+    }
+}
+
+impl Camera for Realistic {
+    fn get_rays(&self, n: usize, film_pos: Point2u) -> Vec<CameraRay> {
+        let mut lens_samples = util::stratified_samples(n);
+        util::shuffle(&mut lens_samples);
+        self.view
+            .samples(n, film_pos)
+            .into_iter()
+            .zip(lens_samples)
+            .map(|((film, pixel_offset, time), lens_sample)| {
+                // The ray from `film` through the lens, if it gets through, and how much light it brings.
+                let trace = |film: Point2f| {
+                    let film = self.film_point(film);
+                    let (p_rear, pupil_area) = self.sample_exit_pupil(film, lens_sample);
+                    let p_film = Point3f::new(film.x, film.y, 0.0);
+                    let from_film = LensRay { origin: p_film, direction: p_rear - p_film };
+                    let r = self.trace_from_film(from_film)?;
+                    let cos_theta = from_film.direction.normalize().z;
+                    let cos4_theta = (cos_theta * cos_theta) * (cos_theta * cos_theta);
+                    let weight = match self.exposure {
+                        LensExposure::Relative => {
+                            cos4_theta * pupil_area / self.pupil_bounds[0].area()
+                        }
+                        LensExposure::Physical => {
+                            cos4_theta * pupil_area / (self.rear_z() * self.rear_z())
+                        }
+                    };
+                    let ray = Ray3f::new_at(
+                        self.view.origin + self.view.to_world(r.origin.to_vec()),
+                        self.view.to_world(r.direction),
+                        time,
+                    );
+                    Some((ray, weight))
+                };
+                match trace(film) {
+                    Some((ray, weight)) => {
+                        // The neighbouring rays go through the same point of the exit pupil's bounds, which is
+                        // near enough the same point of the aperture.
+                        let ray = self
+                            .view
+                            .with_differentials(ray, film, |film| trace(film).map(|(ray, _)| ray));
+                        CameraRay { ray, pixel_offset, weight }
+                    }
+                    None => CameraRay {
+                        ray: Ray3f::new_at(
+                            self.view.origin,
+                            self.view.to_world(Vector3f::unit_z()),
+                            time,
+                        ),
+                        pixel_offset,
+                        weight: 0.0,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn project(&self, p: Point3f) -> Option<Point2f> {
+        // Through the thick lens approximation: a pinhole at the scene side principal plane, with the film as far
+        // behind the film side one.
+        let d = self.view.to_camera(p);
+        let [film_side, scene_side] = self.principal_planes;
+        if d.z <= scene_side {
+            return None;
+        }
+        let film = d.truncate() * (-film_side / (d.z - scene_side));
+        let film = Vector2f::new(0.5, 0.5) - film.div_element_wise(self.film_extent);
+        Some(Point2f::from_vec(film).mul_element_wise(self.view.film_size))
+    }
+}
+
+/// Where `r` meets `element`, whose vertex is at `z`, and the normal there, facing back along `r`; the aperture stop is
+/// flat and bends nothing, so it has no normal.
+fn intersect_element(
+    element: &LensElement, z: Float, r: &LensRay,
+) -> Option<(Float, Option<Vector3f>)> {
+    if element.curvature_radius == 0.0 {
+        return Some(((z - r.origin.z) / r.direction.z, None));
+    }
+    let radius = element.curvature_radius;
+    let o = r.origin - Vector3f::new(0.0, 0.0, z + radius);
+    let d = r.direction;
+    let a = d.magnitude2();
+    let b = 2.0 * d.dot(o.to_vec());
+    let c = o.to_vec().magnitude2() - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (t0, t1) = ((-b - root) / (2.0 * a), (-b + root) / (2.0 * a));
+    // Of the sphere's two crossings, the element is the one on the side of the center's vertex.
+    let closer = (d.z > 0.0) != (radius < 0.0);
+    let t = iff!(closer, t0, t1);
+    if t < 0.0 {
+        return None;
+    }
+    let normal = (o + d * t).to_vec().normalize();
+    Some((t, Some(iff!(normal.dot(d) > 0.0, -normal, normal))))
+}
+
+/// The z of the principal plane and the focal point for a ray `r_in` parallel to the axis that leaves the lens as
+/// `r_out`: where `r_out` is as far from the axis as `r_in` was, and where it crosses the axis.
+fn cardinal_points(r_in: &LensRay, r_out: &LensRay) -> (Float, Float) {
+    let t_focus = -r_out.origin.x / r_out.direction.x;
+    let t_principal = (r_in.origin.x - r_out.origin.x) / r_out.direction.x;
+    (r_out.at(t_principal).z, r_out.at(t_focus).z)
+}
+
+/// The digits of `i` in `base`, mirrored around the radix point: a low discrepancy sequence in [0,1).
+fn radical_inverse(base: usize, mut i: usize) -> Float {
+    let inv_base = 1.0 / base as Float;
+    let (mut reversed, mut scale) = (0.0, inv_base);
+    while i > 0 {
+        reversed += (i % base) as Float * scale;
+        i /= base;
+        scale *= inv_base;
+    }
+    reversed
+}
@@ -1,17 +1,41 @@
+#[macro_use]
+mod macros;
+
 mod aggregate;
+mod aperture;
+mod bench;
+mod bsdf;
+mod bump;
+mod bvh;
 mod camera;
+mod conductor;
+mod csg;
+mod curve;
+mod dielectric;
+mod exposure;
 mod framebuf;
 mod geom;
-#[macro_use]
-mod macros;
+mod hair;
+mod layered;
+mod lens;
 mod material;
+mod mesh;
 mod metrics;
+mod microfacet;
 mod prims;
+mod principled;
 mod scene;
+mod sdf;
 mod shape;
+mod stereo;
+mod subdiv;
+mod texture;
+mod transform;
 mod types;
 mod util;
+mod wide_bvh;
 
+use image::GenericImageView;
 use log::info;
 use rayon::prelude::*;
 use std::error::Error;
@@ -20,10 +44,13 @@ use std::thread;
 use std::time;
 
 pub use self::aggregate::*;
+pub use self::bvh::*;
 pub use self::camera::*;
+pub use self::exposure::*;
 pub use self::geom::*;
 pub use self::macros::*;
 pub use self::prims::*;
+pub use self::stereo::*;
 pub use self::types::*;
 pub use self::util::*;
 
@@ -37,6 +64,11 @@ struct Context {
 
     time_per_ray: tacho::Timer,
     time_per_pass: tacho::Timer,
+
+    bvh_build_time: tacho::Stat,
+    bvh_nodes: tacho::Gauge,
+    bvh_leaves: tacho::Gauge,
+    bvh_max_depth: tacho::Gauge,
 }
 
 impl Context {
@@ -44,13 +76,39 @@ impl Context {
         let (metrics, reporter) = tacho::new();
         let time_per_ray = metrics.timer_us("time_per_ray_us");
         let time_per_pass = metrics.timer_us("time_per_pass_us");
+        let bvh_build_time = metrics.stat("bvh_build_time_us");
+        let bvh_nodes = metrics.gauge("bvh_nodes");
+        let bvh_leaves = metrics.gauge("bvh_leaves");
+        let bvh_max_depth = metrics.gauge("bvh_max_depth");
 
-        Context { reporter, time_per_ray, time_per_pass }
+        Context {
+            reporter,
+            time_per_ray,
+            time_per_pass,
+            bvh_build_time,
+            bvh_nodes,
+            bvh_leaves,
+            bvh_max_depth,
+        }
+    }
+
+    fn record_bvh_stats(&self, stats: &BVHStats) {
+        info!("built BVH: {:?}", stats);
+        self.bvh_build_time.add(stats.build_time.as_micros() as u64);
+        self.bvh_nodes.set(stats.nodes);
+        self.bvh_leaves.set(stats.leaves);
+        self.bvh_max_depth.set(stats.max_depth);
     }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     simple_logger::init()?;
+    if std::env::args().nth(1).as_deref() == Some("bench-bvh") {
+        return bench::bvh();
+    }
+    if std::env::args().nth(1).as_deref() == Some("bench-shading") {
+        return bench::shading();
+    }
     let ctx = Context::new();
 
     let sdl_context = sdl2::init()?;
@@ -60,7 +118,37 @@ fn main() -> Result<(), Box<dyn Error>> {
     640; // 960; // 640; // 1920; // 960; //960;
     let height = 1200;
     400; // 600; // 400; // 1200; // 600; // 600;
+    let from = Point3f::new(12.0, 3.0, 3.0);
+    let to = Point3f::new(0.0, 0.0, -1.0);
+    // Or Projection::Orthographic { height: 6.0 }, Projection::Equirectangular,
+    // Projection::Fisheye { fov: 180.0, mapping: FisheyeMapping::Equisolid }, or
+    // Projection::Realistic { lens: lens::load_lens("lenses/dgauss.50mm.dat")?, aperture: 10.0, film_diagonal: 43.3,
+    //                         exposure: lens::LensExposure::Relative }.
+    let projection = Projection::Perspective {
+        fov: 55.0,
+        aperture: 0.1,
+        focus_dist: (to - from).magnitude(),
+        lens: ThinLens::default(),
+    };
+    // Or Some(StereoDesc { interaxial: 0.065, rig: StereoRig::Parallel, layout: StereoLayout::SideBySide }).
+    let stereo = None;
+    // Or Some(Exposure { f_number: 2.8, iso: 800.0 }), for scenes with radiance in cd/m².
+    let exposure = None;
+    let camera_desc = CameraDesc {
+        from,
+        to,
+        up: Vector3f::unit_y(),
+        projection,
+        shutter: (0.0, 1.0),
+        stereo,
+        exposure,
+    };
+    // With stereo, the film holds a view of this size for each eye.
+    let eye_size = Point2u::new(width, height);
+    let Point2u { x: width, y: height } = camera_desc.film_size(eye_size);
     let samples_per_pixel = 256;
+    let split_method = SplitMethod::Object; // SplitMethod::Spatial { alpha: 1e-5 };
+    let wide_bvh = true;
     let aspect_ratio = width as f64 / height as f64;
 
     let last_display = video.display_bounds(video.num_video_displays()? - 1)?.center();
@@ -85,19 +173,19 @@ fn main() -> Result<(), Box<dyn Error>> {
     event_pump.pump_events();
     canvas.window_mut().set_size(winwidth as u32, winheight as u32)?;
 
-    let world = scene::new_cover_scene();
+    // Or scene::motion_scene()?, scene::materials_scene(), scene::textures_scene()? or scene::bump_scene()?.
+    let prims = scene::cover_scene();
+    let world: Box<dyn Primitive> = if wide_bvh {
+        let world = WideAggregate::with_split_method(prims, split_method)?;
+        ctx.record_bvh_stats(world.stats());
+        Box::new(world)
+    } else {
+        let world = Aggregate::with_split_method(prims, split_method)?;
+        ctx.record_bvh_stats(world.stats());
+        Box::new(world)
+    };
 
-    let from = Point3f::new(12.0, 3.0, 3.0);
-    let to = Point3f::new(0.0, 0.0, -1.0);
-    let c = Camera::new(
-        from,
-        to,
-        Vector3f::unit_y(),
-        /* fov */ 55.0,
-        /* aperture */ 0.1,
-        /*focus_dist */ (to - from).magnitude(),
-        /* film_size */ Point2u::new(width, height),
-    );
+    let c = camera_desc.build(eye_size)?;
 
     let (tx, rx) = sync_channel(100);
     thread::spawn({
@@ -109,7 +197,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             let mut i = 1;
             while i < samples_per_pixel {
                 info!("tracing {} samples per pixel", i);
-                trace_into(&ctx, &mut buf, i, &world, &c);
+                trace_into(&ctx, &mut buf, i, &*world, &*c);
                 info!("filtering");
                 let rgb = buf.to_rgb();
                 let mut filtered_rgb = vec![0.0f32; rgb.len()];
@@ -163,7 +251,13 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
         if let Ok(img) = rx.try_recv() {
-            img.save("out.png")?;
+            if stereo.map(|s| s.layout) == Some(StereoLayout::Separate) {
+                let (eye_width, eye_height) = (eye_size.x as u32, eye_size.y as u32);
+                img.view(0, 0, eye_width, eye_height).to_image().save("out-left.png")?;
+                img.view(eye_width, 0, eye_width, eye_height).to_image().save("out-right.png")?;
+            } else {
+                img.save("out.png")?;
+            }
             texture.with_lock(None, |buf, _| {
                 buf.copy_from_slice(&img.into_raw());
             })?;
@@ -194,7 +288,7 @@ fn rgb_to_image(rgb: &[f32], width: usize, height: usize) -> image::RgbImage {
 
 fn trace_into(
     ctx: &Context, imgbuf: &mut framebuf::FrameBuf, samples_per_pixel: usize,
-    scene: &dyn Primitive, camera: &Camera,
+    scene: &dyn Primitive, camera: &dyn Camera,
 ) {
     let t_begin = time::Instant::now();
     let results: Vec<_> = imgbuf
@@ -204,10 +298,12 @@ fn trace_into(
             let rays = camera.get_rays(samples_per_pixel, *pixel);
             let res: Vec<_> = rays
                 .iter()
-                .map(|(r, offset)| {
-                    let col = color(r, scene);
+                .map(|c| {
+                    // Don't bother tracing rays the camera blocked.
+                    let col =
+                        iff!(c.weight == 0.0, Vector3f::zero(), color(&c.ray, scene) * c.weight);
                     let col = col.map(|x| x.sqrt()); // gamma correction
-                    (*pixel, *offset, col)
+                    (*pixel, c.pixel_offset, col)
                 })
                 .collect();
             res
@@ -226,8 +322,9 @@ fn color(r: &Ray3f, world: &dyn Primitive) -> Vector3f {
     let mut bounces = 0;
     let mut ray = *r;
     let mut throughput = Vector3f::from_value(1.0);
-    while let Some(ref hit) = world.intersect(ray) {
-        match hit.material.scatter(ray, hit.point, hit.normal) {
+    while let Some(mut hit) = world.intersect(ray) {
+        hit.compute_differentials(&ray);
+        match hit.material.scatter(ray, &hit) {
             None => return Vector3f::zero(), // absorbed
             Some((r, t)) => {
                 ray = r;
@@ -1,52 +1,67 @@
 use super::geom::*;
 use super::util::*;
+use crate::bsdf::*;
+use crate::prims::*;
+use crate::texture::*;
 use crate::types::*;
 
 pub trait Material: Sync + Send {
-    fn scatter(&self, in_: Ray3f, point: Point3f, normal: Vector3f) -> Option<(Ray3f, Vector3f)>;
+    /// Picks a direction for the light arriving along `in_` at `hit` to leave in, and returns the ray leaving that
+    /// way and the attenuation along it.
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)>;
 }
 
 #[derive(Copy, Clone)]
-pub struct Lambertian {
-    pub albedo: Vector3f,
+pub struct Lambertian<T = Vector3f> {
+    pub albedo: T,
 }
 
-impl Material for Lambertian {
-    fn scatter(&self, _in_: Ray3f, point: Point3f, normal: Vector3f) -> Option<(Ray3f, Vector3f)> {
+impl<T: Texture<Vector3f>> Material for Lambertian<T> {
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        let normal = hit.shading.normal;
         // Note we could just as well only scatter with some probability p and have attenuation be albedo/p.
-        let ray = Ray3f::new(point, normal + random_in_unit_sphere());
-        Some((ray, self.albedo))
+        let ray = hit.spawn_ray(normal + random_in_unit_sphere(), in_.time);
+        Some((ray, self.albedo.evaluate(hit)))
     }
 }
 
 #[derive(Copy, Clone, Debug)]
-pub struct Metal {
-    pub albedo: Vector3f,
-    pub fuzz: Float,
+pub struct Metal<A = Vector3f, F = Float> {
+    pub albedo: A,
+    pub fuzz: F,
 }
 
-fn reflect(v: Vector3f, norm: Vector3f) -> Vector3f {
+pub fn reflect(v: Vector3f, norm: Vector3f) -> Vector3f {
     (v - norm * v.dot(norm) * 2.0).normalize()
 }
 
-impl Material for Metal {
-    fn scatter(&self, in_: Ray3f, point: Point3f, normal: Vector3f) -> Option<(Ray3f, Vector3f)> {
+impl<A: Texture<Vector3f>, F: Texture<Float>> Material for Metal<A, F> {
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        let normal = hit.shading.normal;
         let reflected = reflect(in_.direction, normal);
-        let scattered = Ray3f::new(point, reflected + random_in_unit_sphere() * self.fuzz);
+        let fuzz = self.fuzz.evaluate(hit);
+        let scattered = iff!(
+            fuzz == 0.0,
+            hit.spawn_specular_ray(&in_, reflected, 1.0),
+            hit.spawn_ray(reflected + random_in_unit_sphere() * fuzz, in_.time)
+        );
         if scattered.direction.dot(normal) > 0.0 {
-            Some((scattered, self.albedo))
+            Some((scattered, self.albedo.evaluate(hit)))
         } else {
             None
         }
     }
 }
 
+/// Perfectly smooth glass, without any absorption inside.
 #[derive(Copy, Clone, PartialEq, Debug)]
-pub struct Dielectric {
-    pub ref_index: Float,
+pub struct Dielectric<T = Float> {
+    pub ref_index: T,
 }
 
-fn refract(v: Vector3f, norm: Vector3f, ni_over_nt: Float) -> Option<Vector3f> {
+/// Bends `v` through a surface with normal `norm`, on the side `v` comes from, by Snell's law; None for total internal
+/// reflection.
+pub fn refract(v: Vector3f, norm: Vector3f, ni_over_nt: Float) -> Option<Vector3f> {
     let uv = v.normalize();
     let dt = uv.dot(norm);
     let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
@@ -56,30 +71,23 @@ fn refract(v: Vector3f, norm: Vector3f, ni_over_nt: Float) -> Option<Vector3f> {
         None
     }
 }
-fn shlick(cosine: Float, ref_idx: Float) -> Float {
-    let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-    let r0 = r0 * r0;
-    r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
-}
-
-impl Material for Dielectric {
-    fn scatter(&self, in_: Ray3f, point: Point3f, normal: Vector3f) -> Option<(Ray3f, Vector3f)> {
+impl<T: Texture<Float>> Material for Dielectric<T> {
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        let normal = hit.shading.normal;
+        let ref_index = self.ref_index.evaluate(hit);
         let reflected = reflect(in_.direction, normal);
-        let (outward_normal, ni_over_nt, cosine) = if in_.direction.dot(normal) > 0.0 {
-            (
-                -normal,
-                self.ref_index,
-                self.ref_index * in_.direction.dot(normal) / in_.direction.magnitude(),
-            )
+        let (outward_normal, ni_over_nt) = if in_.direction.dot(normal) > 0.0 {
+            (-normal, ref_index)
         } else {
-            (normal, 1.0 / self.ref_index, -in_.direction.dot(normal) / in_.direction.magnitude())
+            (normal, 1.0 / ref_index)
         };
         let attenuation = Vector3f::from_value(1.0);
-        let reflect_p = shlick(cosine, self.ref_index);
+        let cosine = -in_.direction.dot(normal) / in_.direction.magnitude();
+        let reflect_p = fresnel_dielectric(cosine, ref_index);
         let out = match refract(in_.direction, outward_normal, ni_over_nt) {
             Some(refracted) if random() >= reflect_p => refracted,
             _ => reflected,
         };
-        Some((Ray3f::new(point, out), attenuation))
+        Some((hit.spawn_specular_ray(&in_, out, 1.0 / ni_over_nt), attenuation))
     }
 }
@@ -0,0 +1,140 @@
+use crate::geom::*;
+use crate::shape::*;
+use crate::types::*;
+use crate::util::*;
+
+/// A single triangle. The front face is the one the vertices wind counter-clockwise around.
+pub struct Triangle {
+    pub p0: Point3f,
+    pub p1: Point3f,
+    pub p2: Point3f,
+    /// The uv coordinates of the vertices, which the hit's are interpolated between; or None to take the barycentric
+    /// weights of `p1` and `p2` instead.
+    pub uvs: Option<[Point2f; 3]>,
+}
+
+impl Triangle {
+    /// The uv of the point with barycentric weights `u` for `p1` and `v` for `p2`, and the derivatives of the point
+    /// with respect to it.
+    fn parameterization(&self, u: Float, v: Float) -> (Point2f, Vector3f, Vector3f) {
+        let (e1, e2) = (self.p1 - self.p0, self.p2 - self.p0);
+        let [uv0, uv1, uv2] = match self.uvs {
+            Some(uvs) => uvs,
+            None => return (Point2f::new(u, v), e1, e2),
+        };
+        let (duv1, duv2) = (uv1 - uv0, uv2 - uv0);
+        let uv = uv0 + duv1 * u + duv2 * v;
+        // Solve e1 = dpdu * duv1.x + dpdv * duv1.y and the same for e2.
+        let det = duv1.x * duv2.y - duv1.y * duv2.x;
+        if det.abs() < 1e-9 {
+            // The uvs are degenerate, so any tangents around the normal do.
+            let (dpdu, dpdv) = coordinate_system(e1.cross(e2).normalize());
+            return (uv, dpdu, dpdv);
+        }
+        let dpdu = (e1 * duv2.y - e2 * duv1.y) / det;
+        let dpdv = (e2 * duv1.x - e1 * duv2.x) / det;
+        (uv, dpdu, dpdv)
+    }
+}
+
+impl Shape for Triangle {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        // Möller–Trumbore.
+        let e1 = self.p1 - self.p0;
+        let e2 = self.p2 - self.p0;
+        let pvec = r.direction.cross(e2);
+        let det = e1.dot(pvec);
+        if det == 0.0 {
+            // ray is parallel to the triangle
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin - self.p0;
+        let u = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qvec = tvec.cross(e1);
+        let v = r.direction.dot(qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(qvec) * inv_det;
+        // Due to floating point errors, skip hits right at the origin to avoid re-intersecting.
+        if t <= 0.000_001 {
+            return None;
+        }
+        // The barycentric combination of the vertices is closer to the plane than the point along the ray.
+        let point = Point3f::from_vec(
+            self.p0.to_vec() * (1.0 - u - v) + self.p1.to_vec() * u + self.p2.to_vec() * v,
+        );
+        let (uv, dpdu, dpdv) = self.parameterization(u, v);
+        Some(Hit { point, normal: e1.cross(e2).normalize(), uv, dpdu, dpdv })
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(AABB::new(self.p0, self.p0).union_p(&self.p1).union_p(&self.p2))
+    }
+
+    fn point_error(&self, _: &Ray3f, _: &Hit) -> Vector3f {
+        (abs(self.p0.to_vec()) + abs(self.p1.to_vec()) + abs(self.p2.to_vec())) * gamma(7)
+    }
+
+    fn clip_bounding_box(&self, clip: &AABB) -> Option<AABB> {
+        let mut poly = Polygon { points: [Point3f::origin(); Polygon::MAX_POINTS], len: 3 };
+        poly.points[..3].copy_from_slice(&[self.p0, self.p1, self.p2]);
+        for dim in 0..3 {
+            poly = poly.clip(|p| p[dim] - clip.min[dim]);
+            poly = poly.clip(|p| clip.max[dim] - p[dim]);
+        }
+        if poly.len == 0 {
+            return None;
+        }
+        let aabb = poly.points[..poly.len].iter().fold(AABB::empty(), |b, p| b.union_p(p));
+        // Interpolated points can land a hair outside the clip box.
+        Some(aabb.intersection(clip))
+    }
+}
+
+impl SampleShape for Triangle {
+    fn area(&self) -> Float {
+        (self.p1 - self.p0).cross(self.p2 - self.p0).magnitude() / 2.0
+    }
+    fn sample(&self, u: Point2f) -> (Point3f, Vector3f) {
+        // Folding the square onto the triangle would do too, but this keeps nearby samples together.
+        let s = u.x.sqrt();
+        let (b1, b2) = (s * (1.0 - u.y), s * u.y);
+        let point = self.p0 + (self.p1 - self.p0) * b1 + (self.p2 - self.p0) * b2;
+        (point, (self.p1 - self.p0).cross(self.p2 - self.p0).normalize())
+    }
+}
+
+/// A convex polygon small enough to clip a triangle against a box without allocating.
+#[derive(Copy, Clone)]
+struct Polygon {
+    points: [Point3f; Polygon::MAX_POINTS],
+    len: usize,
+}
+
+impl Polygon {
+    /// Each of the six clipping planes adds at most one point to a triangle.
+    const MAX_POINTS: usize = 9;
+
+    /// Clips to the half space where `dist` is non-negative (Sutherland–Hodgman).
+    fn clip<F: Fn(&Point3f) -> Float>(&self, dist: F) -> Polygon {
+        let mut out = Polygon { points: self.points, len: 0 };
+        for i in 0..self.len {
+            let (a, b) = (self.points[i], self.points[(i + 1) % self.len]);
+            let (da, db) = (dist(&a), dist(&b));
+            if da >= 0.0 {
+                out.points[out.len] = a;
+                out.len += 1;
+            }
+            if (da >= 0.0) != (db >= 0.0) {
+                out.points[out.len] = a + (b - a) * (da / (da - db));
+                out.len += 1;
+            }
+        }
+        out
+    }
+}
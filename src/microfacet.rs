@@ -0,0 +1,118 @@
+use crate::types::*;
+use crate::util::*;
+
+/// Below this roughness, surfaces are treated as perfectly smooth, which the distribution can't represent.
+const SMOOTH_ALPHA: Float = 1e-3;
+
+/// The GGX, or Trowbridge-Reitz, distribution of microfacet normals, in a BSDF's local frame, with Smith's
+/// height-correlated masking and shadowing and sampling of the normals visible from a direction (Heitz,
+/// "Understanding the Masking-Shadowing Function in Microfacet-Based BRDFs" and "Sampling the GGX Distribution of
+/// Visible Normals", as in PBRT v4).
+#[derive(Copy, Clone, Debug)]
+pub struct TrowbridgeReitz {
+    alpha_x: Float,
+    alpha_y: Float,
+}
+
+impl TrowbridgeReitz {
+    /// A distribution with roughness `alpha.x` along the local x axis and `alpha.y` along y. Equal roughnesses make it
+    /// isotropic, and 0 makes it a mirror.
+    pub fn new(alpha: Vector2f) -> TrowbridgeReitz {
+        let (alpha_x, alpha_y) = (max!(alpha.x, 0.0), max!(alpha.y, 0.0));
+        if max!(alpha_x, alpha_y) < SMOOTH_ALPHA {
+            return TrowbridgeReitz { alpha_x, alpha_y };
+        }
+        // Rough along one axis but smooth along the other would leave no microfacets tilted across it.
+        TrowbridgeReitz {
+            alpha_x: max!(alpha_x, SMOOTH_ALPHA),
+            alpha_y: max!(alpha_y, SMOOTH_ALPHA),
+        }
+    }
+
+    /// Whether the surface is so smooth that it should scatter specularly instead.
+    pub fn is_smooth(&self) -> bool {
+        max!(self.alpha_x, self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    /// The density of microfacets with normal `wm`, per unit of solid angle and of the macrosurface's area.
+    pub fn d(&self, wm: Vector3f) -> Float {
+        let cos2_theta = wm.z * wm.z;
+        if cos2_theta * cos2_theta < 1e-16 {
+            return 0.0;
+        }
+        let e = ((wm.x / self.alpha_x).powi(2) + (wm.y / self.alpha_y).powi(2)) / cos2_theta;
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos2_theta * cos2_theta * (1.0 + e).powi(2))
+    }
+
+    /// The area of microfacets hidden from `w`, over the area of those facing it.
+    fn lambda(&self, w: Vector3f) -> Float {
+        let cos2_theta = w.z * w.z;
+        if cos2_theta == 0.0 {
+            return 0.0;
+        }
+        let alpha2_tan2_theta =
+            ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / cos2_theta;
+        ((1.0 + alpha2_tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    /// The fraction of microfacets visible from `w`.
+    fn g1(&self, w: Vector3f) -> Float {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// The fraction of microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: Vector3f, wi: Vector3f) -> Float {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// The density of the normals visible from `w` being `wm`.
+    pub fn visible_d(&self, w: Vector3f, wm: Vector3f) -> Float {
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// The microfacet normal that reflects `wo` into `wi`, on the positive z side, and the BRDF of a surface of perfect
+    /// mirrors, which the Fresnel reflectance off the microfacet scales; or None if `wi` is on the other side.
+    pub fn reflection(&self, wo: Vector3f, wi: Vector3f) -> Option<(Vector3f, Float)> {
+        let wm = wo + wi;
+        if wo.z * wi.z <= 0.0 || wm.magnitude2() == 0.0 {
+            return None;
+        }
+        let wm = wm.normalize();
+        let wm = iff!(wm.z < 0.0, -wm, wm);
+        Some((wm, self.d(wm) * self.g(wo, wi) / (4.0 * wo.z.abs() * wi.z.abs())))
+    }
+
+    /// The density of `sample_reflection` picking `wi` for `wo`.
+    pub fn reflection_pdf(&self, wo: Vector3f, wi: Vector3f) -> Float {
+        match self.reflection(wo, wi) {
+            Some((wm, _)) => self.visible_d(wo, wm) / (4.0 * wo.dot(wm).abs()),
+            None => 0.0,
+        }
+    }
+
+    /// Reflects `wo` off a microfacet visible from it, with the samples `u` from [0,1)²; or None if that takes it
+    /// through the surface.
+    pub fn sample_reflection(&self, wo: Vector3f, u: Point2f) -> Option<Vector3f> {
+        let wm = self.sample_visible(wo, u);
+        let wi = wm * (2.0 * wo.dot(wm)) - wo;
+        iff!(wo.z * wi.z > 0.0, Some(wi), None)
+    }
+
+    /// Picks a microfacet normal visible from `w`, in proportion to `visible_d`, with the samples `u` from [0,1)². The
+    /// normal is always on the positive z side.
+    pub fn sample_visible(&self, w: Vector3f, u: Point2f) -> Vector3f {
+        // Stretch the distribution to a hemisphere of normals, which is easy to sample seen from any direction: a disk
+        // across the view, squashed at the bottom where the hemisphere's edge hides it, projected up onto it.
+        let wh = Vector3f::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalize();
+        let wh = iff!(wh.z < 0.0, -wh, wh);
+        let t1 = iff!(wh.z < 0.99999, Vector3f::unit_z().cross(wh).normalize(), Vector3f::unit_x());
+        let t2 = wh.cross(t1);
+        let p = disk_sample(u);
+        let h = (1.0 - p.x * p.x).sqrt();
+        let s = (1.0 + wh.z) / 2.0;
+        let py = (1.0 - s) * h + s * p.y;
+        let pz = max!(0.0, 1.0 - p.x * p.x - py * py).sqrt();
+        let nh = t1 * p.x + t2 * py + wh * pz;
+        Vector3f::new(self.alpha_x * nh.x, self.alpha_y * nh.y, max!(1e-6, nh.z)).normalize()
+    }
+}
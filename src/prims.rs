@@ -1,24 +1,178 @@
-use std::cmp::Ordering;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 use crate::geom::*;
 use crate::material::*;
 use crate::shape::*;
+use crate::transform::*;
 use crate::types::*;
+use crate::util::*;
 
 pub trait Primitive: Sync + Send {
     fn intersect(&self, _: Ray3f) -> Option<SurfaceInteraction<'_>>;
     fn bounding_box(&self) -> Option<AABB>;
+    /// Bounds the part of the primitive inside `clip`, or None if nothing is inside.
+    fn clip_bounding_box(&self, clip: &AABB) -> Option<AABB> {
+        self.bounding_box().map(|b| b.intersection(clip)).filter(|b| !b.is_empty())
+    }
 }
 
 pub struct SurfaceInteraction<'a> {
     pub prim: &'a dyn Primitive,
     pub point: Point3f,
+    /// Bounds the floating point error in each coordinate of `point`.
+    pub p_error: Vector3f,
     pub normal: Vector3f,
+    /// The hit's surface parameterization, from `Hit::uv`.
+    pub uv: Point2f,
+    pub dpdu: Vector3f,
+    pub dpdv: Vector3f,
+    /// How far the point and its uv move from one pixel to the next, across the film and up it. They are zero unless
+    /// `compute_differentials` found them from the ray's differentials.
+    pub dpdx: Vector3f,
+    pub dpdy: Vector3f,
+    pub duvdx: Vector2f,
+    pub duvdy: Vector2f,
+    /// The frame materials shade in, which normal and bump maps perturb away from the geometry's.
+    pub shading: Shading,
     pub material: &'a dyn Material,
     pub t: Float,
 }
 
+/// A normal, on the same side of the surface as the geometric one, and the derivatives of the point along the surface
+/// that go with it.
+#[derive(Copy, Clone, Debug)]
+pub struct Shading {
+    pub normal: Vector3f,
+    pub dpdu: Vector3f,
+    pub dpdv: Vector3f,
+}
+
+impl SurfaceInteraction<'static> {
+    /// A point on a surface facing along `normal` that no ray hit, to look textures up at, e.g. at a mesh's vertices to
+    /// displace them. It has no uv, primitive or material, and no differentials, so textures should be mapped by
+    /// position and aren't filtered.
+    pub fn at(point: Point3f, normal: Vector3f) -> SurfaceInteraction<'static> {
+        SurfaceInteraction {
+            prim: &Nowhere,
+            point,
+            p_error: Vector3f::zero(),
+            normal,
+            uv: Point2f::origin(),
+            dpdu: Vector3f::zero(),
+            dpdv: Vector3f::zero(),
+            dpdx: Vector3f::zero(),
+            dpdy: Vector3f::zero(),
+            duvdx: Vector2f::zero(),
+            duvdy: Vector2f::zero(),
+            shading: Shading { normal, dpdu: Vector3f::zero(), dpdv: Vector3f::zero() },
+            material: &Nowhere,
+            t: 0.0,
+        }
+    }
+}
+
+/// The primitive and material of a `SurfaceInteraction` that isn't on any.
+struct Nowhere;
+
+impl Primitive for Nowhere {
+    fn intersect(&self, _: Ray3f) -> Option<SurfaceInteraction<'_>> {
+        None
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        None
+    }
+}
+
+impl Material for Nowhere {
+    fn scatter(&self, _in: Ray3f, _hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        None
+    }
+}
+
+impl<'a> SurfaceInteraction<'a> {
+    /// A ray leaving the surface in `direction`. It starts from the point pushed off the surface along the normal, to
+    /// the side it leaves on, by enough to clear the error in the point, so that it can't hit the surface it's leaving
+    /// (PBRT 3.9.5).
+    pub fn spawn_ray(&self, direction: Vector3f, time: Float) -> Ray3f {
+        let n = self.normal;
+        let d = n.map(Float::abs).dot(self.p_error);
+        let offset = n * iff!(direction.dot(n) < 0.0, -d, d);
+        let mut origin = self.point + offset;
+        // Round away from the point, in case the addition rounded back towards it.
+        for dim in 0..3 {
+            if offset[dim] > 0.0 {
+                origin[dim] = next_float_up(origin[dim]);
+            } else if offset[dim] < 0.0 {
+                origin[dim] = next_float_down(origin[dim]);
+            }
+        }
+        Ray3f::new_at(origin, direction, time)
+    }
+
+    /// Works out the pixel's footprint on the surface, where the neighbouring rays of `r`, which found the hit, meet
+    /// the plane tangent to it.
+    pub fn compute_differentials(&mut self, r: &Ray3f) {
+        let d = match r.differentials {
+            Some(d) => d,
+            None => return,
+        };
+        let n = self.normal;
+        let plane = |origin: Point3f, direction: Vector3f| {
+            let t = n.dot(self.point - origin) / n.dot(direction);
+            iff!(t.is_finite(), Some(origin + direction * t - self.point), None)
+        };
+        let (dpdx, dpdy) =
+            match (plane(d.rx_origin, d.rx_direction), plane(d.ry_origin, d.ry_direction)) {
+                (Some(dpdx), Some(dpdy)) => (dpdx, dpdy),
+                _ => return,
+            };
+        // The change in uv that best explains each step, by least squares, since the steps needn't be in the plane of
+        // dpdu and dpdv where the normal isn't their cross product.
+        let (a00, a01, a11) =
+            (self.dpdu.magnitude2(), self.dpdu.dot(self.dpdv), self.dpdv.magnitude2());
+        let inv_det = 1.0 / (a00 * a11 - a01 * a01);
+        if !inv_det.is_finite() {
+            return;
+        }
+        let duv = |step: Vector3f| {
+            let (b0, b1) = (self.dpdu.dot(step), self.dpdv.dot(step));
+            let duv = Vector2f::new(a11 * b0 - a01 * b1, a00 * b1 - a01 * b0) * inv_det;
+            duv.map(|x| iff!(x.is_finite(), clamp!(x, -1e8, 1e8), 0.0))
+        };
+        let (duvdx, duvdy) = (duv(dpdx), duv(dpdy));
+        self.dpdx = dpdx;
+        self.dpdy = dpdy;
+        self.duvdx = duvdx;
+        self.duvdy = duvdy;
+    }
+
+    /// A ray leaving the surface in `direction`, as `spawn_ray` does, that carries on the differentials of `in_` by
+    /// mirroring or refracting its neighbouring rays the same way, for specular scattering. `eta` is the index of
+    /// refraction on the side the ray leaves into over that on the side `in_` arrives from, and is ignored for
+    /// reflection. The surface is taken to be flat around the hit, so that curved mirrors spread the rays out less
+    /// than they should. The neighbouring rays scatter off the shading normal, as the ray did.
+    pub fn spawn_specular_ray(&self, in_: &Ray3f, direction: Vector3f, eta: Float) -> Ray3f {
+        let ray = self.spawn_ray(direction, in_.time);
+        let d = match in_.differentials {
+            Some(d) if self.dpdx != Vector3f::zero() => d,
+            _ => return ray,
+        };
+        let n = self.shading.normal;
+        let reflected = (direction.dot(n) > 0.0) != (in_.direction.dot(n) > 0.0);
+        let facing = iff!(in_.direction.dot(n) > 0.0, -n, n);
+        let scatter =
+            |w: Vector3f| iff!(reflected, Some(reflect(w, facing)), refract(w, facing, 1.0 / eta));
+        match (scatter(d.rx_direction), scatter(d.ry_direction)) {
+            (Some(rx), Some(ry)) => ray.with_differentials(
+                Ray3f::new_at(self.point + self.dpdx, rx, in_.time),
+                Ray3f::new_at(self.point + self.dpdy, ry, in_.time),
+            ),
+            _ => ray,
+        }
+    }
+}
+
 pub struct ShapePrimitive<S: Shape, M: Material> {
     pub shape: S,
     pub material: M,
@@ -37,197 +191,111 @@ impl<S: Shape, M: Material> Primitive for ShapePrimitive<S, M> {
         if !self.aabb.map(|b| b.intersect(r)).unwrap_or(true) {
             return None;
         }
-        self.shape.intersect(r).map(|(point, normal)| SurfaceInteraction {
-            point,
-            normal,
+        self.shape.intersect(r).map(|hit| SurfaceInteraction {
+            point: hit.point,
+            p_error: self.shape.point_error(&r, &hit),
+            normal: hit.normal,
+            uv: hit.uv,
+            dpdu: hit.dpdu,
+            dpdv: hit.dpdv,
+            dpdx: Vector3f::zero(),
+            dpdy: Vector3f::zero(),
+            duvdx: Vector2f::zero(),
+            duvdy: Vector2f::zero(),
+            shading: Shading { normal: hit.normal, dpdu: hit.dpdu, dpdv: hit.dpdv },
             prim: self,
             material: &self.material,
-            t: (point - r.origin).dot(r.direction),
+            t: (hit.point - r.origin).dot(r.direction),
         })
     }
     fn bounding_box(&self) -> Option<AABB> {
         self.aabb
     }
+    fn clip_bounding_box(&self, clip: &AABB) -> Option<AABB> {
+        self.shape.clip_bounding_box(clip)
+    }
 }
 
-pub enum BVH {
-    Leaf { aabb: AABB, prims: Vec<*const dyn Primitive> },
-    Node { aabb: AABB, left: Box<BVH>, right: Box<BVH> },
-}
-
-unsafe impl Sync for BVH {}
-unsafe impl Send for BVH {}
-
-#[derive(Copy, Clone, Debug)]
-struct PrimitiveInfo {
-    prim: *const dyn Primitive,
-    aabb: AABB,
-    center: Point3f,
-}
-
-#[derive(Clone, Debug)]
-struct SplitResult {
-    cost: Float,
-    left: Vec<PrimitiveInfo>,
-    left_aabb: AABB,
-    right: Vec<PrimitiveInfo>,
-    right_aabb: AABB,
-    bounds: AABB,
-}
-
-#[derive(Copy, Clone, Debug)]
-struct BucketInfo {
-    count: usize,
-    aabb: AABB,
+/// A primitive placed in the world with an object-to-world transform. The primitive is shared, so an `Aggregate` of
+/// a mesh can be instanced many times without copying it, with the instances in a top-level `Aggregate` of their own.
+pub struct TransformedPrimitive {
+    prim: Arc<dyn Primitive>,
+    object_to_world: Transform,
+    world_to_object: Transform,
+    aabb: Option<AABB>,
 }
 
-impl BVH {
-    const OBJECT_SPLIT_BUCKETS: usize = 16;
-    const MAX_PRIMITIVES_PER_NODE: usize = 4;
-
-    unsafe fn new_leaf(aabb: AABB, prims: Vec<PrimitiveInfo>) -> Box<Self> {
-        Box::new(BVH::Leaf { aabb, prims: prims.iter().map(|pi| pi.prim).collect() })
-    }
-
-    fn fold_aabb(aabs: &[AABB]) -> AABB {
-        aabs.iter().fold(AABB::empty(), |r, b| r.union(b))
-    }
-
-    unsafe fn new_sorted(aabb: AABB, prims: Vec<PrimitiveInfo>) -> Box<Self> {
-        if prims.len() == 0 {
-            unimplemented!("empty BVH::new input")
-        } else if prims.len() <= Self::MAX_PRIMITIVES_PER_NODE {
-            return Self::new_leaf(aabb, prims);
-        } else {
-            let mut splits: Vec<SplitResult> =
-                (0..3).map(|dim| Self::object_split(prims.clone(), dim)).flatten().collect();
-            if splits.len() == 0 {
-                return Self::new_leaf(aabb, prims);
-            }
-            let mut best = 0;
-            for i in 1..splits.len() {
-                if splits[i].cost < splits[best].cost {
-                    best = i
-                }
-            }
-
-            let split = splits.swap_remove(best);
-            if split.cost >= prims.len() as Float {
-                // BVH cost same as just checking everything, so don't bother with a node.
-                return Self::new_leaf(aabb, prims);
-            }
-            let left = BVH::new_sorted(split.left_aabb, split.left);
-            let right = BVH::new_sorted(split.right_aabb, split.right);
-            Box::new(BVH::Node { aabb, left, right })
+impl TransformedPrimitive {
+    pub fn new(prim: Arc<dyn Primitive>, object_to_world: Transform) -> TransformedPrimitive {
+        let aabb = prim.bounding_box().map(|b| object_to_world.aabb(&b));
+        TransformedPrimitive {
+            prim,
+            object_to_world,
+            world_to_object: object_to_world.inverse(),
+            aabb,
         }
     }
+}
 
-    unsafe fn object_split(mut prims: Vec<PrimitiveInfo>, dim: usize) -> Option<SplitResult> {
-        let bounds = prims.iter().fold(AABB::empty(), |res, pi| res.union(&pi.aabb));
-
-        let center_bounds = prims.iter().fold(AABB::empty(), |res, pi| res.union_p(&pi.center));
-        if center_bounds.min[dim] == center_bounds.max[dim] {
+impl Primitive for TransformedPrimitive {
+    fn intersect(&self, r: Ray3f) -> Option<SurfaceInteraction<'_>> {
+        if !self.aabb.map(|b| b.intersect(r)).unwrap_or(true) {
             return None;
         }
-        prims.sort_unstable_by(|left, right| {
-            left.center[dim].partial_cmp(&right.center[dim]).unwrap_or(Ordering::Less)
-        });
-
-        let c_buckets = Self::OBJECT_SPLIT_BUCKETS;
-        let pi_bucket = |pi: &PrimitiveInfo| {
-            let i = (center_bounds.offset_p(&pi.center)[dim] * (c_buckets as Float)) as usize;
-            clamp!(i, 0, c_buckets - 1)
-        };
-
-        let mut buckets = vec![BucketInfo { count: 0, aabb: AABB::empty() }; c_buckets];
-        for pi in prims.iter() {
-            let i = pi_bucket(pi);
-            buckets[i].count += 1;
-            buckets[i].aabb = buckets[i].aabb.union(&pi.aabb);
-        }
-
-        // cost for splitting at this bucket
-        let mut cost = vec![0.0 as Float; c_buckets - 1];
-        for i in 0..cost.len() {
-            let aabb_left = buckets[..i + 1].iter().fold(AABB::empty(), |r, b| r.union(&b.aabb));
-            let aabb_right = buckets[i + 1..].iter().fold(AABB::empty(), |r, b| r.union(&b.aabb));
-
-            let count_left = buckets[..i + 1].iter().fold(0, |r, b| r + b.count);
-            let count_right = buckets[i + 1..].iter().fold(0, |r, b| r + b.count);
-
-            cost[i] = 1.0
-                + (count_left as Float * aabb_left.surface_area()
-                    + count_right as Float * aabb_right.surface_area())
-                    / bounds.surface_area()
-        }
-
-        let mut min_cost = cost[0];
-        let mut min_cost_bucket = 0;
-        for i in 1..c_buckets - 1 {
-            if cost[i] < min_cost {
-                min_cost = cost[i];
-                min_cost_bucket = i;
-            }
-        }
-
-        let (pi_left, pi_right): (Vec<PrimitiveInfo>, Vec<PrimitiveInfo>) =
-            prims.iter().partition(|pi| pi_bucket(pi) <= min_cost_bucket);
-        let left_aabbs: Vec<AABB> = pi_left.iter().map(|pi| (pi.aabb)).collect();
-        let left_aabb = Self::fold_aabb(&left_aabbs);
-        let right_aabbs: Vec<AABB> = pi_right.iter().map(|pi| pi.aabb).collect();
-        let right_aabb = Self::fold_aabb(&right_aabbs);
-
-        Some(SplitResult {
-            cost: min_cost,
-            bounds,
-            left: pi_left,
-            left_aabb,
-            right: pi_right,
-            right_aabb,
-        })
+        intersect_transformed(&*self.prim, &self.object_to_world, &self.world_to_object, r)
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        self.aabb
     }
+}
+
+/// A primitive moving along keyframed transforms, which is intersected in the pose at the ray's time. Its bounds
+/// cover the whole motion, so it can sit in a static `Aggregate`.
+pub struct AnimatedPrimitive {
+    prim: Arc<dyn Primitive>,
+    object_to_world: AnimatedTransform,
+    aabb: Option<AABB>,
+}
 
-    pub unsafe fn new(prims: &[Box<dyn Primitive>]) -> Box<Self> {
-        let prims: Vec<PrimitiveInfo> = prims
-            .iter()
-            .map(|x| {
-                let prim = &**x as *const dyn Primitive;
-                let aabb = (*prim)
-                    .bounding_box()
-                    .unwrap_or_else(|| unimplemented!("No bounding box in BVH::new"));
-                let center = aabb.center();
-                PrimitiveInfo { prim, aabb, center }
-            })
-            .collect();
-        let aabb = Self::fold_aabb(&prims.iter().map(|pi| pi.aabb).collect::<Vec<AABB>>());
-        Self::new_sorted(aabb, prims)
+impl AnimatedPrimitive {
+    pub fn new(prim: Arc<dyn Primitive>, object_to_world: AnimatedTransform) -> AnimatedPrimitive {
+        let aabb = prim.bounding_box().map(|b| object_to_world.motion_bounds(&b));
+        AnimatedPrimitive { prim, object_to_world, aabb }
     }
 }
 
-impl Primitive for BVH {
+impl Primitive for AnimatedPrimitive {
     fn intersect(&self, r: Ray3f) -> Option<SurfaceInteraction<'_>> {
-        match self {
-            BVH::Leaf { aabb, prims } if aabb.intersect(r) => {
-                prims.iter().fold(None, |hit, prim| match (hit, unsafe { &**prim }.intersect(r)) {
-                    (None, hit) | (hit, None) => hit,
-                    (Some(best), Some(hit)) => Some(iff!(best.t < hit.t, best, hit)),
-                })
-            }
-            BVH::Node { aabb, left, right } if aabb.intersect(r) => {
-                match (left.intersect(r), right.intersect(r)) {
-                    (None, None) => None,
-                    (hit, None) | (None, hit) => hit,
-                    (Some(hl), Some(hr)) => Some(iff!(hl.t < hr.t, hl, hr)),
-                }
-            }
-            _ => None,
+        if !self.aabb.map(|b| b.intersect(r)).unwrap_or(true) {
+            return None;
         }
+        let object_to_world = self.object_to_world.at(r.time);
+        intersect_transformed(&*self.prim, &object_to_world, &object_to_world.inverse(), r)
     }
-
     fn bounding_box(&self) -> Option<AABB> {
-        Some(*match self {
-            BVH::Leaf { aabb, prims: _ } => aabb,
-            BVH::Node { aabb, left: _, right: _ } => aabb,
-        })
+        self.aabb
     }
 }
+
+fn intersect_transformed<'a>(
+    prim: &'a dyn Primitive, object_to_world: &Transform, world_to_object: &Transform, r: Ray3f,
+) -> Option<SurfaceInteraction<'a>> {
+    prim.intersect(world_to_object.ray(r)).map(|hit| {
+        let point = object_to_world.point(hit.point);
+        SurfaceInteraction {
+            point,
+            p_error: object_to_world.point_error(hit.point, hit.p_error),
+            normal: object_to_world.normal(hit.normal).normalize(),
+            dpdu: object_to_world.vector(hit.dpdu),
+            dpdv: object_to_world.vector(hit.dpdv),
+            shading: Shading {
+                normal: object_to_world.normal(hit.shading.normal).normalize(),
+                dpdu: object_to_world.vector(hit.shading.dpdu),
+                dpdv: object_to_world.vector(hit.shading.dpdv),
+            },
+            // The object space ray's direction was renormalized, so its distances don't carry over.
+            t: (point - r.origin).dot(r.direction),
+            ..hit
+        }
+    })
+}
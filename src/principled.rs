@@ -0,0 +1,296 @@
+use std::sync::Arc;
+
+use crate::bsdf::*;
+use crate::dielectric::*;
+use crate::geom::*;
+use crate::material::*;
+use crate::microfacet::*;
+use crate::prims::*;
+use crate::texture::*;
+use crate::types::*;
+use crate::util::*;
+
+/// The least GGX roughness of the specular lobes, which keeps them glossy: perfectly specular lobes can't be summed
+/// with the others.
+const MIN_ALPHA: Float = 2e-3;
+
+/// Disney's principled BSDF (Burley, "Physically-Based Shading at Disney" and "Extending the Disney BRDF to a BSDF
+/// with Integrated Subsurface Scattering", as in PBRT v3), which covers most materials with a few parameters from 0 to
+/// 1: a diffuse lobe with sheen and flattened by subsurface scattering, GGX specular reflection, a clearcoat and rough
+/// transmission.
+///
+/// Transmissive surfaces expect normals to point out of the solid.
+#[derive(Copy, Clone, Debug)]
+pub struct Principled<C = Vector3f, F = Float> {
+    /// The color of diffuse reflection, of metals' specular reflection and of transmission.
+    pub base_color: C,
+    /// Blends from a dielectric to a metal.
+    pub metallic: F,
+    /// Perceptual roughness, whose square is the GGX roughness.
+    pub roughness: F,
+    /// How much dielectrics reflect specularly, where 0.5 is 4% head on, as for an index of refraction of 1.5.
+    pub specular: F,
+    /// Tints dielectrics' specular reflection towards the base color.
+    pub specular_tint: F,
+    /// Makes the surface rougher along its `dpdu` than across it.
+    pub anisotropic: F,
+    /// Adds soft reflection at grazing angles, as off cloth.
+    pub sheen: F,
+    /// Tints the sheen towards the base color.
+    pub sheen_tint: F,
+    /// Adds a second specular lobe, like a coat of lacquer, which is always white.
+    pub clearcoat: F,
+    /// From a satin clearcoat at 0 to a glossy one at 1.
+    pub clearcoat_gloss: F,
+    /// Blends from an opaque dielectric to glass, whose index of refraction follows from `specular`.
+    pub transmission: F,
+    /// Flattens diffuse reflection the way light that scatters under the surface does.
+    pub subsurface: F,
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_color: Vector3f::new(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            anisotropic: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            subsurface: 0.0,
+        }
+    }
+}
+
+impl Principled {
+    /// The same material with each parameter a shared texture, so that any of them can be swapped for another kind of
+    /// texture, as in `Principled { roughness: Arc::new(noise), ..Principled::default().shared() }`.
+    pub fn shared(self) -> Principled<Arc<dyn Texture<Vector3f>>, Arc<dyn Texture<Float>>> {
+        Principled {
+            base_color: Arc::new(self.base_color),
+            metallic: Arc::new(self.metallic),
+            roughness: Arc::new(self.roughness),
+            specular: Arc::new(self.specular),
+            specular_tint: Arc::new(self.specular_tint),
+            anisotropic: Arc::new(self.anisotropic),
+            sheen: Arc::new(self.sheen),
+            sheen_tint: Arc::new(self.sheen_tint),
+            clearcoat: Arc::new(self.clearcoat),
+            clearcoat_gloss: Arc::new(self.clearcoat_gloss),
+            transmission: Arc::new(self.transmission),
+            subsurface: Arc::new(self.subsurface),
+        }
+    }
+
+    fn alpha(&self) -> Vector2f {
+        let aspect = (1.0 - 0.9 * self.anisotropic).sqrt();
+        let alpha = self.roughness * self.roughness;
+        Vector2f::new(max!(alpha / aspect, MIN_ALPHA), max!(alpha * aspect, MIN_ALPHA))
+    }
+
+    /// The base color's hue, at a luminance of 1.
+    fn tint(&self) -> Vector3f {
+        let luminance = luminance(&self.base_color);
+        iff!(luminance > 0.0, self.base_color / luminance, Vector3f::from_value(1.0))
+    }
+
+    /// The glass that `transmission` blends to, whose specular reflection is as bright as `specular` makes
+    /// dielectrics'. It refracts at least a little, since a boundary that doesn't is specular.
+    fn glass(&self) -> RoughDielectric {
+        let eta = 2.0 / (1.0 - (0.08 * self.specular).sqrt()) - 1.0;
+        RoughDielectric::new(max!(eta, 1.01), self.alpha())
+    }
+
+    /// How much of the light goes to the diffuse, specular, clearcoat and glass lobes, which is also how often each
+    /// is sampled.
+    fn lobe_weights(&self) -> [Float; 4] {
+        let (metallic, transmission) = (self.metallic, self.transmission);
+        let diffuse = (1.0 - metallic) * (1.0 - transmission);
+        let glass = (1.0 - metallic) * transmission;
+        [diffuse, 1.0 - glass, 0.25 * self.clearcoat, glass]
+    }
+
+    fn lobe_probabilities(&self) -> [Float; 4] {
+        let mut weights = self.lobe_weights();
+        let total: Float = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= total);
+        weights
+    }
+
+    /// The diffuse lobe, with retro-reflection at grazing angles off rough surfaces, Burley's approximation of
+    /// Hanrahan-Krueger subsurface scattering, and the sheen.
+    fn diffuse(&self, wo: Vector3f, wi: Vector3f) -> Vector3f {
+        let wh = wo + wi;
+        if wo.z * wi.z <= 0.0 || wh.magnitude2() == 0.0 {
+            return Vector3f::zero();
+        }
+        let (cos_theta_o, cos_theta_i) = (wo.z.abs(), wi.z.abs());
+        let cos_theta_d = wi.dot(wh.normalize());
+        let (fo, fi) = (schlick_weight(cos_theta_o), schlick_weight(cos_theta_i));
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_theta_d * cos_theta_d;
+        let fd = (1.0 + (fd90 - 1.0) * fo) * (1.0 + (fd90 - 1.0) * fi);
+        let fss90 = self.roughness * cos_theta_d * cos_theta_d;
+        let fss = (1.0 + (fss90 - 1.0) * fo) * (1.0 + (fss90 - 1.0) * fi);
+        let ss = 1.25 * (fss * (1.0 / (cos_theta_o + cos_theta_i) - 0.5) + 0.5);
+        let diffuse = fd + (ss - fd) * self.subsurface;
+        let sheen_color = Vector3f::from_value(1.0).lerp(self.tint(), self.sheen_tint);
+        self.base_color * (diffuse / PI) + sheen_color * (self.sheen * schlick_weight(cos_theta_d))
+    }
+
+    /// Specular reflection off GGX microfacets, with Schlick's approximation of the Fresnel reflectance, which blends
+    /// from dielectrics' to the base color for metals.
+    fn specular(&self, wo: Vector3f, wi: Vector3f) -> Vector3f {
+        let distribution = TrowbridgeReitz::new(self.alpha());
+        let (wm, mirrors) = match distribution.reflection(wo, wi) {
+            Some(reflection) => reflection,
+            None => return Vector3f::zero(),
+        };
+        let dielectric = Vector3f::from_value(1.0).lerp(self.tint(), self.specular_tint)
+            * (0.08 * self.specular);
+        let f0 = dielectric.lerp(self.base_color, self.metallic);
+        f0.lerp(Vector3f::from_value(1.0), schlick_weight(wo.dot(wm).abs())) * mirrors
+    }
+
+    fn clearcoat_alpha(&self) -> Float {
+        0.1 + (0.001 - 0.1) * self.clearcoat_gloss
+    }
+
+    /// The clearcoat, whose normals follow the longer tailed GTR1 distribution, and whose masking and shadowing is
+    /// fixed at a roughness of 0.25.
+    fn clearcoat(&self, wo: Vector3f, wi: Vector3f) -> Float {
+        let wh = wo + wi;
+        if wo.z * wi.z <= 0.0 || wh.magnitude2() == 0.0 {
+            return 0.0;
+        }
+        let wh = wh.normalize();
+        let d = gtr1(wh.z.abs(), self.clearcoat_alpha());
+        let f = 0.04 + 0.96 * schlick_weight(wo.dot(wh).abs());
+        let g = smith_g1(wo.z.abs(), 0.25) * smith_g1(wi.z.abs(), 0.25);
+        d * f * g / (4.0 * wo.z.abs() * wi.z.abs())
+    }
+
+    fn clearcoat_pdf(&self, wo: Vector3f, wi: Vector3f) -> Float {
+        let wh = wo + wi;
+        if wo.z * wi.z <= 0.0 || wh.magnitude2() == 0.0 {
+            return 0.0;
+        }
+        let wh = wh.normalize();
+        gtr1(wh.z.abs(), self.clearcoat_alpha()) * wh.z.abs() / (4.0 * wo.dot(wh).abs())
+    }
+
+    fn sample_clearcoat(&self, wo: Vector3f, u: Point2f) -> Option<Vector3f> {
+        let alpha2 = self.clearcoat_alpha().powi(2);
+        let cos_theta = safe_sqrt((1.0 - alpha2.powf(1.0 - u.x)) / (1.0 - alpha2));
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let phi = 2.0 * PI * u.y;
+        let wh = Vector3f::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let wi = wh * (2.0 * wo.dot(wh)) - wo;
+        iff!(wo.z * wi.z > 0.0, Some(wi), None)
+    }
+}
+
+/// Schlick's approximation of how much more than head on a surface reflects at the angle `cos_theta`.
+fn schlick_weight(cos_theta: Float) -> Float {
+    (1.0 - clamp!(cos_theta, 0.0, 1.0)).powi(5)
+}
+
+/// The "generalized Trowbridge-Reitz" distribution of normals with exponent 1, which has a longer tail than GGX.
+fn gtr1(cos_theta_h: Float, alpha: Float) -> Float {
+    if alpha >= 1.0 {
+        return 1.0 / PI;
+    }
+    let alpha2 = alpha * alpha;
+    (alpha2 - 1.0) / (PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta_h * cos_theta_h))
+}
+
+/// Smith's GGX masking, for a direction at `cos_theta` to the normal.
+fn smith_g1(cos_theta: Float, alpha: Float) -> Float {
+    let alpha2 = alpha * alpha;
+    let cos2_theta = cos_theta * cos_theta;
+    2.0 * cos_theta / (cos_theta + (alpha2 + cos2_theta - alpha2 * cos2_theta).sqrt())
+}
+
+impl Bsdf for Principled {
+    fn eval(&self, wo: Vector3f, wi: Vector3f) -> Vector3f {
+        let [diffuse, specular, clearcoat, glass] = self.lobe_weights();
+        // Light that goes through is tinted on the way in and again on the way out.
+        let transmittance =
+            iff!(wo.z * wi.z < 0.0, self.base_color.map(Float::sqrt), Vector3f::from_value(1.0));
+        self.diffuse(wo, wi) * diffuse
+            + self.specular(wo, wi) * specular
+            + Vector3f::from_value(self.clearcoat(wo, wi) * clearcoat)
+            + self.glass().eval(wo, wi).mul_element_wise(transmittance) * glass
+    }
+
+    fn pdf(&self, wo: Vector3f, wi: Vector3f) -> Float {
+        let [diffuse, specular, clearcoat, glass] = self.lobe_probabilities();
+        let cosine = iff!(wo.z * wi.z > 0.0, wi.z.abs() / PI, 0.0);
+        let distribution = TrowbridgeReitz::new(self.alpha());
+        diffuse * cosine
+            + specular * distribution.reflection_pdf(wo, wi)
+            + clearcoat * self.clearcoat_pdf(wo, wi)
+            + glass * self.glass().pdf(wo, wi)
+    }
+
+    fn sample(&self, wo: Vector3f, u: [Float; 3]) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        // Pick a lobe by how much light it gets, and sample it; the sum of the lobes then weights the direction.
+        let probabilities = self.lobe_probabilities();
+        let mut lobe = 0;
+        let mut u0 = u[0];
+        while lobe < 3 && u0 >= probabilities[lobe] {
+            u0 -= probabilities[lobe];
+            lobe += 1;
+        }
+        if probabilities[lobe] <= 0.0 {
+            return None;
+        }
+        let u0 = min!(u0 / probabilities[lobe], 1.0 - FLOAT_EPSILON);
+        let uv = Point2f::new(u[1], u[2]);
+        let wi = match lobe {
+            0 => {
+                let d = disk_sample(uv);
+                let z = safe_sqrt(1.0 - d.x * d.x - d.y * d.y);
+                Vector3f::new(d.x, d.y, iff!(wo.z < 0.0, -z, z))
+            }
+            1 => TrowbridgeReitz::new(self.alpha()).sample_reflection(wo, uv)?,
+            2 => self.sample_clearcoat(wo, uv)?,
+            _ => self.glass().sample(wo, [u0, u[1], u[2]])?.wi,
+        };
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let weight = self.eval(wo, wi) * (wi.z.abs() / pdf);
+        let glass_eta = self.glass().eta;
+        let eta = iff!(wo.z * wi.z < 0.0, iff!(wo.z > 0.0, glass_eta, 1.0 / glass_eta), 1.0);
+        Some(BsdfSample { wi, weight, specular: false, eta })
+    }
+}
+
+impl<C: Texture<Vector3f>, F: Texture<Float>> Material for Principled<C, F> {
+    fn scatter(&self, in_: Ray3f, hit: &SurfaceInteraction) -> Option<(Ray3f, Vector3f)> {
+        let bsdf = Principled {
+            base_color: self.base_color.evaluate(hit),
+            metallic: self.metallic.evaluate(hit),
+            roughness: self.roughness.evaluate(hit),
+            specular: self.specular.evaluate(hit),
+            specular_tint: self.specular_tint.evaluate(hit),
+            anisotropic: self.anisotropic.evaluate(hit),
+            sheen: self.sheen.evaluate(hit),
+            sheen_tint: self.sheen_tint.evaluate(hit),
+            clearcoat: self.clearcoat.evaluate(hit),
+            clearcoat_gloss: self.clearcoat_gloss.evaluate(hit),
+            transmission: self.transmission.evaluate(hit),
+            subsurface: self.subsurface.evaluate(hit),
+        };
+        scatter_bsdf(&bsdf, in_, hit)
+    }
+}
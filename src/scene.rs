@@ -1,11 +1,29 @@
+use std::error::Error;
+use std::sync::Arc;
+
 use crate::aggregate::*;
+use crate::bump::*;
+use crate::bvh::*;
+use crate::camera::*;
+use crate::conductor::*;
+use crate::csg::*;
+use crate::curve::*;
+use crate::dielectric::*;
+use crate::hair::*;
+use crate::layered::*;
 use crate::material::*;
+use crate::mesh::*;
 use crate::prims::*;
+use crate::principled::*;
+use crate::sdf::*;
 use crate::shape::*;
+use crate::subdiv::*;
+use crate::texture::*;
+use crate::transform::*;
 use crate::types::*;
 use crate::util;
 
-pub fn new_cover_scene<'a>() -> Aggregate {
+pub fn cover_scene() -> Vec<Box<dyn Primitive>> {
     let mut random = util::new_random(0);
 
     let mut prims: Vec<Box<dyn Primitive>> = vec![
@@ -69,5 +87,741 @@ pub fn new_cover_scene<'a>() -> Aggregate {
             prims.push(prim)
         }
     }
-    Aggregate::new(prims)
+    prims
+}
+
+/// A cloud of long, thin, randomly oriented triangles, which object splits bound poorly.
+pub fn slivers_scene() -> Vec<Box<dyn Primitive>> {
+    let mut random = util::new_random(0);
+    let mut random_vector =
+        || Vector3f::new(random(), random(), random()) * 2.0 - Vector3f::from_value(1.0);
+
+    (0..100_000)
+        .map(|_| {
+            let center = Point3f::from_vec(random_vector() * 10.0);
+            let long = random_vector().normalize();
+            let wide = long.cross(random_vector()).normalize() * 0.01;
+            Box::new(ShapePrimitive::new(
+                Triangle { p0: center - long, p1: center + long, p2: center + wide, uvs: None },
+                Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+            )) as Box<dyn Primitive>
+        })
+        .collect()
+}
+
+/// Thousands of randomly placed, rotated and stretched copies of a helix of spheres, all sharing one BVH.
+pub fn instances_scene() -> Result<Vec<Box<dyn Primitive>>, BuildError> {
+    let mut random = util::new_random(0);
+
+    let helix: Vec<Box<dyn Primitive>> = (0..64)
+        .map(|i| {
+            let angle = i as Float * 0.5;
+            let center =
+                Point3f::new(angle.cos() * 0.5, 0.1 + i as Float * 0.03, angle.sin() * 0.5);
+            iff!(
+                i % 2 == 0,
+                Box::new(ShapePrimitive::new(
+                    Sphere { center, radius: 0.1 },
+                    Metal { albedo: Vector3f::new(0.8, 0.6, 0.2), fuzz: 0.1 },
+                )) as Box<dyn Primitive>,
+                Box::new(ShapePrimitive::new(
+                    Sphere { center, radius: 0.1 },
+                    Lambertian { albedo: Vector3f::new(0.1, 0.2, 0.5) },
+                )) as Box<dyn Primitive>
+            )
+        })
+        .collect();
+    let helix: Arc<dyn Primitive> = Arc::new(Aggregate::new(helix)?);
+
+    let mut prims: Vec<Box<dyn Primitive>> = vec![Box::new(ShapePrimitive::new(
+        Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+        Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+    ))];
+    for _ in 0..4096 {
+        let object_to_world = Transform::scale(Vector3f::new(1.0, 0.5 + random(), 1.0))
+            .then(&Transform::rotate(Vector3f::unit_y(), random() * 360.0))
+            .then(&Transform::translate(Vector3f::new(
+                random() * 100.0 - 50.0,
+                0.0,
+                random() * 100.0 - 50.0,
+            )));
+        prims.push(Box::new(TransformedPrimitive::new(helix.clone(), object_to_world)));
+    }
+    Ok(prims)
+}
+
+/// Spheres bouncing over a floor around a spinning helix, for a camera with its shutter open from time 0 to 1.
+pub fn motion_scene() -> Result<Vec<Box<dyn Primitive>>, Box<dyn Error>> {
+    let mut random = util::new_random(0);
+
+    let mut prims: Vec<Box<dyn Primitive>> = vec![Box::new(ShapePrimitive::new(
+        Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+        Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+    ))];
+    for a in -11i16..11i16 {
+        for b in -11i16..11i16 {
+            let sphere: Arc<dyn Primitive> = Arc::new(ShapePrimitive::new(
+                Sphere { center: Point3f::new(0.0, 0.2, 0.0), radius: 0.2 },
+                Lambertian {
+                    albedo: Vector3f::new(
+                        random() * random(),
+                        random() * random(),
+                        random() * random(),
+                    ),
+                },
+            ));
+            let center = Vector3f::new(
+                Float::from(a) + 0.9 * random(),
+                0.0,
+                Float::from(b) + 0.9 * random(),
+            );
+            let motion = AnimatedTransform::new(vec![
+                Keyframe::new(0.0).translate(center),
+                Keyframe::new(1.0).translate(center + Vector3f::new(0.0, 0.5 * random(), 0.0)),
+            ])
+            .ok_or("no keyframes")?;
+            prims.push(Box::new(AnimatedPrimitive::new(sphere, motion)));
+        }
+    }
+
+    let helix: Vec<Box<dyn Primitive>> = (0..64)
+        .map(|i| {
+            let angle = i as Float * 0.5;
+            let center = Point3f::new(angle.cos(), 0.2 + i as Float * 0.05, angle.sin());
+            Box::new(ShapePrimitive::new(
+                Sphere { center, radius: 0.2 },
+                Metal { albedo: Vector3f::new(0.8, 0.6, 0.2), fuzz: 0.1 },
+            )) as Box<dyn Primitive>
+        })
+        .collect();
+    let spin = AnimatedTransform::new(vec![
+        Keyframe::new(0.0),
+        Keyframe::new(1.0).rotate(Vector3f::unit_y(), 90.0),
+    ])
+    .ok_or("no keyframes")?;
+    prims.push(Box::new(AnimatedPrimitive::new(Arc::new(Aggregate::new(helix)?), spin)));
+    Ok(prims)
+}
+
+/// One of each analytic shape in a row, with as many small spheres scattered over each surface by area sampling,
+/// sized by the share of the surface each one stands for.
+pub fn shapes_scene() -> Vec<Box<dyn Primitive>> {
+    let mut random = util::new_random(0);
+    let gray = Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) };
+    let gold = Metal { albedo: Vector3f::new(0.8, 0.6, 0.2), fuzz: 0.2 };
+
+    let quad = Quad {
+        corner: Point3f::new(-7.0, 0.0, 0.0),
+        u: Vector3f::new(2.0, 0.0, 0.0),
+        v: Vector3f::new(0.0, 2.0, -0.5),
+    };
+    let disk = Disk {
+        center: Point3f::new(-3.5, 1.0, 0.0),
+        normal: Vector3f::new(0.0, 0.3, 1.0),
+        radius: 1.0,
+    };
+    let cylinder = Cylinder {
+        base: Point3f::new(-1.0, 0.0, 0.0),
+        axis: Vector3f::new(0.0, 2.0, 0.0),
+        radius: 0.7,
+    };
+    let cone =
+        Cone { base: Point3f::new(1.5, 0.0, 0.0), axis: Vector3f::new(0.0, 2.0, 0.0), radius: 0.9 };
+    let cuboid = Cuboid { min: Point3f::new(3.0, 0.0, -0.8), max: Point3f::new(4.6, 1.6, 0.8) };
+    let torus = Torus {
+        center: Point3f::new(6.5, 1.2, 0.0),
+        axis: Vector3f::new(0.0, 0.5, 1.0),
+        major_radius: 0.9,
+        minor_radius: 0.3,
+    };
+
+    let mut prims: Vec<Box<dyn Primitive>> = vec![Box::new(ShapePrimitive::new(
+        Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+        gray,
+    ))];
+    let shapes: [&dyn SampleShape; 6] = [&quad, &disk, &cylinder, &cone, &cuboid, &torus];
+    for shape in shapes.iter() {
+        let count = 60;
+        for _ in 0..count {
+            let (point, normal) = shape.sample(Point2f::new(random(), random()));
+            // Each sphere takes up about the same part of its share of the surface.
+            let share = 1.0 / (shape.pdf(point) * count as Float);
+            let radius = 0.16 * share.sqrt();
+            prims.push(Box::new(ShapePrimitive::new(
+                Sphere { center: point + normal * (0.6 * radius), radius },
+                Lambertian { albedo: Vector3f::new(random(), random(), random()) },
+            )));
+        }
+    }
+    prims.push(Box::new(ShapePrimitive::new(quad, gray)));
+    prims.push(Box::new(ShapePrimitive::new(disk, gold)));
+    prims.push(Box::new(ShapePrimitive::new(cylinder, gray)));
+    prims.push(Box::new(ShapePrimitive::new(cone, gold)));
+    prims.push(Box::new(ShapePrimitive::new(cuboid, gray)));
+    prims.push(Box::new(ShapePrimitive::new(torus, gold)));
+    prims
+}
+
+/// A row of balls, one of each material: rough gold, polished copper, brushed aluminium, a silver mirror, frosted
+/// glass, green glass, principled red car paint, velvet and wax, and coated metal and lacquered wood; with a window
+/// pane in front of the glass.
+pub fn materials_scene() -> Vec<Box<dyn Primitive>> {
+    let ball = |i: i16| Sphere {
+        center: Point3f::new(-11.0 + 2.2 * Float::from(i), 1.0, 0.0),
+        radius: 1.0,
+    };
+    vec![
+        Box::new(ShapePrimitive::new(
+            Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+            Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(0),
+            Conductor::new(MeasuredMetal::Gold, Vector2f::new(0.2, 0.2)),
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(1),
+            Conductor::new(MeasuredMetal::Copper, Vector2f::new(0.05, 0.05)),
+        )),
+        // Brushed along the lines of latitude.
+        Box::new(ShapePrimitive::new(
+            ball(2),
+            Conductor::new(MeasuredMetal::Aluminium, Vector2f::new(0.05, 0.4)),
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(3),
+            Conductor::new(MeasuredMetal::Silver, Vector2f::new(0.0, 0.0)),
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(4),
+            RoughDielectric::new(1.5, Vector2f::new(0.15, 0.15)),
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(5),
+            RoughDielectric::new(1.5, Vector2f::new(0.0, 0.0))
+                .with_transmittance(Vector3f::new(0.4, 0.8, 0.5), 2.0),
+        )),
+        Box::new(ShapePrimitive::new(
+            Quad {
+                corner: Point3f::new(-2.7, 0.0, 2.0),
+                u: Vector3f::new(3.0, 0.0, 0.8),
+                v: Vector3f::new(0.0, 2.5, 0.0),
+            },
+            ThinDielectric { eta: 1.5 },
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(6),
+            Principled {
+                base_color: Vector3f::new(0.6, 0.02, 0.02),
+                metallic: 0.3,
+                roughness: 0.4,
+                clearcoat: 1.0,
+                ..Principled::default()
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(7),
+            Principled {
+                base_color: Vector3f::new(0.2, 0.05, 0.3),
+                roughness: 1.0,
+                specular: 0.2,
+                sheen: 1.0,
+                ..Principled::default()
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(8),
+            Principled {
+                base_color: Vector3f::new(0.9, 0.8, 0.6),
+                roughness: 0.3,
+                subsurface: 1.0,
+                transmission: 0.2,
+                ..Principled::default()
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(9),
+            Coated {
+                coat: RoughDielectric::new(1.5, Vector2f::new(0.0, 0.0)),
+                thickness: 0.0,
+                base: Conductor::new(MeasuredMetal::Aluminium, Vector2f::new(0.3, 0.3)),
+            },
+        )),
+        // The base shows through an amber lacquer, darker where it is seen at an angle.
+        Box::new(ShapePrimitive::new(
+            ball(10),
+            Coated {
+                coat: RoughDielectric::new(1.5, Vector2f::new(0.05, 0.05))
+                    .with_transmittance(Vector3f::new(0.9, 0.6, 0.3), 1.0),
+                thickness: 0.5,
+                base: Lambertian { albedo: Vector3f::new(0.5, 0.3, 0.15) },
+            },
+        )),
+    ]
+}
+
+/// Textured surfaces: a checkered floor, marble, metal with patches of different roughness, glass of varying density,
+/// and the UV grid image on a ball, a tile, a disk and a box, each wrapping it differently, and tiled down a long
+/// strip of floor that needs filtering into the distance. In front, smaller balls of copper in patches of roughness,
+/// frosted glass clouded with color, lacquer of uneven thickness, and a principled checkerboard of metal and plastic.
+pub fn textures_scene() -> image::ImageResult<Vec<Box<dyn Primitive>>> {
+    let grid = TextureImage::load(concat!(env!("CARGO_MANIFEST_DIR"), "/textures/uv-grid.png"))?;
+    let grid = Arc::new(MipMap::new(grid));
+    let ball = |i: i16| Sphere {
+        center: Point3f::new(-3.3 + 2.2 * Float::from(i), 1.0, 0.0),
+        radius: 1.0,
+    };
+    let marble = Noise { frequency: 2.0, octaves: 6 };
+    let small_ball = |x: Float| Sphere { center: Point3f::new(x, 0.5, 2.5), radius: 0.5 };
+    let squares = Mapping::Uv { scale: Vector2f::new(8.0, 4.0), offset: Vector2f::zero() };
+    let mut painted = Principled::default().shared();
+    let orange = Vector3f::new(0.9, 0.6, 0.2);
+    painted.base_color =
+        Arc::new(Checkerboard { a: orange, b: Vector3f::new(0.1, 0.2, 0.6), mapping: squares });
+    painted.metallic = Arc::new(Checkerboard { a: 1.0, b: 0.0, mapping: squares });
+    painted.roughness = Arc::new(Checkerboard { a: 0.2, b: 0.5, mapping: squares });
+    painted.clearcoat = Arc::new(Checkerboard { a: 0.0, b: 1.0, mapping: squares });
+    Ok(vec![
+        Box::new(ShapePrimitive::new(
+            Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+            Lambertian {
+                albedo: Checkerboard {
+                    a: Vector3f::new(0.8, 0.8, 0.8),
+                    b: Vector3f::new(0.2, 0.2, 0.2),
+                    mapping: Mapping::Planar { s: Vector3f::unit_x(), t: Vector3f::unit_z() },
+                },
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(0),
+            Lambertian {
+                albedo: Mix {
+                    a: Vector3f::new(0.9, 0.88, 0.85),
+                    b: Vector3f::new(0.3, 0.3, 0.35),
+                    amount: marble,
+                },
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(1),
+            Metal { albedo: Vector3f::new(0.8, 0.8, 0.85), fuzz: Voronoi { frequency: 4.0 } },
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(2),
+            Dielectric { ref_index: Mix { a: 1.3, b: 1.7, amount: marble } },
+        )),
+        Box::new(ShapePrimitive::new(
+            ball(3),
+            Lambertian {
+                albedo: ImageTexture {
+                    image: grid.clone(),
+                    mapping: Mapping::Uv {
+                        scale: Vector2f::new(2.0, 1.0),
+                        offset: Vector2f::zero(),
+                    },
+                    wrap: WrapMode::Repeat,
+                    filter: Filter::Trilinear,
+                },
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            Quad {
+                corner: Point3f::new(-1.0, 0.001, 3.0),
+                u: Vector3f::new(2.0, 0.0, 0.0),
+                v: Vector3f::new(0.0, 0.0, -60.0),
+            },
+            Lambertian {
+                albedo: ImageTexture {
+                    image: grid.clone(),
+                    mapping: Mapping::Uv {
+                        scale: Vector2f::new(1.0, 30.0),
+                        offset: Vector2f::zero(),
+                    },
+                    wrap: WrapMode::Repeat,
+                    filter: Filter::Ewa { max_anisotropy: 8.0 },
+                },
+            },
+        )),
+        // Past its edges the image is flipped on the tile and stretched on the disk.
+        Box::new(ShapePrimitive::new(
+            Quad {
+                corner: Point3f::new(-4.0, 0.0, -3.0),
+                u: Vector3f::new(3.0, 0.0, 0.0),
+                v: Vector3f::new(0.0, 3.0, 0.0),
+            },
+            Lambertian {
+                albedo: ImageTexture {
+                    image: grid.clone(),
+                    mapping: Mapping::Uv {
+                        scale: Vector2f::new(2.0, 2.0),
+                        offset: Vector2f::zero(),
+                    },
+                    wrap: WrapMode::Mirror,
+                    filter: Filter::Trilinear,
+                },
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            Disk { center: Point3f::new(0.5, 1.5, -3.0), normal: Vector3f::unit_z(), radius: 1.5 },
+            Lambertian {
+                albedo: ImageTexture {
+                    image: grid.clone(),
+                    mapping: Mapping::Uv {
+                        scale: Vector2f::new(1.0, 1.5),
+                        offset: Vector2f::new(0.0, -0.25),
+                    },
+                    wrap: WrapMode::Clamp,
+                    filter: Filter::Bilinear,
+                },
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            Cuboid { min: Point3f::new(2.5, 0.0, -3.5), max: Point3f::new(4.5, 2.0, -1.5) },
+            Lambertian {
+                albedo: ImageTexture {
+                    image: grid,
+                    mapping: Mapping::Spherical { center: Point3f::new(3.5, 1.0, -2.5) },
+                    wrap: WrapMode::Repeat,
+                    filter: Filter::Ewa { max_anisotropy: 8.0 },
+                },
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            small_ball(-3.0),
+            Conductor::new(
+                MeasuredMetal::Copper,
+                Mix {
+                    a: Vector2f::new(0.02, 0.02),
+                    b: Vector2f::new(0.4, 0.4),
+                    amount: Voronoi { frequency: 8.0 },
+                },
+            ),
+        )),
+        Box::new(ShapePrimitive::new(
+            small_ball(-1.8),
+            RoughDielectric {
+                eta: 1.5,
+                roughness: Mix { a: Vector2f::zero(), b: Vector2f::new(0.3, 0.3), amount: marble },
+                absorption: Mix {
+                    a: Vector3f::zero(),
+                    b: Vector3f::new(3.0, 0.5, 3.0),
+                    amount: Noise { frequency: 4.0, octaves: 2 },
+                },
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            small_ball(1.6),
+            Coated {
+                coat: RoughDielectric::new(1.5, Vector2f::new(0.05, 0.05))
+                    .with_transmittance(Vector3f::new(0.9, 0.6, 0.3), 1.0),
+                thickness: Mix { a: 0.1, b: 1.0, amount: Noise { frequency: 3.0, octaves: 4 } },
+                base: Lambertian { albedo: Vector3f::new(0.8, 0.8, 0.7) },
+            },
+        )),
+        Box::new(ShapePrimitive::new(small_ball(2.8), painted)),
+    ])
+}
+
+/// Surfaces with detail from normal and bump maps: a brick floor of two triangles with tiled uvs, a brick disk
+/// standing on it, a gold ball with the same bricks, and a hammered mirror and piece of glass.
+pub fn bump_scene() -> image::ImageResult<Vec<Box<dyn Primitive>>> {
+    let bricks = TextureImage::load_linear(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/textures/bricks-normal.png"
+    ))?;
+    let bricks = NormalMap {
+        texture: ImageTexture {
+            image: Arc::new(MipMap::new(bricks)),
+            mapping: Mapping::Uv { scale: Vector2f::new(1.0, 1.0), offset: Vector2f::zero() },
+            wrap: WrapMode::Repeat,
+            filter: Filter::Trilinear,
+        },
+    };
+    let floor = |p0: [Float; 2], p1: [Float; 2], p2: [Float; 2]| {
+        let corner = |p: [Float; 2]| Point3f::new(p[0], 0.0, p[1]);
+        // Two bricks' width to a unit.
+        let uv = |p: [Float; 2]| Point2f::new(p[0] / 2.0, -p[1] / 2.0);
+        Triangle {
+            p0: corner(p0),
+            p1: corner(p1),
+            p2: corner(p2),
+            uvs: Some([uv(p0), uv(p1), uv(p2)]),
+        }
+    };
+    let mortar = Vector3f::new(0.6, 0.3, 0.2);
+    let hammered = |frequency: Float| BumpMap { height: Voronoi { frequency }, scale: 0.02 };
+    Ok(vec![
+        Box::new(ShapePrimitive::new(
+            floor([-20.0, 20.0], [20.0, 20.0], [20.0, -20.0]),
+            Bumped { base: Lambertian { albedo: mortar }, bump: bricks.clone() },
+        )),
+        Box::new(ShapePrimitive::new(
+            floor([-20.0, 20.0], [20.0, -20.0], [-20.0, -20.0]),
+            Bumped { base: Lambertian { albedo: mortar }, bump: bricks.clone() },
+        )),
+        Box::new(ShapePrimitive::new(
+            Disk { center: Point3f::new(0.0, 1.5, -3.0), normal: Vector3f::unit_z(), radius: 1.5 },
+            Bumped {
+                base: Lambertian { albedo: Vector3f::new(0.7, 0.7, 0.7) },
+                bump: bricks.clone(),
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            Sphere { center: Point3f::new(-2.2, 1.0, 0.0), radius: 1.0 },
+            Bumped {
+                base: Conductor::new(MeasuredMetal::Gold, Vector2f::new(0.2, 0.2)),
+                bump: bricks,
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            Sphere { center: Point3f::new(0.0, 1.0, 0.0), radius: 1.0 },
+            Bumped {
+                base: Metal { albedo: Vector3f::new(0.9, 0.9, 0.9), fuzz: 0.0 },
+                bump: hammered(6.0),
+            },
+        )),
+        Box::new(ShapePrimitive::new(
+            Sphere { center: Point3f::new(2.2, 1.0, 0.0), radius: 1.0 },
+            Bumped {
+                base: Dielectric { ref_index: 1.5 },
+                bump: BumpMap { height: Noise { frequency: 3.0, octaves: 4 }, scale: 0.05 },
+            },
+        )),
+    ])
+}
+
+/// A ball covered in curly fur.
+pub fn fur_scene() -> Vec<Box<dyn Primitive>> {
+    let mut random = util::new_random(0);
+    let ball = Sphere { center: Point3f::new(0.0, 1.0, 0.0), radius: 1.0 };
+    let mut prims: Vec<Box<dyn Primitive>> = vec![
+        Box::new(ShapePrimitive::new(
+            Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+            Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+        )),
+        Box::new(ShapePrimitive::new(
+            Sphere { center: ball.center, radius: ball.radius },
+            Lambertian { albedo: Vector3f::new(0.3, 0.2, 0.1) },
+        )),
+    ];
+    let hair = Hair::from_melanin(1.3, 0.2);
+    for _ in 0..20_000 {
+        let (root, normal) = ball.sample(Point2f::new(random(), random()));
+        let length = 0.2 + 0.1 * random();
+        let mut random_vector =
+            || Vector3f::new(random(), random(), random()) * 2.0 - Vector3f::from_value(1.0);
+        // Hairs leave the skin along the normal, then droop and curl.
+        let control = [
+            root,
+            root + normal * (length / 3.0),
+            root + (normal + random_vector() * 0.5) * (length * 2.0 / 3.0),
+            root + (normal + random_vector() * 0.7 - Vector3f::unit_y() * 0.3) * length,
+        ];
+        let curve = Curve::new(control, [0.004, 0.001], CurveType::Flat);
+        for segment in curve.split(2) {
+            prims.push(Box::new(ShapePrimitive::new(segment, hair)));
+        }
+    }
+    // A few thick, pale whiskers, shaded as tubes.
+    let whisker = Hair::from_color(Vector3f::new(0.9, 0.85, 0.8), 0.3);
+    for i in 0..12 {
+        let angle = i as Float * PI / 6.0;
+        let root = ball.center + Vector3f::new(0.3 * angle.cos(), 0.3 * angle.sin() - 0.2, 0.95);
+        let out = Vector3f::new(angle.cos(), angle.sin() * 0.3, 0.2);
+        let control =
+            [root, root + out * 0.3, root + out * 0.6, root + out * 0.9 - Vector3f::unit_y() * 0.1];
+        let curve = Curve::new(control, [0.01, 0.002], CurveType::Cylinder);
+        for segment in curve.split(4) {
+            prims.push(Box::new(ShapePrimitive::new(segment, whisker)));
+        }
+    }
+    prims
+}
+
+/// Procedural shapes built from distance functions: a blob of smoothly merged spheres with a droplet beside it, a grid
+/// of rounded boxes with holes drilled through, and a tilted twisted torus.
+pub fn sdf_scene() -> Vec<Box<dyn Primitive>> {
+    let blob = SphereSdf { radius: 0.8 }
+        .translate(Vector3f::new(-0.5, 0.0, 0.0))
+        .smooth_union(SphereSdf { radius: 0.6 }.translate(Vector3f::new(0.6, 0.3, 0.0)), 0.4)
+        .smooth_union(SphereSdf { radius: 0.4 }.translate(Vector3f::new(0.0, 0.9, 0.3)), 0.4)
+        .union(SphereSdf { radius: 1.0 }.scale(0.2).translate(Vector3f::new(0.0, 0.0, 1.0)))
+        .translate(Vector3f::new(-3.0, 1.0, 0.0));
+    let drilled = BoxSdf { half_size: Vector3f::from_value(0.3), rounding: 0.05 }
+        .difference(SphereSdf { radius: 0.37 })
+        .repeat(Vector3f::from_value(0.8), [2, 0, 2])
+        .translate(Vector3f::new(0.5, 0.3, 0.0));
+    // Twisting bends space, which stretches distances by up to the twist rate times the radius; scale them back.
+    let twisted =
+        FnSdf::new(AABB::new(Point3f::new(-1.3, -0.3, -1.3), Point3f::new(1.3, 0.3, 1.3)), |p| {
+            let angle = p.y * 2.0;
+            let (sin, cos) = angle.sin_cos();
+            let q = Point3f::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z);
+            let d = TorusSdf { major_radius: 1.0, minor_radius: 0.3 };
+            let square = BoxSdf { half_size: Vector3f::new(1.3, 0.2, 1.3), rounding: 0.05 };
+            d.intersection(square).distance(q) / 3.0
+        })
+        .rotate(Vector3f::unit_x(), 30.0)
+        .translate(Vector3f::new(3.5, 1.2, 0.0));
+
+    vec![
+        Box::new(ShapePrimitive::new(
+            Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+            Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+        )),
+        Box::new(ShapePrimitive::new(SdfShape::new(blob), Dielectric { ref_index: 1.5 })),
+        Box::new(ShapePrimitive::new(
+            SdfShape::new(drilled),
+            Lambertian { albedo: Vector3f::new(0.7, 0.3, 0.2) },
+        )),
+        Box::new(ShapePrimitive::new(
+            SdfShape::new(twisted),
+            Metal { albedo: Vector3f::new(0.8, 0.8, 0.9), fuzz: 0.05 },
+        )),
+    ]
+}
+
+/// Solids carved out of each other.
+pub fn csg_scene() -> Vec<Box<dyn Primitive>> {
+    let lens = Csg::intersection(
+        Sphere { center: Point3f::new(-3.0, 1.2, -1.6), radius: 1.8 },
+        Sphere { center: Point3f::new(-3.0, 1.2, 1.6), radius: 1.8 },
+    );
+    let cutaway = Csg::difference(
+        Sphere { center: Point3f::new(0.0, 1.0, 0.0), radius: 1.0 },
+        Cuboid { min: Point3f::new(0.0, 1.0, 0.0), max: Point3f::new(1.5, 2.5, 1.5) },
+    );
+    // The classic: a box rounded off by a sphere, drilled through along each axis.
+    let center = Point3f::new(3.0, 1.0, 0.0);
+    let drill =
+        |axis: Vector3f| Cylinder { base: center - axis * 1.2, axis: axis * 2.4, radius: 0.45 };
+    let rounded = Csg::intersection(
+        Cuboid { min: center - Vector3f::from_value(0.8), max: center + Vector3f::from_value(0.8) },
+        Sphere { center, radius: 1.05 },
+    );
+    let holes = Csg::union(
+        drill(Vector3f::unit_x()),
+        Csg::union(drill(Vector3f::unit_y()), drill(Vector3f::unit_z())),
+    );
+    let drilled = Csg::difference(rounded, holes);
+    let ring = Csg::difference(
+        Torus {
+            center: Point3f::new(0.0, 0.35, 2.5),
+            axis: Vector3f::unit_y(),
+            major_radius: 0.8,
+            minor_radius: 0.35,
+        },
+        Cone { base: Point3f::new(0.0, 0.0, 3.3), axis: Vector3f::new(0.0, 1.2, 0.0), radius: 0.6 },
+    );
+
+    vec![
+        Box::new(ShapePrimitive::new(
+            Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+            Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+        )),
+        Box::new(ShapePrimitive::new(lens, Dielectric { ref_index: 1.5 })),
+        Box::new(ShapePrimitive::new(cutaway, Lambertian { albedo: Vector3f::new(0.7, 0.3, 0.2) })),
+        Box::new(ShapePrimitive::new(
+            drilled,
+            Metal { albedo: Vector3f::new(0.8, 0.8, 0.9), fuzz: 0.05 },
+        )),
+        Box::new(ShapePrimitive::new(
+            ring,
+            Metal { albedo: Vector3f::new(0.8, 0.6, 0.2), fuzz: 0.2 },
+        )),
+    ]
+}
+
+/// Smooth surfaces subdivided from coarse cages, finely enough for `camera`.
+pub fn subdiv_scene(camera: &dyn Camera) -> Vec<Box<dyn Primitive>> {
+    let tetrahedron = SubdivMesh::new(
+        vec![
+            Point3f::new(2.5, 0.0, -0.8),
+            Point3f::new(4.1, 0.0, -0.8),
+            Point3f::new(3.3, 0.0, 0.7),
+            Point3f::new(3.3, 2.2, -0.3),
+        ],
+        vec![vec![0, 2, 1], vec![0, 1, 3], vec![1, 2, 3], vec![2, 0, 3]],
+    );
+    // A square sheet of 4x4 quads with its middle pulled up, open at the edges.
+    let sheet = SubdivMesh::new(
+        (0..25)
+            .map(|i| {
+                let (x, z) = ((i % 5) as Float, (i / 5) as Float);
+                let lift = iff!(x > 0.0 && x < 4.0 && z > 0.0 && z < 4.0, 0.8, 0.1);
+                Point3f::new(x * 0.6 - 4.5, lift, z * 0.6 - 1.2)
+            })
+            .collect(),
+        (0..16)
+            .map(|i| {
+                let v = i / 4 * 5 + i % 4;
+                vec![v, v + 5, v + 6, v + 1]
+            })
+            .collect(),
+    );
+    let blob = cube_cage(Point3f::new(0.0, 1.0, 0.0), 1.8);
+
+    let mut prims: Vec<Box<dyn Primitive>> = vec![Box::new(ShapePrimitive::new(
+        Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+        Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+    ))];
+    // The bumps need a finer mesh than the smooth surface does.
+    let level = blob.adaptive_level(camera, 1.0) + 1;
+    for t in blob.tessellate_displaced(level, &bumps()) {
+        prims.push(Box::new(ShapePrimitive::new(
+            t,
+            Lambertian { albedo: Vector3f::new(0.7, 0.3, 0.2) },
+        )));
+    }
+    for t in tetrahedron.tessellate(tetrahedron.adaptive_level(camera, 2.0)) {
+        prims.push(Box::new(ShapePrimitive::new(t, Dielectric { ref_index: 1.5 })));
+    }
+    for t in sheet.tessellate(sheet.adaptive_level(camera, 2.0)) {
+        prims.push(Box::new(ShapePrimitive::new(
+            t,
+            Metal { albedo: Vector3f::new(0.8, 0.6, 0.2), fuzz: 0.1 },
+        )));
+    }
+    prims
+}
+
+/// A bumpy blob of about 800k triangles, for measuring one large mesh.
+pub fn mesh_scene() -> Vec<Box<dyn Primitive>> {
+    let blob = cube_cage(Point3f::new(0.0, 1.0, 0.0), 1.8);
+    let mut prims: Vec<Box<dyn Primitive>> = vec![Box::new(ShapePrimitive::new(
+        Sphere { center: Point3f::new(0.0, -1000.0, 0.0), radius: 1000.0 },
+        Lambertian { albedo: Vector3f::new(0.5, 0.5, 0.5) },
+    ))];
+    for t in blob.tessellate_displaced(8, &bumps()) {
+        prims.push(Box::new(ShapePrimitive::new(
+            t,
+            Lambertian { albedo: Vector3f::new(0.7, 0.3, 0.2) },
+        )));
+    }
+    prims
+}
+
+/// The cage of a cube of `size` around `center`, which subdivides into a rounded blob.
+fn cube_cage(center: Point3f, size: Float) -> SubdivMesh {
+    let positions = (0..8)
+        .map(|i| {
+            let corner =
+                Vector3f::new((i & 1) as Float, ((i >> 1) & 1) as Float, (i >> 2) as Float);
+            center + (corner - Vector3f::from_value(0.5)) * size
+        })
+        .collect();
+    let faces = vec![
+        vec![0, 2, 3, 1],
+        vec![4, 5, 7, 6],
+        vec![0, 1, 5, 4],
+        vec![2, 6, 7, 3],
+        vec![0, 4, 6, 2],
+        vec![1, 3, 7, 5],
+    ];
+    SubdivMesh::new(positions, faces)
+}
+
+/// Lumps for displacing a surface by up to 0.04 either way.
+fn bumps() -> Mix<Float, Float, Noise> {
+    Mix { a: -0.04, b: 0.04, amount: Noise { frequency: 3.0, octaves: 3 } }
 }
@@ -0,0 +1,332 @@
+use crate::geom::*;
+use crate::shape::*;
+use crate::transform::*;
+use crate::types::*;
+
+/// A signed distance function: negative inside the surface and positive outside. Its magnitude must never be more
+/// than the distance to the surface, or sphere tracing may step through it.
+pub trait Sdf: Sync + Send {
+    fn distance(&self, p: Point3f) -> Float;
+    /// Bounds the region inside the surface.
+    fn bounds(&self) -> AABB;
+}
+
+/// Combinators, for any `Sdf`.
+pub trait SdfExt: Sdf + Sized {
+    fn union<B: Sdf>(self, b: B) -> Union<Self, B> {
+        Union(self, b)
+    }
+
+    fn intersection<B: Sdf>(self, b: B) -> Intersection<Self, B> {
+        Intersection(self, b)
+    }
+
+    /// Cuts `b` out of `self`.
+    fn difference<B: Sdf>(self, b: B) -> Difference<Self, B> {
+        Difference(self, b)
+    }
+
+    /// Unites the two with a fillet about `k` wide where they meet.
+    fn smooth_union<B: Sdf>(self, b: B, k: Float) -> SmoothUnion<Self, B> {
+        SmoothUnion { a: self, b, k }
+    }
+
+    /// Repeats the shape every `spacing` along each axis, `copies` more times in each direction. The shape should fit
+    /// in its cell, or its copies get cut off.
+    fn repeat(self, spacing: Vector3f, copies: [u32; 3]) -> Repeat<Self> {
+        let copies = Vector3f::new(copies[0] as Float, copies[1] as Float, copies[2] as Float);
+        Repeat { sdf: self, spacing, copies }
+    }
+
+    fn translate(self, v: Vector3f) -> Transformed<Self> {
+        Transformed::new(self, Transform::translate(v), 1.0)
+    }
+
+    /// Rotates counter-clockwise by `degrees` around `axis`.
+    fn rotate(self, axis: Vector3f, degrees: Float) -> Transformed<Self> {
+        Transformed::new(self, Transform::rotate(axis, degrees), 1.0)
+    }
+
+    /// Scales uniformly; distances scale with it, which a non-uniform scale wouldn't allow.
+    fn scale(self, s: Float) -> Transformed<Self> {
+        Transformed::new(self, Transform::scale(Vector3f::from_value(s)), s)
+    }
+}
+
+impl<T: Sdf> SdfExt for T {}
+
+/// A sphere of `radius` around the origin.
+pub struct SphereSdf {
+    pub radius: Float,
+}
+
+impl Sdf for SphereSdf {
+    fn distance(&self, p: Point3f) -> Float {
+        p.to_vec().magnitude() - self.radius
+    }
+    fn bounds(&self) -> AABB {
+        let r = Vector3f::from_value(self.radius);
+        AABB::new(Point3f::from_vec(-r), Point3f::from_vec(r))
+    }
+}
+
+/// A box around the origin reaching `half_size` along each axis, with its edges rounded off by `rounding`.
+pub struct BoxSdf {
+    pub half_size: Vector3f,
+    pub rounding: Float,
+}
+
+impl Sdf for BoxSdf {
+    fn distance(&self, p: Point3f) -> Float {
+        let q = p.to_vec().map(Float::abs) - self.half_size + Vector3f::from_value(self.rounding);
+        let outside = q.map(|v| max!(v, 0.0)).magnitude();
+        let inside = min!(max!(q.x, q.y, q.z), 0.0);
+        outside + inside - self.rounding
+    }
+    fn bounds(&self) -> AABB {
+        AABB::new(Point3f::from_vec(-self.half_size), Point3f::from_vec(self.half_size))
+    }
+}
+
+/// A torus around the y axis through the origin.
+pub struct TorusSdf {
+    pub major_radius: Float,
+    pub minor_radius: Float,
+}
+
+impl Sdf for TorusSdf {
+    fn distance(&self, p: Point3f) -> Float {
+        let ring = p.x.hypot(p.z) - self.major_radius;
+        ring.hypot(p.y) - self.minor_radius
+    }
+    fn bounds(&self) -> AABB {
+        let outer = self.major_radius + self.minor_radius;
+        let r = Vector3f::new(outer, self.minor_radius, outer);
+        AABB::new(Point3f::from_vec(-r), Point3f::from_vec(r))
+    }
+}
+
+/// A distance function given as a closure, which can't work out its own bounds.
+pub struct FnSdf<F> {
+    f: F,
+    bounds: AABB,
+}
+
+impl<F: Fn(Point3f) -> Float + Sync + Send> FnSdf<F> {
+    pub fn new(bounds: AABB, f: F) -> FnSdf<F> {
+        FnSdf { f, bounds }
+    }
+}
+
+impl<F: Fn(Point3f) -> Float + Sync + Send> Sdf for FnSdf<F> {
+    fn distance(&self, p: Point3f) -> Float {
+        (self.f)(p)
+    }
+    fn bounds(&self) -> AABB {
+        self.bounds
+    }
+}
+
+pub struct Union<A, B>(A, B);
+
+impl<A: Sdf, B: Sdf> Sdf for Union<A, B> {
+    fn distance(&self, p: Point3f) -> Float {
+        min!(self.0.distance(p), self.1.distance(p))
+    }
+    fn bounds(&self) -> AABB {
+        self.0.bounds().union(&self.1.bounds())
+    }
+}
+
+pub struct Intersection<A, B>(A, B);
+
+impl<A: Sdf, B: Sdf> Sdf for Intersection<A, B> {
+    fn distance(&self, p: Point3f) -> Float {
+        max!(self.0.distance(p), self.1.distance(p))
+    }
+    fn bounds(&self) -> AABB {
+        self.0.bounds().intersection(&self.1.bounds())
+    }
+}
+
+pub struct Difference<A, B>(A, B);
+
+impl<A: Sdf, B: Sdf> Sdf for Difference<A, B> {
+    fn distance(&self, p: Point3f) -> Float {
+        max!(self.0.distance(p), -self.1.distance(p))
+    }
+    fn bounds(&self) -> AABB {
+        self.0.bounds()
+    }
+}
+
+/// A union blended with the polynomial smooth minimum (Quílez, "Smooth Minimum").
+pub struct SmoothUnion<A, B> {
+    a: A,
+    b: B,
+    k: Float,
+}
+
+impl<A: Sdf, B: Sdf> Sdf for SmoothUnion<A, B> {
+    fn distance(&self, p: Point3f) -> Float {
+        let (a, b) = (self.a.distance(p), self.b.distance(p));
+        let h = clamp!(0.5 + 0.5 * (b - a) / self.k, 0.0, 1.0);
+        b + (a - b) * h - self.k * h * (1.0 - h)
+    }
+    fn bounds(&self) -> AABB {
+        // The fillet undercuts the minimum by at most k/4.
+        self.a.bounds().union(&self.b.bounds()).padded(self.k / 4.0)
+    }
+}
+
+pub struct Repeat<S> {
+    sdf: S,
+    spacing: Vector3f,
+    copies: Vector3f,
+}
+
+impl<S: Sdf> Sdf for Repeat<S> {
+    fn distance(&self, p: Point3f) -> Float {
+        let cell = p.to_vec().div_element_wise(self.spacing).map(Float::round);
+        let cell = Vector3f::new(
+            clamp!(cell.x, -self.copies.x, self.copies.x),
+            clamp!(cell.y, -self.copies.y, self.copies.y),
+            clamp!(cell.z, -self.copies.z, self.copies.z),
+        );
+        self.sdf.distance(p - cell.mul_element_wise(self.spacing))
+    }
+    fn bounds(&self) -> AABB {
+        let reach = self.copies.mul_element_wise(self.spacing);
+        let b = self.sdf.bounds();
+        AABB::new(b.min - reach, b.max + reach)
+    }
+}
+
+/// An `Sdf` moved by a rigid transform and a uniform scale.
+pub struct Transformed<S> {
+    sdf: S,
+    object_to_world: Transform,
+    world_to_object: Transform,
+    scale: Float,
+}
+
+impl<S: Sdf> Transformed<S> {
+    fn new(sdf: S, object_to_world: Transform, scale: Float) -> Transformed<S> {
+        Transformed { sdf, object_to_world, world_to_object: object_to_world.inverse(), scale }
+    }
+}
+
+impl<S: Sdf> Sdf for Transformed<S> {
+    fn distance(&self, p: Point3f) -> Float {
+        self.sdf.distance(self.world_to_object.point(p)) * self.scale
+    }
+    fn bounds(&self) -> AABB {
+        self.object_to_world.aabb(&self.sdf.bounds())
+    }
+}
+
+/// Distances below this count as being on the surface.
+const HIT_DISTANCE: Float = 0.000_01;
+/// Rays that take more steps than this are taken to have missed, e.g. when grazing the surface.
+const MAX_STEPS: usize = 512;
+
+/// A shape whose surface is where an `Sdf` is zero, found by sphere tracing (Hart, "Sphere Tracing").
+///
+/// u and v come from projecting the point along the axis closest to the normal onto the bounds' faces.
+pub struct SdfShape<S: Sdf> {
+    sdf: S,
+    bounds: AABB,
+}
+
+impl<S: Sdf> SdfShape<S> {
+    pub fn new(sdf: S) -> SdfShape<S> {
+        // The surface is only within a hit distance of the zero set, so it may stick out by that much.
+        let bounds = sdf.bounds().padded(HIT_DISTANCE);
+        SdfShape { sdf, bounds }
+    }
+
+    /// The direction of steepest increase of the distance, by central differences at the corners of a tetrahedron.
+    fn gradient(&self, p: Point3f) -> Vector3f {
+        let h = HIT_DISTANCE / 2.0;
+        [
+            Vector3f::new(1.0, -1.0, -1.0),
+            Vector3f::new(-1.0, -1.0, 1.0),
+            Vector3f::new(-1.0, 1.0, -1.0),
+            Vector3f::new(1.0, 1.0, 1.0),
+        ]
+        .iter()
+        .fold(Vector3f::zero(), |g, k| g + k * self.sdf.distance(p + k * h))
+    }
+
+    fn hit(&self, point: Point3f, normal: Vector3f) -> Hit {
+        let dim = (0..3).fold(0, |best, i| iff!(normal[i].abs() > normal[best].abs(), i, best));
+        let (du, dv) = ((dim + 1) % 3, (dim + 2) % 3);
+        let offset = self.bounds.offset_p(&point);
+        let (mut u_axis, mut v_axis) = (Vector3f::zero(), Vector3f::zero());
+        u_axis[du] = self.bounds.max[du] - self.bounds.min[du];
+        v_axis[dv] = self.bounds.max[dv] - self.bounds.min[dv];
+        Hit {
+            point,
+            normal,
+            uv: Point2f::new(clamp!(offset[du], 0.0, 1.0), clamp!(offset[dv], 0.0, 1.0)),
+            // Moving along u and v on the bounds' face, projected onto the surface.
+            dpdu: u_axis - normal * normal.dot(u_axis),
+            dpdv: v_axis - normal * normal.dot(v_axis),
+        }
+    }
+}
+
+impl<S: Sdf> Shape for SdfShape<S> {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        // Only march through the part of the ray inside the bounds.
+        let (mut t, mut t_max) = (0.0, FLOAT_MAX);
+        for dim in 0..3 {
+            let mut t0 = (self.bounds.min[dim] - r.origin[dim]) * r.inv_d[dim];
+            let mut t1 = (self.bounds.max[dim] - r.origin[dim]) * r.inv_d[dim];
+            if r.inv_d[dim] < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t = iff!(t0 > t, t0, t);
+            t_max = iff!(t1 < t_max, t1, t_max);
+        }
+        if t > t_max {
+            return None;
+        }
+
+        // Rays starting inside march towards the surface from within. Rays leaving the surface pick a side by their
+        // direction, and only hit once they've got away from it.
+        let p = r.origin + r.direction * t;
+        let start = self.sdf.distance(p);
+        let sign = if start.abs() >= HIT_DISTANCE {
+            start.signum()
+        } else {
+            iff!(self.gradient(p).dot(r.direction) >= 0.0, 1.0, -1.0)
+        };
+        let mut escaped = start.abs() >= HIT_DISTANCE;
+        for _ in 0..MAX_STEPS {
+            let p = r.origin + r.direction * t;
+            let d = sign * self.sdf.distance(p);
+            if d >= HIT_DISTANCE {
+                escaped = true;
+            } else if escaped && t > 0.000_001 {
+                let normal = self.gradient(p).normalize();
+                return Some(self.hit(p, normal));
+            }
+            t += max!(d, HIT_DISTANCE);
+            if t > t_max {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bounds)
+    }
+
+    fn point_error(&self, r: &Ray3f, hit: &Hit) -> Vector3f {
+        // Marching stops anywhere within the hit distance of the surface.
+        (abs(hit.point.to_vec()) + abs(hit.point - r.origin)) * gamma(7)
+            + Vector3f::from_value(HIT_DISTANCE)
+    }
+}
@@ -1,22 +1,82 @@
 use crate::geom::*;
 use crate::types::*;
+use crate::util::*;
+
+/// Where a ray meets a shape.
+#[derive(Copy, Clone, Debug)]
+pub struct Hit {
+    pub point: Point3f,
+    pub normal: Vector3f,
+    /// The point in the shape's surface parameterization, with both coordinates in [0, 1].
+    pub uv: Point2f,
+    /// The derivative of the point with respect to u, which is tangent to the surface.
+    pub dpdu: Vector3f,
+    /// The derivative of the point with respect to v.
+    pub dpdv: Vector3f,
+}
 
 pub trait Shape: Sync + Send {
     // TODO: &Ray3f to reduce possible copies
-    fn intersect(&self, _: Ray3f) -> Option<(Point3f, Vector3f)>;
+    fn intersect(&self, _: Ray3f) -> Option<Hit>;
     fn bounding_box(&self) -> Option<AABB>;
+    /// Bounds the part of the shape inside `clip`, or None if nothing is inside.
+    fn clip_bounding_box(&self, clip: &AABB) -> Option<AABB> {
+        self.bounding_box().map(|b| b.intersection(clip)).filter(|b| !b.is_empty())
+    }
+    /// Bounds the floating point error in each coordinate of `hit.point`, where `r` hit the shape. By default this
+    /// assumes the point was found by going some distance along the ray, with a few ulps of error in the distance.
+    fn point_error(&self, r: &Ray3f, hit: &Hit) -> Vector3f {
+        (abs(hit.point.to_vec()) + abs(hit.point - r.origin)) * gamma(7)
+    }
+}
+
+/// The magnitude of each component of `v`.
+pub fn abs(v: Vector3f) -> Vector3f {
+    v.map(Float::abs)
+}
+
+/// Shapes with a finite surface that can be sampled uniformly, e.g. to use them as area lights.
+pub trait SampleShape: Shape {
+    fn area(&self) -> Float;
+    /// Maps `u`, uniformly distributed over the unit square, to a point uniformly distributed over the surface, and
+    /// returns that point and its normal.
+    fn sample(&self, u: Point2f) -> (Point3f, Vector3f);
+    /// The density of `sample` at `point` with respect to surface area.
+    fn pdf(&self, _point: Point3f) -> Float {
+        1.0 / self.area()
+    }
+}
+
+/// A point where the line along a ray crosses a surface, at distance `t` along the ray, which may be negative.
+#[derive(Copy, Clone, Debug)]
+pub struct Crossing {
+    pub t: Float,
+    pub hit: Hit,
 }
 
+/// Closed shapes, which divide space into an inside and an outside.
+pub trait Solid: Shape {
+    /// Every point where the ray's line, extended both ways, crosses the surface, sorted by `t`. The line starts
+    /// outside, so the crossings alternate between entering and leaving, starting with entering. Normals point out.
+    fn crossings(&self, r: Ray3f) -> Vec<Crossing>;
+}
+
+/// Hits closer than this to the ray origin are skipped, so that rays leaving a surface don't hit it again.
+const T_MIN: Float = 0.000_001;
+
+/// Flat shapes' bounds are padded by this much, so that the box has some thickness for the slab test.
+const FLAT_PADDING: Float = 0.000_001;
+
 pub struct Sphere {
     pub center: Point3f,
     pub radius: Float,
 }
 
 impl Shape for Sphere {
-    fn intersect(&self, r: Ray3f) -> Option<(Point3f, Vector3f)> {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
         // Due to floating point errors, advance ray up to avoid re-intersecting.
         // NOTE: this is insufficient for very oblique rays; see PBRT error-tracking for a better solution.
-        let r = Ray3f::new(r.origin + r.direction * (0.000_001), r.direction);
+        let r = Ray3f::new_at(r.origin + r.direction * (0.000_001), r.direction, r.time);
         let r2 = self.radius * self.radius;
         let norm_dir = if self.radius > 0.0 { 1.0 } else { -1.0 };
         let l = self.center - r.origin;
@@ -51,12 +111,696 @@ impl Shape for Sphere {
             let thc = (r2 - d2).sqrt();
             r.origin + r.direction * (tca + thc)
         };
-        Some((p, (p - self.center).normalize() * norm_dir))
+        // Move the point onto the surface, which leaves less error than finding it along the ray did.
+        let p =
+            self.center + (p - self.center) * (self.radius.abs() / (p - self.center).magnitude());
+        let outward = (p - self.center).normalize();
+        // Longitude around the y axis and latitude from the bottom pole.
+        let uv = Point2f::new(
+            (Float::atan2(-outward.z, outward.x) + PI) / (2.0 * PI),
+            clamp!(-outward.y, -1.0, 1.0).acos() / PI,
+        );
+        let (dpdu, dpdv) = sphere_derivatives(p - self.center);
+        Some(Hit { point: p, normal: outward * norm_dir, uv, dpdu, dpdv })
     }
     fn bounding_box(&self) -> Option<AABB> {
         let rad = Vector3f::from_value(self.radius);
         Some(AABB::new(self.center - rad, self.center + rad))
     }
+    fn point_error(&self, _: &Ray3f, hit: &Hit) -> Vector3f {
+        (abs(hit.point.to_vec()) + abs(hit.point - self.center)) * gamma(5)
+    }
+}
+
+impl Solid for Sphere {
+    fn crossings(&self, r: Ray3f) -> Vec<Crossing> {
+        let l = r.origin - self.center;
+        let b = l.dot(r.direction);
+        let discriminant = b * b - (l.magnitude2() - self.radius * self.radius);
+        if discriminant <= 0.0 {
+            return vec![];
+        }
+        let root = discriminant.sqrt();
+        [-b - root, -b + root]
+            .iter()
+            .map(|&t| {
+                let point = r.origin + r.direction * t;
+                let point = self.center
+                    + (point - self.center)
+                        * (self.radius.abs() / (point - self.center).magnitude());
+                let outward = (point - self.center) / self.radius.abs();
+                let uv = Point2f::new(
+                    (Float::atan2(-outward.z, outward.x) + PI) / (2.0 * PI),
+                    clamp!(-outward.y, -1.0, 1.0).acos() / PI,
+                );
+                let (dpdu, dpdv) = sphere_derivatives(point - self.center);
+                Crossing { t, hit: Hit { point, normal: outward, uv, dpdu, dpdv } }
+            })
+            .collect()
+    }
+}
+
+/// The derivatives of the point `d` from a sphere's center with respect to its longitude and latitude, as fractions of
+/// a turn and of a half turn.
+fn sphere_derivatives(d: Vector3f) -> (Vector3f, Vector3f) {
+    let dpdu = Vector3f::new(d.z, 0.0, -d.x) * (2.0 * PI);
+    // Up a meridian, which has no direction at the poles.
+    let rho = d.x.hypot(d.z);
+    let dpdv = iff!(
+        rho > 0.0,
+        Vector3f::new(-d.y * d.x / rho, rho, -d.y * d.z / rho) * PI,
+        Vector3f::zero()
+    );
+    (dpdu, dpdv)
+}
+
+impl SampleShape for Sphere {
+    fn area(&self) -> Float {
+        4.0 * PI * self.radius * self.radius
+    }
+    fn sample(&self, u: Point2f) -> (Point3f, Vector3f) {
+        let z = 1.0 - 2.0 * u.x;
+        let r = max!(0.0, 1.0 - z * z).sqrt();
+        let phi = 2.0 * PI * u.y;
+        let outward = Vector3f::new(r * phi.cos(), r * phi.sin(), z);
+        (self.center + outward * self.radius.abs(), iff!(self.radius > 0.0, outward, -outward))
+    }
+}
+
+/// A parallelogram with a corner at `corner` and edges `u` and `v`, which span the uv parameterization. The front face
+/// is the one `u` turns counter-clockwise towards `v` around.
+pub struct Quad {
+    pub corner: Point3f,
+    pub u: Vector3f,
+    pub v: Vector3f,
+}
+
+impl Shape for Quad {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        let n = self.u.cross(self.v);
+        let denom = n.dot(r.direction);
+        if denom == 0.0 {
+            return None;
+        }
+        let t = n.dot(self.corner - r.origin) / denom;
+        if t <= T_MIN {
+            return None;
+        }
+        let point = r.origin + r.direction * t;
+        // Coordinates of the point along the edges, from the plane's dual basis.
+        let w = n / n.magnitude2();
+        let d = point - self.corner;
+        let uv = Point2f::new(w.dot(d.cross(self.v)), w.dot(self.u.cross(d)));
+        if uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 {
+            return None;
+        }
+        Some(Hit { point, normal: n.normalize(), uv, dpdu: self.u, dpdv: self.v })
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        let b = AABB::new(self.corner, self.corner)
+            .union_p(&(self.corner + self.u))
+            .union_p(&(self.corner + self.v))
+            .union_p(&(self.corner + self.u + self.v));
+        Some(b.padded(FLAT_PADDING))
+    }
+}
+
+impl SampleShape for Quad {
+    fn area(&self) -> Float {
+        self.u.cross(self.v).magnitude()
+    }
+    fn sample(&self, u: Point2f) -> (Point3f, Vector3f) {
+        (self.corner + self.u * u.x + self.v * u.y, self.u.cross(self.v).normalize())
+    }
+}
+
+/// A flat disk facing along `normal`. The uv parameterization is the angle around the center and the distance from it.
+pub struct Disk {
+    pub center: Point3f,
+    pub normal: Vector3f,
+    pub radius: Float,
+}
+
+impl Shape for Disk {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        let frame = Frame::new(self.center, self.normal);
+        let (o, d) = frame.local_ray(&r);
+        let hit = first_hit(&[cap_crossing(&o, &d, 0.0, self.radius, 1.0)])?;
+        Some(frame.world_hit(hit))
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(disk_bounds(self.center, self.normal, self.radius).padded(FLAT_PADDING))
+    }
+    fn point_error(&self, _: &Ray3f, hit: &Hit) -> Vector3f {
+        frame_point_error(self.center, hit)
+    }
+}
+
+impl SampleShape for Disk {
+    fn area(&self) -> Float {
+        PI * self.radius * self.radius
+    }
+    fn sample(&self, u: Point2f) -> (Point3f, Vector3f) {
+        let frame = Frame::new(self.center, self.normal);
+        (frame.point(sample_disk(u, self.radius, 0.0)), frame.z)
+    }
+}
+
+/// A closed cylinder around the segment from `base` to `base + axis`.
+///
+/// On the side, u is the angle around the axis and v the height along it; on the caps, they are the angle and the
+/// distance from the axis.
+pub struct Cylinder {
+    pub base: Point3f,
+    pub axis: Vector3f,
+    pub radius: Float,
+}
+
+impl Shape for Cylinder {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        let frame = Frame::new(self.base, self.axis);
+        let (o, d) = frame.local_ray(&r);
+        let hit = first_hit(&self.local_crossings(o, d))?;
+        Some(frame.world_hit(hit))
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        let cap = disk_bounds(self.base, self.axis, self.radius);
+        Some(cap.union(&disk_bounds(self.base + self.axis, self.axis, self.radius)))
+    }
+    fn point_error(&self, _: &Ray3f, hit: &Hit) -> Vector3f {
+        frame_point_error(self.base, hit)
+    }
+}
+
+impl Cylinder {
+    /// Where the ray's line crosses the side and the caps, in the cylinder's frame.
+    fn local_crossings(&self, o: Point3f, d: Vector3f) -> [Option<LocalHit>; 4] {
+        let h = self.axis.magnitude();
+        let roots = polynomial_roots(&[
+            o.x * o.x + o.y * o.y - self.radius * self.radius,
+            2.0 * (o.x * d.x + o.y * d.y),
+            d.x * d.x + d.y * d.y,
+        ]);
+        let side = |i: usize| {
+            let t = *roots.get(i)?;
+            let p = o + d * t;
+            // Move the point onto the side, which leaves less error than finding it along the ray did.
+            let scale = self.radius / p.x.hypot(p.y);
+            let p = Point3f::new(p.x * scale, p.y * scale, p.z);
+            iff!(
+                p.z >= 0.0 && p.z <= h,
+                Some(LocalHit {
+                    t,
+                    point: p,
+                    normal: Vector3f::new(p.x, p.y, 0.0) / self.radius,
+                    uv: Point2f::new(angle_around_z(&p), p.z / h),
+                    dpdu: around_z(&p),
+                    dpdv: Vector3f::new(0.0, 0.0, h),
+                }),
+                None
+            )
+        };
+        [
+            side(0),
+            side(1),
+            cap_crossing(&o, &d, 0.0, self.radius, -1.0),
+            cap_crossing(&o, &d, h, self.radius, 1.0),
+        ]
+    }
+}
+
+impl Solid for Cylinder {
+    fn crossings(&self, r: Ray3f) -> Vec<Crossing> {
+        let frame = Frame::new(self.base, self.axis);
+        let (o, d) = frame.local_ray(&r);
+        frame.convex_crossings(&self.local_crossings(o, d))
+    }
+}
+
+impl SampleShape for Cylinder {
+    fn area(&self) -> Float {
+        2.0 * PI * self.radius * (self.axis.magnitude() + self.radius)
+    }
+    fn sample(&self, u: Point2f) -> (Point3f, Vector3f) {
+        let frame = Frame::new(self.base, self.axis);
+        let h = self.axis.magnitude();
+        let cap = PI * self.radius * self.radius;
+        match pick_part(&[2.0 * PI * self.radius * h, cap, cap], u) {
+            (0, u) => {
+                let phi = 2.0 * PI * u.y;
+                let p = Point3f::new(self.radius * phi.cos(), self.radius * phi.sin(), h * u.x);
+                (frame.point(p), frame.vector(Vector3f::new(p.x, p.y, 0.0) / self.radius))
+            }
+            (1, u) => (frame.point(sample_disk(u, self.radius, 0.0)), -frame.z),
+            (_, u) => (frame.point(sample_disk(u, self.radius, h)), frame.z),
+        }
+    }
+}
+
+/// A closed cone with its base disk at `base` and its apex at `base + axis`.
+///
+/// On the side, u is the angle around the axis and v the height along it; on the base, they are the angle and the
+/// distance from the axis.
+pub struct Cone {
+    pub base: Point3f,
+    pub axis: Vector3f,
+    pub radius: Float,
+}
+
+impl Shape for Cone {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        let frame = Frame::new(self.base, self.axis);
+        let (o, d) = frame.local_ray(&r);
+        let hit = first_hit(&self.local_crossings(o, d))?;
+        Some(frame.world_hit(hit))
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(disk_bounds(self.base, self.axis, self.radius).union_p(&(self.base + self.axis)))
+    }
+    fn point_error(&self, _: &Ray3f, hit: &Hit) -> Vector3f {
+        frame_point_error(self.base, hit)
+    }
+}
+
+impl Cone {
+    /// Where the ray's line crosses the side and the base, in the cone's frame.
+    fn local_crossings(&self, o: Point3f, d: Vector3f) -> [Option<LocalHit>; 3] {
+        let h = self.axis.magnitude();
+        // The side is where x² + y² = (k (h - z))².
+        let k2 = (self.radius / h) * (self.radius / h);
+        let oh = h - o.z;
+        let roots = polynomial_roots(&[
+            o.x * o.x + o.y * o.y - k2 * oh * oh,
+            2.0 * (o.x * d.x + o.y * d.y + k2 * oh * d.z),
+            d.x * d.x + d.y * d.y - k2 * d.z * d.z,
+        ]);
+        let side = |i: usize| {
+            let t = *roots.get(i)?;
+            let p = o + d * t;
+            if p.z < 0.0 || p.z > h {
+                return None;
+            }
+            // Move the point to the closest one on the side, which is on the line from (r, 0) to (0, h) in the
+            // half-plane through the axis and the point.
+            let (r, rho) = (self.radius, p.x.hypot(p.y));
+            let s = clamp!((r * (r - rho) + h * p.z) / (r * r + h * h), 0.0, 1.0);
+            let scale = iff!(rho > 0.0, r * (1.0 - s) / rho, 0.0);
+            let outward = Vector3f::new(p.x, p.y, 0.0) * iff!(rho > 0.0, 1.0 / rho, 0.0);
+            let p = Point3f::new(p.x * scale, p.y * scale, h * s);
+            Some(LocalHit {
+                t,
+                point: p,
+                normal: Vector3f::new(p.x, p.y, k2 * (h - p.z)).normalize(),
+                uv: Point2f::new(angle_around_z(&p), p.z / h),
+                dpdu: around_z(&p),
+                // Up the slant, towards the apex.
+                dpdv: Vector3f::unit_z() * h - outward * r,
+            })
+        };
+        [side(0), side(1), cap_crossing(&o, &d, 0.0, self.radius, -1.0)]
+    }
+}
+
+impl Solid for Cone {
+    fn crossings(&self, r: Ray3f) -> Vec<Crossing> {
+        let frame = Frame::new(self.base, self.axis);
+        let (o, d) = frame.local_ray(&r);
+        frame.convex_crossings(&self.local_crossings(o, d))
+    }
+}
+
+impl SampleShape for Cone {
+    fn area(&self) -> Float {
+        let slant = self.radius.hypot(self.axis.magnitude());
+        PI * self.radius * (slant + self.radius)
+    }
+    fn sample(&self, u: Point2f) -> (Point3f, Vector3f) {
+        let frame = Frame::new(self.base, self.axis);
+        let h = self.axis.magnitude();
+        let slant = self.radius.hypot(h);
+        match pick_part(&[PI * self.radius * slant, PI * self.radius * self.radius], u) {
+            (0, u) => {
+                // The circumference grows linearly away from the apex, so the fraction of the way down goes as √u.
+                let s = u.x.sqrt();
+                let phi = 2.0 * PI * u.y;
+                let (x, y) = (self.radius * s * phi.cos(), self.radius * s * phi.sin());
+                let normal = Vector3f::new(x, y, self.radius * self.radius / h * s).normalize();
+                (frame.point(Point3f::new(x, y, h * (1.0 - s))), frame.vector(normal))
+            }
+            (_, u) => (frame.point(sample_disk(u, self.radius, 0.0)), -frame.z),
+        }
+    }
+}
+
+/// An axis-aligned box. Each face is parameterized by the next two axes after its normal's, in cyclic order.
+pub struct Cuboid {
+    pub min: Point3f,
+    pub max: Point3f,
+}
+
+impl Shape for Cuboid {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        let (near, far) = self.slabs(&r)?;
+        let crossing = iff!(near.t > T_MIN, near, far);
+        iff!(crossing.t > T_MIN, Some(crossing.hit), None)
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(AABB::new(self.min, self.max))
+    }
+}
+
+impl Cuboid {
+    /// Where the ray's line enters and leaves the box.
+    fn slabs(&self, r: &Ray3f) -> Option<(Crossing, Crossing)> {
+        let (mut t_near, mut t_far) = (FLOAT_MIN, FLOAT_MAX);
+        let (mut near_dim, mut far_dim) = (0, 0);
+        for dim in 0..3 {
+            let mut t0 = (self.min[dim] - r.origin[dim]) * r.inv_d[dim];
+            let mut t1 = (self.max[dim] - r.origin[dim]) * r.inv_d[dim];
+            if r.inv_d[dim] < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            if t0 > t_near {
+                t_near = t0;
+                near_dim = dim;
+            }
+            if t1 < t_far {
+                t_far = t1;
+                far_dim = dim;
+            }
+        }
+        if t_near > t_far {
+            return None;
+        }
+        // Entering, the face looks back at the ray; leaving, it looks the same way.
+        let near =
+            self.face_crossing(r, t_near, near_dim, iff!(r.direction[near_dim] > 0.0, -1.0, 1.0));
+        let far =
+            self.face_crossing(r, t_far, far_dim, iff!(r.direction[far_dim] > 0.0, 1.0, -1.0));
+        Some((near, far))
+    }
+
+    fn face_crossing(&self, r: &Ray3f, t: Float, dim: usize, sign: Float) -> Crossing {
+        let mut point = r.origin + r.direction * t;
+        point[dim] = iff!(sign > 0.0, self.max[dim], self.min[dim]);
+        let mut normal = Vector3f::zero();
+        normal[dim] = sign;
+        let offset = AABB::new(self.min, self.max).offset_p(&point);
+        let uv = Point2f::new(offset[(dim + 1) % 3], offset[(dim + 2) % 3]);
+        let (mut dpdu, mut dpdv) = (Vector3f::zero(), Vector3f::zero());
+        dpdu[(dim + 1) % 3] = self.max[(dim + 1) % 3] - self.min[(dim + 1) % 3];
+        dpdv[(dim + 2) % 3] = self.max[(dim + 2) % 3] - self.min[(dim + 2) % 3];
+        Crossing { t, hit: Hit { point, normal, uv, dpdu, dpdv } }
+    }
+}
+
+impl Solid for Cuboid {
+    fn crossings(&self, r: Ray3f) -> Vec<Crossing> {
+        self.slabs(&r).map(|(near, far)| vec![near, far]).unwrap_or_default()
+    }
+}
+
+impl SampleShape for Cuboid {
+    fn area(&self) -> Float {
+        AABB::new(self.min, self.max).surface_area()
+    }
+    fn sample(&self, u: Point2f) -> (Point3f, Vector3f) {
+        let d = self.max - self.min;
+        let faces = [d.y * d.z, d.y * d.z, d.z * d.x, d.z * d.x, d.x * d.y, d.x * d.y];
+        let (face, u) = pick_part(&faces, u);
+        let dim = face / 2;
+        let mut point = self.min;
+        point[dim] = iff!(face % 2 == 0, self.min[dim], self.max[dim]);
+        point[(dim + 1) % 3] += d[(dim + 1) % 3] * u.x;
+        point[(dim + 2) % 3] += d[(dim + 2) % 3] * u.y;
+        let mut normal = Vector3f::zero();
+        normal[dim] = iff!(face % 2 == 0, -1.0, 1.0);
+        (point, normal)
+    }
+}
+
+/// A ring torus around `axis` through `center`, with its tube's center `major_radius` from the axis.
+///
+/// u is the angle around the axis, and v the angle around the tube, starting from the outer equator.
+pub struct Torus {
+    pub center: Point3f,
+    pub axis: Vector3f,
+    pub major_radius: Float,
+    pub minor_radius: Float,
+}
+
+impl Shape for Torus {
+    fn intersect(&self, r: Ray3f) -> Option<Hit> {
+        let frame = Frame::new(self.center, self.axis);
+        let (o, d) = frame.local_ray(&r);
+        let hit = first_hit(&self.local_crossings(o, d))?;
+        Some(frame.world_hit(hit))
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        let ring = disk_bounds(self.center, self.axis, self.major_radius);
+        Some(ring.padded(self.minor_radius))
+    }
+    fn point_error(&self, _: &Ray3f, hit: &Hit) -> Vector3f {
+        // Rays leaving the surface find it again at a root of the quartic near zero, which is far less accurate than
+        // the point itself, so they have to start further off. The factor is empirical.
+        let reach = self.major_radius + self.minor_radius;
+        frame_point_error(self.center, hit) + Vector3f::from_value(reach * gamma(256))
+    }
+}
+
+impl Torus {
+    /// Where the ray's line crosses the torus, in its frame, in order.
+    fn local_crossings(&self, o: Point3f, d: Vector3f) -> [Option<LocalHit>; 4] {
+        let (big_r2, small_r2) =
+            (self.major_radius * self.major_radius, self.minor_radius * self.minor_radius);
+        // The quartic is badly conditioned far from the torus, so start the line at its bounding sphere.
+        let start = -o.to_vec().dot(d) - (self.major_radius + self.minor_radius);
+        let o = o + d * start;
+        // Substituting the ray into (|p|² + R² - r²)² = 4R²(x² + y²), with |d| = 1.
+        let e = o.to_vec().magnitude2() + big_r2 - small_r2;
+        let f = o.to_vec().dot(d);
+        let roots = polynomial_roots(&[
+            e * e - 4.0 * big_r2 * (o.x * o.x + o.y * o.y),
+            4.0 * e * f - 8.0 * big_r2 * (o.x * d.x + o.y * d.y),
+            4.0 * f * f + 2.0 * e - 4.0 * big_r2 * (d.x * d.x + d.y * d.y),
+            4.0 * f,
+            1.0,
+        ]);
+        let mut hits = [None; 4];
+        for (hit, &t) in hits.iter_mut().zip(roots.iter()) {
+            let p = o + d * t;
+            // Move the point to the closest one on the tube around the ring.
+            let rho = p.x.hypot(p.y);
+            let center = Point3f::new(p.x, p.y, 0.0) * (self.major_radius / rho);
+            let p = center + (p - center) * (self.minor_radius / (p - center).magnitude());
+            let s = p.to_vec().magnitude2() + big_r2 - small_r2;
+            let normal =
+                (p.to_vec() * s - Vector3f::new(p.x, p.y, 0.0) * (2.0 * big_r2)).normalize();
+            let ring = p.x.hypot(p.y) - self.major_radius;
+            let v = Float::atan2(p.z, ring) / (2.0 * PI);
+            let uv = Point2f::new(angle_around_z(&p), iff!(v < 0.0, v + 1.0, v));
+            // Around the tube, from the outer equator over the top.
+            let outward = Vector3f::new(p.x, p.y, 0.0) / (ring + self.major_radius);
+            let dpdv = (Vector3f::unit_z() * ring - outward * p.z) * (2.0 * PI);
+            *hit = Some(LocalHit { t: t + start, point: p, normal, uv, dpdu: around_z(&p), dpdv });
+        }
+        hits
+    }
+}
+
+impl Solid for Torus {
+    fn crossings(&self, r: Ray3f) -> Vec<Crossing> {
+        let frame = Frame::new(self.center, self.axis);
+        let (o, d) = frame.local_ray(&r);
+        let mut crossings: Vec<Crossing> = self
+            .local_crossings(o, d)
+            .iter()
+            .flatten()
+            .map(|hit| Crossing { t: hit.t, hit: frame.world_hit(*hit) })
+            .collect();
+        // A line grazing the surface touches it at a double root, which may come out as one or none.
+        if crossings.len() % 2 == 1 {
+            crossings.clear();
+        }
+        crossings
+    }
+}
+
+impl SampleShape for Torus {
+    fn area(&self) -> Float {
+        4.0 * PI * PI * self.major_radius * self.minor_radius
+    }
+    fn sample(&self, u: Point2f) -> (Point3f, Vector3f) {
+        let frame = Frame::new(self.center, self.axis);
+        let (big_r, small_r) = (self.major_radius, self.minor_radius);
+        // The surface is denser on the outside of the tube: the CDF of the angle around it is
+        // (Rθ + r sin θ) / 2πR, which is inverted with Newton's method.
+        let target = 2.0 * PI * big_r * u.x;
+        let mut theta = 2.0 * PI * u.x;
+        for _ in 0..8 {
+            let err = big_r * theta + small_r * theta.sin() - target;
+            theta = clamp!(theta - err / (big_r + small_r * theta.cos()), 0.0, 2.0 * PI);
+        }
+        let phi = 2.0 * PI * u.y;
+        let radial = Vector3f::new(phi.cos(), phi.sin(), 0.0);
+        let normal = radial * theta.cos() + Vector3f::unit_z() * theta.sin();
+        let p = Point3f::from_vec(radial * big_r + normal * small_r);
+        (frame.point(p), frame.vector(normal))
+    }
+}
+
+/// An orthonormal frame whose z axis is a shape's axis of symmetry, so intersections can be done in local space.
+/// Since the frame is orthonormal, ray distances are the same in both spaces.
+struct Frame {
+    origin: Point3f,
+    x: Vector3f,
+    y: Vector3f,
+    z: Vector3f,
+}
+
+impl Frame {
+    fn new(origin: Point3f, axis: Vector3f) -> Frame {
+        let z = axis.normalize();
+        let (x, y) = coordinate_system(z);
+        Frame { origin, x, y, z }
+    }
+
+    fn local_ray(&self, r: &Ray3f) -> (Point3f, Vector3f) {
+        let o = r.origin - self.origin;
+        let d = r.direction;
+        (
+            Point3f::new(o.dot(self.x), o.dot(self.y), o.dot(self.z)),
+            Vector3f::new(d.dot(self.x), d.dot(self.y), d.dot(self.z)),
+        )
+    }
+
+    fn point(&self, p: Point3f) -> Point3f {
+        self.origin + self.vector(p.to_vec())
+    }
+
+    fn vector(&self, v: Vector3f) -> Vector3f {
+        self.x * v.x + self.y * v.y + self.z * v.z
+    }
+
+    /// Keeps the first and last of a convex shape's crossings, since any in between are where its parts meet.
+    fn convex_crossings(&self, hits: &[Option<LocalHit>]) -> Vec<Crossing> {
+        let by_t = |a: &&LocalHit, b: &&LocalHit| {
+            a.t.partial_cmp(&b.t).unwrap_or(std::cmp::Ordering::Equal)
+        };
+        match (hits.iter().flatten().min_by(by_t), hits.iter().flatten().max_by(by_t)) {
+            (Some(near), Some(far)) if near.t < far.t => vec![
+                Crossing { t: near.t, hit: self.world_hit(*near) },
+                Crossing { t: far.t, hit: self.world_hit(*far) },
+            ],
+            _ => vec![],
+        }
+    }
+
+    fn world_hit(&self, hit: LocalHit) -> Hit {
+        Hit {
+            point: self.point(hit.point),
+            normal: self.vector(hit.normal),
+            uv: hit.uv,
+            dpdu: self.vector(hit.dpdu),
+            dpdv: self.vector(hit.dpdv),
+        }
+    }
+}
+
+/// Bounds the error in a hit found on the surface in a `Frame` at `origin`, and moved from there to world space.
+fn frame_point_error(origin: Point3f, hit: &Hit) -> Vector3f {
+    (abs(hit.point.to_vec()) + abs(hit.point - origin)) * gamma(7)
+}
+
+/// A hit in a `Frame`, before it is moved back to world space.
+#[derive(Copy, Clone)]
+struct LocalHit {
+    t: Float,
+    /// On the surface, which is closer to it than the point along the ray.
+    point: Point3f,
+    normal: Vector3f,
+    uv: Point2f,
+    dpdu: Vector3f,
+    dpdv: Vector3f,
+}
+
+/// The closest of `hits` that is far enough along the ray.
+fn first_hit(hits: &[Option<LocalHit>]) -> Option<LocalHit> {
+    hits.iter().flatten().filter(|hit| hit.t > T_MIN).fold(None, |best: Option<LocalHit>, hit| {
+        match best {
+            Some(b) if b.t <= hit.t => best,
+            _ => Some(*hit),
+        }
+    })
+}
+
+/// Intersects a local ray with the disk of `radius` around the z axis at height `z`, facing `normal_z`.
+fn cap_crossing(
+    o: &Point3f, d: &Vector3f, z: Float, radius: Float, normal_z: Float,
+) -> Option<LocalHit> {
+    if d.z == 0.0 {
+        return None;
+    }
+    let t = (z - o.z) / d.z;
+    let p = o + d * t;
+    let rho = p.x.hypot(p.y);
+    if rho > radius {
+        return None;
+    }
+    Some(LocalHit {
+        t,
+        // Exactly in the plane, whatever the rounding in t.
+        point: Point3f::new(p.x, p.y, z),
+        normal: Vector3f::new(0.0, 0.0, normal_z),
+        uv: Point2f::new(angle_around_z(&p), rho / radius),
+        dpdu: around_z(&p),
+        // Straight out from the center, which has no direction at the center itself.
+        dpdv: iff!(rho > 0.0, Vector3f::new(p.x, p.y, 0.0) * (radius / rho), Vector3f::zero()),
+    })
+}
+
+/// The angle of `p` around the z axis, as a fraction of a turn in [0, 1).
+fn angle_around_z(p: &Point3f) -> Float {
+    let u = Float::atan2(p.y, p.x) / (2.0 * PI);
+    iff!(u < 0.0, u + 1.0, u)
+}
+
+/// The derivative of `p` with respect to `angle_around_z`.
+fn around_z(p: &Point3f) -> Vector3f {
+    Vector3f::new(-p.y, p.x, 0.0) * (2.0 * PI)
+}
+
+/// Bounds a disk exactly: along each axis, it reaches as far as its radius times the sine of the axis' angle to the
+/// disk's normal.
+fn disk_bounds(center: Point3f, normal: Vector3f, radius: Float) -> AABB {
+    let n = normal.normalize();
+    let extent = Vector3f::new(
+        radius * max!(0.0, 1.0 - n.x * n.x).sqrt(),
+        radius * max!(0.0, 1.0 - n.y * n.y).sqrt(),
+        radius * max!(0.0, 1.0 - n.z * n.z).sqrt(),
+    );
+    AABB::new(center - extent, center + extent)
+}
+
+/// Samples the disk of `radius` around the z axis at height `z` uniformly by area.
+fn sample_disk(u: Point2f, radius: Float, z: Float) -> Point3f {
+    let rho = radius * u.x.sqrt();
+    let phi = 2.0 * PI * u.y;
+    Point3f::new(rho * phi.cos(), rho * phi.sin(), z)
+}
+
+/// Picks one of the parts of a shape with probability proportional to its area, and stretches `u.x` back over [0, 1)
+/// within the chosen part.
+fn pick_part(areas: &[Float], u: Point2f) -> (usize, Point2f) {
+    let total: Float = areas.iter().sum();
+    let mut target = u.x * total;
+    for (i, &area) in areas.iter().enumerate() {
+        if target < area || i == areas.len() - 1 {
+            return (i, Point2f::new(clamp!(target / area, 0.0, 1.0), u.y));
+        }
+        target -= area;
+    }
+    unreachable!("no parts")
 }
 
 #[derive(Copy, Clone)]
@@ -78,6 +822,10 @@ impl fmt::Debug for AABB {
     }
 }
 
+/// Scales the exit distance in slab tests so that they're conservative: rounding can make it too small by a factor of
+/// up to 2 gamma(3), which is just over 3 epsilon (PBRT 3.9.2).
+pub const SLAB_ROUNDING: Float = 1.0 + 2.0 * 3.0 * FLOAT_EPSILON;
+
 impl AABB {
     pub fn empty() -> AABB {
         AABB { min: Point3f::from_value(FLOAT_MAX), max: Point3f::from_value(FLOAT_MIN) }
@@ -96,6 +844,8 @@ impl AABB {
             if r.inv_d[i] < 0.0 {
                 std::mem::swap(&mut t0, &mut t1);
             }
+            // Rounding can bring the far side in past the near one for a ray grazing the box.
+            t1 *= SLAB_ROUNDING;
             t_min = iff!(t0 > t_min, t0, t_min);
             t_max = iff!(t1 < t_max, t1, t_max);
             if t_max <= t_min {
@@ -120,6 +870,26 @@ impl AABB {
         }
     }
 
+    /// Returns the overlap of the two boxes, which is empty if they don't overlap.
+    pub fn intersection(&self, other: &AABB) -> AABB {
+        AABB {
+            min: Point3f::new(
+                self.min[0].max(other.min[0]),
+                self.min[1].max(other.min[1]),
+                self.min[2].max(other.min[2]),
+            ),
+            max: Point3f::new(
+                self.max[0].min(other.max[0]),
+                self.max[1].min(other.max[1]),
+                self.max[2].min(other.max[2]),
+            ),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.min[i] > self.max[i])
+    }
+
     pub fn union_p(&self, other: &Point3f) -> AABB {
         AABB {
             min: Point3f::new(
@@ -154,7 +924,73 @@ impl AABB {
         2.0 * (d.x * d.y + d.y * d.z + d.x * d.z)
     }
 
+    /// Grows the box by `delta` on every side.
+    pub fn padded(&self, delta: Float) -> AABB {
+        let delta = Vector3f::from_value(delta);
+        AABB { min: self.min - delta, max: self.max + delta }
+    }
+
     pub fn center(&self) -> Point3f {
         (self.min + self.max.to_vec()) / 2.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    /// Checks that `pdf` gives the fraction of samples that land where `inside` holds, over the region's `area`.
+    fn assert_density(shape: &dyn SampleShape, area: Float, inside: impl Fn(Point3f) -> bool) {
+        let mut random = util::new_random(0);
+        let n = 100_000;
+        let (mut hits, mut density) = (0, 0.0);
+        for _ in 0..n {
+            let (point, _) = shape.sample(Point2f::new(random(), random()));
+            if inside(point) {
+                hits += 1;
+                density = shape.pdf(point);
+            }
+        }
+        let fraction = hits as Float / n as Float;
+        assert!(
+            (fraction - density * area).abs() < 0.01,
+            "{} of samples, expected {}",
+            fraction,
+            density * area
+        );
+    }
+
+    #[test]
+    fn pdf_is_one_over_area() {
+        let sphere = Sphere { center: Point3f::new(1.0, 2.0, 3.0), radius: 2.0 };
+        assert!((sphere.pdf(Point3f::new(1.0, 4.0, 3.0)) - 1.0 / (16.0 * PI)).abs() < 1e-9);
+        let quad = Quad {
+            corner: Point3f::new(0.0, 0.0, 0.0),
+            u: Vector3f::new(2.0, 0.0, 0.0),
+            v: Vector3f::new(0.0, 3.0, 0.0),
+        };
+        assert!((quad.pdf(Point3f::new(1.0, 1.0, 0.0)) - 1.0 / 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pdf_matches_sampling() {
+        // A cap of the sphere, 2π r (r - h) in area.
+        let sphere = Sphere { center: Point3f::new(0.0, 0.0, 0.0), radius: 2.0 };
+        assert_density(&sphere, 2.0 * PI * 2.0 * (2.0 - 1.0), |p| p.z > 1.0);
+        // The middle of a disk.
+        let disk = Disk {
+            center: Point3f::new(0.0, 1.0, 0.0),
+            normal: Vector3f::new(0.0, 1.0, 0.0),
+            radius: 2.0,
+        };
+        assert_density(&disk, PI, |p| (p - disk.center).magnitude() < 1.0);
+        // A corner of a quad.
+        let quad = Quad {
+            corner: Point3f::new(0.0, 0.0, 0.0),
+            u: Vector3f::new(2.0, 0.0, 0.0),
+            v: Vector3f::new(0.0, 3.0, 0.0),
+        };
+        assert_density(&quad, 1.0, |p| p.x < 1.0 && p.y < 1.0);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::camera::*;
+use crate::types::*;
+
+/// How the two cameras of a stereo pair are set up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoRig {
+    /// Both cameras look straight ahead. Perspective cameras shift their films apart so that things at the target's
+    /// distance line up, which puts them at the depth of the screen; other projections line up at infinity.
+    Parallel,
+    /// Each camera turns in to look at the target (toe-in), which also lines it up but keystones the corners.
+    Converged,
+}
+
+/// Where the two views go on the film.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// The left view on the left, and the right view on the right.
+    SideBySide,
+    /// The left view on top, and the right view below it.
+    TopBottom,
+    /// Side by side while rendering, and then saved to a file per view.
+    Separate,
+}
+
+impl StereoLayout {
+    /// The size of the film holding both views, each of `eye_size`.
+    pub fn film_size(self, eye_size: Point2u) -> Point2u {
+        match self {
+            StereoLayout::SideBySide | StereoLayout::Separate => {
+                Point2u::new(eye_size.x * 2, eye_size.y)
+            }
+            StereoLayout::TopBottom => Point2u::new(eye_size.x, eye_size.y * 2),
+        }
+    }
+}
+
+/// A camera description's second view.
+#[derive(Copy, Clone, Debug)]
+pub struct StereoDesc {
+    /// Distance between the two cameras.
+    pub interaxial: Float,
+    pub rig: StereoRig,
+    pub layout: StereoLayout,
+}
+
+/// Renders a view for each eye onto its own part of one film, so that both come from the same pass over the scene.
+pub struct Stereo {
+    left: Box<dyn Camera>,
+    right: Box<dyn Camera>,
+    layout: StereoLayout,
+    /// The size of each view.
+    eye_size: Point2u,
+}
+
+impl Stereo {
+    pub fn new(
+        left: Box<dyn Camera>, right: Box<dyn Camera>, layout: StereoLayout, eye_size: Point2u,
+    ) -> Stereo {
+        Stereo { left, right, layout, eye_size }
+    }
+
+    /// Where the right view starts on the film. Film rows count up from the bottom, so the left view's being on top
+    /// means the right view starts at the bottom.
+    fn right_corner(&self) -> Point2u {
+        match self.layout {
+            StereoLayout::SideBySide | StereoLayout::Separate => Point2u::new(self.eye_size.x, 0),
+            StereoLayout::TopBottom => Point2u::new(0, 0),
+        }
+    }
+
+    fn left_corner(&self) -> Point2u {
+        match self.layout {
+            StereoLayout::SideBySide | StereoLayout::Separate => Point2u::new(0, 0),
+            StereoLayout::TopBottom => Point2u::new(0, self.eye_size.y),
+        }
+    }
+}
+
+impl Camera for Stereo {
+    fn get_rays(&self, n: usize, film_pos: Point2u) -> Vec<CameraRay> {
+        let left = self.left_corner();
+        let in_left = film_pos.x >= left.x
+            && film_pos.x < left.x + self.eye_size.x
+            && film_pos.y >= left.y
+            && film_pos.y < left.y + self.eye_size.y;
+        let (camera, corner) =
+            iff!(in_left, (&self.left, left), (&self.right, self.right_corner()));
+        camera.get_rays(n, film_pos - corner.to_vec())
+    }
+
+    /// Where `p` lands in the left view.
+    fn project(&self, p: Point3f) -> Option<Point2f> {
+        let corner = self.left_corner().map(|v| v as Float);
+        Some(self.left.project(p)? + corner.to_vec())
+    }
+}
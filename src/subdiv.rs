@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+
+use crate::camera::*;
+use crate::mesh::*;
+use crate::prims::*;
+use crate::texture::*;
+use crate::types::*;
+
+/// The most rounds of subdivision `SubdivMesh::adaptive_level` asks for; each round makes four times as many faces.
+const MAX_LEVEL: u32 = 6;
+
+/// The control mesh, or cage, of a Catmull-Clark subdivision surface (Catmull and Clark, "Recursively generated
+/// B-spline surfaces on arbitrary topological meshes"), which is tessellated into triangles for the BVH.
+///
+/// Faces may have any number of sides, and list their vertices counter-clockwise as seen from the front. Edges of only
+/// one face are boundaries, which subdivide as cubic B-splines so that open meshes keep their rims.
+#[derive(Clone, Debug)]
+pub struct SubdivMesh {
+    positions: Vec<Point3f>,
+    faces: Vec<Vec<usize>>,
+}
+
+/// The faces around each edge, keyed by its two vertices in increasing order.
+type EdgeFaces = BTreeMap<(usize, usize), Vec<usize>>;
+
+impl SubdivMesh {
+    /// A cage with vertices at `positions`, and faces that each list at least three indices into it.
+    pub fn new(positions: Vec<Point3f>, faces: Vec<Vec<usize>>) -> SubdivMesh {
+        for (i, face) in faces.iter().enumerate() {
+            assert!(face.len() >= 3, "face {} has {} vertices, fewer than 3", i, face.len());
+            assert!(
+                face.iter().all(|&v| v < positions.len()),
+                "face {} is {:?}, but there are only {} vertices",
+                i,
+                face,
+                positions.len()
+            );
+        }
+        SubdivMesh { positions, faces }
+    }
+
+    /// The fewest rounds of subdivision after which no edge is longer than `max_edge` pixels on `camera`'s film,
+    /// taking each round to halve the edges. The whole mesh gets the same level, since faces subdivided different
+    /// numbers of times would leave cracks between them. Edges with an end the camera doesn't see don't count.
+    pub fn adaptive_level(&self, camera: &dyn Camera, max_edge: Float) -> u32 {
+        let longest = self
+            .edges()
+            .keys()
+            .filter_map(|&(a, b)| {
+                let (pa, pb) =
+                    (camera.project(self.positions[a])?, camera.project(self.positions[b])?);
+                Some((pb - pa).magnitude())
+            })
+            .fold(0.0, |longest: Float, l| max!(longest, l));
+        iff!(longest > max_edge, min!((longest / max_edge).log2().ceil() as u32, MAX_LEVEL), 0)
+    }
+
+    /// Subdivides `level` times and splits the faces into triangles. Past level 0 the vertices are moved onto the limit
+    /// surface, so the triangles' corners lie on it.
+    pub fn tessellate(&self, level: u32) -> Vec<Triangle> {
+        let mesh = self.refine(level);
+        mesh.triangles(&mesh.positions)
+    }
+
+    /// Like `tessellate`, but moves each vertex along the surface normal by the `displacement` texture, looked up at
+    /// its undisplaced position with `SurfaceInteraction::at`, so it should be a solid texture or be mapped by
+    /// position. Detail finer than the tessellation is lost, so `level` should be high enough to resolve it.
+    pub fn tessellate_displaced<T: Texture<Float>>(
+        &self, level: u32, displacement: &T,
+    ) -> Vec<Triangle> {
+        let mesh = self.refine(level);
+        let displaced: Vec<Point3f> = mesh
+            .positions
+            .iter()
+            .zip(mesh.vertex_normals())
+            .map(|(&p, n)| p + n * displacement.evaluate(&SurfaceInteraction::at(p, n)))
+            .collect();
+        mesh.triangles(&displaced)
+    }
+
+    /// One round of Catmull-Clark, which splits each n-sided face into n quads.
+    pub fn subdivide(&self) -> SubdivMesh {
+        let edges = self.edges();
+        let (n_vertices, n_faces) = (self.positions.len(), self.faces.len());
+        let face_points: Vec<Point3f> = self
+            .faces
+            .iter()
+            .map(|face| {
+                Point3f::centroid(&face.iter().map(|&v| self.positions[v]).collect::<Vec<_>>())
+            })
+            .collect();
+
+        // Gather each vertex's neighbourhood: the face points and edge midpoints around it, and its neighbours along
+        // boundary edges.
+        let mut face_sum = vec![Vector3f::zero(); n_vertices];
+        let mut face_count = vec![0usize; n_vertices];
+        for (face, point) in self.faces.iter().zip(face_points.iter()) {
+            for &v in face {
+                face_sum[v] += point.to_vec();
+                face_count[v] += 1;
+            }
+        }
+        let mut midpoint_sum = vec![Vector3f::zero(); n_vertices];
+        let mut valence = vec![0usize; n_vertices];
+        let mut boundary = vec![Vec::new(); n_vertices];
+        let mut edge_points = Vec::with_capacity(edges.len());
+        let mut edge_index = BTreeMap::new();
+        for (&(a, b), faces) in edges.iter() {
+            let (pa, pb) = (self.positions[a], self.positions[b]);
+            let midpoint = pa.midpoint(pb);
+            for &v in &[a, b] {
+                midpoint_sum[v] += midpoint.to_vec();
+                valence[v] += 1;
+            }
+            if faces.len() == 1 {
+                boundary[a].push(b);
+                boundary[b].push(a);
+            }
+            edge_index.insert((a, b), n_vertices + n_faces + edge_points.len());
+            edge_points.push(if faces.len() == 2 {
+                Point3f::centroid(&[pa, pb, face_points[faces[0]], face_points[faces[1]]])
+            } else {
+                // Boundaries stay put, and so do edges where more than two faces meet, which have no smooth rule.
+                midpoint
+            });
+        }
+
+        let vertex_points = self.positions.iter().enumerate().map(|(v, &p)| {
+            let n = valence[v] as Float;
+            match boundary[v].len() {
+                0 if face_count[v] == valence[v] && valence[v] >= 3 => {
+                    let q = face_sum[v] / n;
+                    let r = midpoint_sum[v] / n;
+                    Point3f::from_vec((q + r * 2.0 + p.to_vec() * (n - 3.0)) / n)
+                }
+                2 => {
+                    let (a, b) = (self.positions[boundary[v][0]], self.positions[boundary[v][1]]);
+                    Point3f::from_vec((a.to_vec() + p.to_vec() * 6.0 + b.to_vec()) / 8.0)
+                }
+                // Corners, and vertices where the mesh isn't a surface.
+                _ => p,
+            }
+        });
+        let mut positions: Vec<Point3f> = vertex_points.collect();
+        positions.extend(face_points);
+        positions.extend(edge_points);
+
+        let edge = |a: usize, b: usize| edge_index[&iff!(a < b, (a, b), (b, a))];
+        let faces = self
+            .faces
+            .iter()
+            .enumerate()
+            .flat_map(|(f, face)| {
+                let n = face.len();
+                (0..n).map(move |i| {
+                    let (prev, v, next) = (face[(i + n - 1) % n], face[i], face[(i + 1) % n]);
+                    vec![v, edge(v, next), n_vertices + f, edge(prev, v)]
+                })
+            })
+            .collect();
+        SubdivMesh { positions, faces }
+    }
+
+    fn edges(&self) -> EdgeFaces {
+        let mut edges = EdgeFaces::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for (i, &a) in face.iter().enumerate() {
+                let b = face[(i + 1) % face.len()];
+                edges.entry(iff!(a < b, (a, b), (b, a))).or_default().push(f);
+            }
+        }
+        edges
+    }
+
+    /// `level` rounds of subdivision, with the vertices moved to the limit surface if there were any.
+    fn refine(&self, level: u32) -> SubdivMesh {
+        let mut mesh = self.clone();
+        for _ in 0..level {
+            mesh = mesh.subdivide();
+        }
+        if level > 0 {
+            mesh.positions = mesh.limit_positions();
+        }
+        mesh
+    }
+
+    /// Where each vertex of an all-quad mesh ends up after infinitely many rounds of subdivision (Halstead et al.,
+    /// "Efficient, fair interpolation using Catmull-Clark surfaces").
+    fn limit_positions(&self) -> Vec<Point3f> {
+        let n_vertices = self.positions.len();
+        // Each neighbour along an edge is in two of the vertex's faces, so it's counted half in each.
+        let mut edge_sum = vec![Vector3f::zero(); n_vertices];
+        let mut diagonal_sum = vec![Vector3f::zero(); n_vertices];
+        let mut face_count = vec![0usize; n_vertices];
+        for face in &self.faces {
+            for i in 0..4 {
+                let v = face[i];
+                let (next, opposite, prev) =
+                    (face[(i + 1) % 4], face[(i + 2) % 4], face[(i + 3) % 4]);
+                edge_sum[v] +=
+                    (self.positions[next].to_vec() + self.positions[prev].to_vec()) / 2.0;
+                diagonal_sum[v] += self.positions[opposite].to_vec();
+                face_count[v] += 1;
+            }
+        }
+        let mut boundary = vec![Vec::new(); n_vertices];
+        for (&(a, b), faces) in self.edges().iter() {
+            if faces.len() == 1 {
+                boundary[a].push(b);
+                boundary[b].push(a);
+            }
+        }
+
+        self.positions
+            .iter()
+            .enumerate()
+            .map(|(v, &p)| match boundary[v].len() {
+                0 if face_count[v] >= 3 => {
+                    let n = face_count[v] as Float;
+                    let sum = p.to_vec() * (n * n) + edge_sum[v] * 4.0 + diagonal_sum[v];
+                    Point3f::from_vec(sum / (n * (n + 5.0)))
+                }
+                2 => {
+                    let (a, b) = (self.positions[boundary[v][0]], self.positions[boundary[v][1]]);
+                    Point3f::from_vec((a.to_vec() + p.to_vec() * 4.0 + b.to_vec()) / 6.0)
+                }
+                _ => p,
+            })
+            .collect()
+    }
+
+    /// The average of the normals of the faces around each vertex, weighted by their areas.
+    fn vertex_normals(&self) -> Vec<Vector3f> {
+        let mut normals = vec![Vector3f::zero(); self.positions.len()];
+        for face in &self.faces {
+            let p0 = self.positions[face[0]];
+            let normal = (1..face.len() - 1).fold(Vector3f::zero(), |n, i| {
+                n + (self.positions[face[i]] - p0).cross(self.positions[face[i + 1]] - p0)
+            });
+            for &v in face {
+                normals[v] += normal;
+            }
+        }
+        normals.iter().map(|n| iff!(n.magnitude2() > 0.0, n.normalize(), *n)).collect()
+    }
+
+    /// Splits each face into a fan of triangles, with its vertices at `positions`.
+    fn triangles(&self, positions: &[Point3f]) -> Vec<Triangle> {
+        self.faces
+            .iter()
+            .flat_map(|face| {
+                (1..face.len() - 1).map(move |i| Triangle {
+                    p0: positions[face[0]],
+                    p1: positions[face[i]],
+                    p2: positions[face[i + 1]],
+                    uvs: None,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,502 @@
+use image::codecs::hdr::HdrDecoder;
+use image::error::{ImageError, ParameterError, ParameterErrorKind};
+use image::{ColorType, DynamicImage, ImageBuffer, Rgb};
+use std::fs::File;
+use std::io::BufReader;
+use std::ops::{Add, Mul};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::prims::*;
+use crate::types::*;
+
+/// A material parameter that varies over surfaces.
+pub trait Texture<T>: Sync + Send {
+    fn evaluate(&self, hit: &SurfaceInteraction) -> T;
+}
+
+/// A constant is a texture that is the same everywhere.
+impl Texture<Float> for Float {
+    fn evaluate(&self, _hit: &SurfaceInteraction) -> Float {
+        *self
+    }
+}
+
+impl Texture<Vector2f> for Vector2f {
+    fn evaluate(&self, _hit: &SurfaceInteraction) -> Vector2f {
+        *self
+    }
+}
+
+impl Texture<Vector3f> for Vector3f {
+    fn evaluate(&self, _hit: &SurfaceInteraction) -> Vector3f {
+        *self
+    }
+}
+
+/// A shared texture, which also lets parameters of the same type be different kinds of texture.
+impl<T> Texture<T> for Arc<dyn Texture<T>> {
+    fn evaluate(&self, hit: &SurfaceInteraction) -> T {
+        (**self).evaluate(hit)
+    }
+}
+
+/// Where in a 2D texture a hit is.
+#[derive(Copy, Clone, Debug)]
+pub enum Mapping {
+    /// The surface's own parameterization, scaled and then offset.
+    Uv { scale: Vector2f, offset: Vector2f },
+    /// Longitude and latitude around `center`, as a sphere around it is parameterized.
+    Spherical { center: Point3f },
+    /// The position along `s` and `t`, so that the texture repeats every 1/|s| and 1/|t|.
+    Planar { s: Vector3f, t: Vector3f },
+}
+
+impl Mapping {
+    pub fn map(&self, hit: &SurfaceInteraction) -> TexCoord {
+        match *self {
+            Mapping::Uv { scale, offset } => TexCoord {
+                st: hit.uv.mul_element_wise(Point2f::from_vec(scale)) + offset,
+                dstdx: hit.duvdx.mul_element_wise(scale),
+                dstdy: hit.duvdy.mul_element_wise(scale),
+            },
+            Mapping::Spherical { center } => {
+                let st = spherical(hit.point - center);
+                // Differences over a fraction of the step, since the angles are far from linear over a whole one,
+                // taking the short way across the seam.
+                let delta = 0.1;
+                let diff = |step: Vector3f| {
+                    let d = spherical(hit.point + step * delta - center) - st;
+                    let ds = d.x - d.x.round();
+                    Vector2f::new(ds, d.y) / delta
+                };
+                TexCoord { st, dstdx: diff(hit.dpdx), dstdy: diff(hit.dpdy) }
+            }
+            Mapping::Planar { s, t } => {
+                let p = hit.point.to_vec();
+                TexCoord {
+                    st: Point2f::new(p.dot(s), p.dot(t)),
+                    dstdx: Vector2f::new(hit.dpdx.dot(s), hit.dpdx.dot(t)),
+                    dstdy: Vector2f::new(hit.dpdy.dot(s), hit.dpdy.dot(t)),
+                }
+            }
+        }
+    }
+}
+
+/// The longitude and latitude of `d`, as a sphere is parameterized.
+fn spherical(d: Vector3f) -> Point2f {
+    let d = d.normalize();
+    Point2f::new((Float::atan2(-d.z, d.x) + PI) / (2.0 * PI), clamp!(-d.y, -1.0, 1.0).acos() / PI)
+}
+
+/// A point in a 2D texture, and how far it moves from one pixel to the next, across the film and up it.
+#[derive(Copy, Clone, Debug)]
+pub struct TexCoord {
+    pub st: Point2f,
+    pub dstdx: Vector2f,
+    pub dstdy: Vector2f,
+}
+
+/// Squares of `a` and `b`, one unit of the mapping across.
+#[derive(Copy, Clone, Debug)]
+pub struct Checkerboard<A, B> {
+    pub a: A,
+    pub b: B,
+    pub mapping: Mapping,
+}
+
+impl<T, A: Texture<T>, B: Texture<T>> Texture<T> for Checkerboard<A, B> {
+    fn evaluate(&self, hit: &SurfaceInteraction) -> T {
+        let st = self.mapping.map(hit).st;
+        let parity = (st.x.floor() + st.y.floor()) as i64;
+        iff!(parity.rem_euclid(2) == 0, self.a.evaluate(hit), self.b.evaluate(hit))
+    }
+}
+
+/// Blends from `a` where `amount` is 0 to `b` where it is 1.
+#[derive(Copy, Clone, Debug)]
+pub struct Mix<A, B, M> {
+    pub a: A,
+    pub b: B,
+    pub amount: M,
+}
+
+impl<T, A, B, M> Texture<T> for Mix<A, B, M>
+where
+    T: Add<Output = T> + Mul<Float, Output = T>,
+    A: Texture<T>,
+    B: Texture<T>,
+    M: Texture<Float>,
+{
+    fn evaluate(&self, hit: &SurfaceInteraction) -> T {
+        let t = self.amount.evaluate(hit);
+        self.a.evaluate(hit) * (1.0 - t) + self.b.evaluate(hit) * t
+    }
+}
+
+/// Perlin's gradient noise at the hit's position, summed over `octaves` of doubling frequency and halving amplitude
+/// (fractional Brownian motion), from 0 to 1.
+#[derive(Copy, Clone, Debug)]
+pub struct Noise {
+    /// The frequency of the first octave, in cycles per unit of distance.
+    pub frequency: Float,
+    pub octaves: u32,
+}
+
+impl Texture<Float> for Noise {
+    fn evaluate(&self, hit: &SurfaceInteraction) -> Float {
+        let mut p = hit.point.to_vec() * self.frequency;
+        let (mut sum, mut amplitude) = (0.0, 0.5);
+        for _ in 0..self.octaves {
+            sum += perlin(p) * amplitude;
+            p *= 2.0;
+            amplitude /= 2.0;
+        }
+        clamp!(0.5 + sum, 0.0, 1.0)
+    }
+}
+
+/// Perlin's improved noise, from about -1 to 1, with the gradients at the lattice points picked by hashing them
+/// rather than from a permutation table.
+fn perlin(p: Vector3f) -> Float {
+    let cell = p.map(Float::floor);
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let d = p - cell;
+    let fade = |t: Float| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (u, v, w) = (fade(d.x), fade(d.y), fade(d.z));
+    let grad = |i: i32, j: i32, k: i32| {
+        let d = d - Vector3f::new(i as Float, j as Float, k as Float);
+        // One of the 12 directions to the middles of a cube's edges.
+        let h = hash(x + i, y + j, z + k) & 15;
+        let a = iff!(h < 8, d.x, d.y);
+        let b = iff!(h < 4, d.y, iff!(h == 12 || h == 14, d.x, d.z));
+        iff!(h & 1 == 0, a, -a) + iff!(h & 2 == 0, b, -b)
+    };
+    let lerp = |t: Float, a: Float, b: Float| a + (b - a) * t;
+    lerp(
+        w,
+        lerp(v, lerp(u, grad(0, 0, 0), grad(1, 0, 0)), lerp(u, grad(0, 1, 0), grad(1, 1, 0))),
+        lerp(v, lerp(u, grad(0, 0, 1), grad(1, 0, 1)), lerp(u, grad(0, 1, 1), grad(1, 1, 1))),
+    )
+}
+
+/// Scrambles a lattice point into 32 random looking bits.
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+/// Cells around points scattered through space (Worley's cellular texture): the distance from the hit's position to
+/// the nearest point, which is 0 at the points and about 1 at the edges of their cells.
+#[derive(Copy, Clone, Debug)]
+pub struct Voronoi {
+    /// How many points there are per unit of distance, in each direction.
+    pub frequency: Float,
+}
+
+impl Texture<Float> for Voronoi {
+    fn evaluate(&self, hit: &SurfaceInteraction) -> Float {
+        let p = hit.point.to_vec() * self.frequency;
+        let cell = p.map(Float::floor);
+        let mut nearest: Float = 1.0;
+        // Each cell of the lattice holds one point, and the nearest is in a cell next to the hit's.
+        for i in -1..=1 {
+            for j in -1..=1 {
+                for k in -1..=1 {
+                    let c = cell + Vector3f::new(i as Float, j as Float, k as Float);
+                    let (x, y, z) = (c.x as i32, c.y as i32, c.z as i32);
+                    let h = hash(x, y, z);
+                    let jitter = Vector3f::new(
+                        h as Float,
+                        hash(x, y, z ^ 0x5555) as Float,
+                        hash(x ^ 0x3333, y, z) as Float,
+                    ) / 4_294_967_296.0;
+                    nearest = min!(nearest, (c + jitter - p).magnitude());
+                }
+            }
+        }
+        nearest
+    }
+}
+
+/// How an image texture continues past its edges.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    /// Every other copy is flipped, so that there are no seams.
+    Mirror,
+    /// The edge pixels stretch out.
+    Clamp,
+}
+
+impl WrapMode {
+    /// The pixel that `i` wraps to, in an image `n` pixels across.
+    fn wrap(self, i: isize, n: usize) -> usize {
+        let n = n as isize;
+        (match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                iff!(i < n, i, 2 * n - 1 - i)
+            }
+            WrapMode::Clamp => clamp!(i, 0, n - 1),
+        }) as usize
+    }
+}
+
+/// The pixels of an image, in linear RGB, which textures can share.
+#[derive(Clone, Debug)]
+pub struct TextureImage {
+    width: usize,
+    height: usize,
+    /// Row by row from the top.
+    pixels: Vec<Vector3f>,
+}
+
+impl TextureImage {
+    /// An image of `width` by `height` pixels, which mustn't be empty, as a `MipMap` of it would have no top level.
+    pub fn new(width: usize, height: usize, pixels: Vec<Vector3f>) -> TextureImage {
+        assert!(width > 0 && height > 0, "empty {}x{} image", width, height);
+        assert_eq!(pixels.len(), width * height);
+        TextureImage { width, height, pixels }
+    }
+
+    /// Reads an image file in any format the `image` crate can decode. PNG, JPEG and other 8 or 16 bit images are
+    /// taken to be sRGB, while OpenEXR and Radiance HDR images hold linear values that can go above 1, and are kept
+    /// as they are.
+    pub fn load<P: AsRef<Path>>(path: P) -> image::ImageResult<TextureImage> {
+        TextureImage::read(path, srgb_to_linear)
+    }
+
+    /// Reads an image file as `load` does, but keeps its pixels' values from 0 to 1 as they are, for images of data
+    /// like normal maps rather than of colors.
+    pub fn load_linear<P: AsRef<Path>>(path: P) -> image::ImageResult<TextureImage> {
+        TextureImage::read(path, |c| c)
+    }
+
+    fn read<P: AsRef<Path>>(
+        path: P, decode: fn(Float) -> Float,
+    ) -> image::ImageResult<TextureImage> {
+        let image = if image::ImageFormat::from_path(&path)? == image::ImageFormat::Hdr {
+            // `image::open` would tone map these down to 8 bits.
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+            let (width, height) = (decoder.metadata().width, decoder.metadata().height);
+            let pixels = decoder.read_image_hdr()?;
+            DynamicImage::ImageRgb32F(ImageBuffer::from_fn(width, height, |x, y| {
+                pixels[(y * width + x) as usize]
+            }))
+        } else {
+            image::open(path)?
+        };
+        if let ColorType::Rgb32F | ColorType::Rgba32F = image.color() {
+            let image = image.into_rgb32f();
+            let (width, height) = (image.width() as usize, image.height() as usize);
+            let pixels = image
+                .pixels()
+                .map(|&Rgb([r, g, b])| Vector3f::new(r as Float, g as Float, b as Float));
+            return TextureImage::decoded(width, height, pixels.collect());
+        }
+        // Through 16 bits, which 8 bit images widen to exactly.
+        let image = image.into_rgb16();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = image.pixels().map(|&Rgb([r, g, b])| {
+            Vector3f::new(r as Float, g as Float, b as Float).map(|c| decode(c / 65535.0))
+        });
+        TextureImage::decoded(width, height, pixels.collect())
+    }
+
+    fn decoded(
+        width: usize, height: usize, pixels: Vec<Vector3f>,
+    ) -> image::ImageResult<TextureImage> {
+        if width == 0 || height == 0 {
+            return Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::DimensionMismatch,
+            )));
+        }
+        Ok(TextureImage::new(width, height, pixels))
+    }
+
+    fn pixel(&self, x: isize, y: isize, wrap: WrapMode) -> Vector3f {
+        self.pixels[wrap.wrap(y, self.height) * self.width + wrap.wrap(x, self.width)]
+    }
+
+    /// Interpolates between the four pixels around `st`, where [0,1]² covers the image with t going up.
+    pub fn bilinear(&self, st: Point2f, wrap: WrapMode) -> Vector3f {
+        let x = st.x * self.width as Float - 0.5;
+        let y = (1.0 - st.y) * self.height as Float - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        self.pixel(x0, y0, wrap) * ((1.0 - dx) * (1.0 - dy))
+            + self.pixel(x0 + 1, y0, wrap) * (dx * (1.0 - dy))
+            + self.pixel(x0, y0 + 1, wrap) * ((1.0 - dx) * dy)
+            + self.pixel(x0 + 1, y0 + 1, wrap) * (dx * dy)
+    }
+
+    /// Averages the pixels inside the ellipse around `st` with axes `dst0` and `dst1`, in texture coordinates,
+    /// weighted by a Gaussian that falls to zero at its edge.
+    fn ewa(&self, st: Point2f, dst0: Vector2f, dst1: Vector2f, wrap: WrapMode) -> Vector3f {
+        // In pixels, with y down the image.
+        let size = Vector2f::new(self.width as Float, -(self.height as Float));
+        let x = st.x * self.width as Float - 0.5;
+        let y = (1.0 - st.y) * self.height as Float - 0.5;
+        let (d0, d1) = (dst0.mul_element_wise(size), dst1.mul_element_wise(size));
+        // The ellipse is where a x² + b x y + c y² < 1, widened by a pixel so that it never falls between them.
+        let a = d0.y * d0.y + d1.y * d1.y + 1.0;
+        let b = -2.0 * (d0.x * d0.y + d1.x * d1.y);
+        let c = d0.x * d0.x + d1.x * d1.x + 1.0;
+        let inv_f = 1.0 / (a * c - b * b / 4.0);
+        let (a, b, c) = (a * inv_f, b * inv_f, c * inv_f);
+        // And its bounding box.
+        let det = 4.0 * a * c - b * b;
+        let (half_x, half_y) = (2.0 * (det * c).sqrt() / det, 2.0 * (det * a).sqrt() / det);
+        let (mut sum, mut weights) = (Vector3f::zero(), 0.0);
+        for py in (y - half_y).ceil() as isize..=(y + half_y).floor() as isize {
+            for px in (x - half_x).ceil() as isize..=(x + half_x).floor() as isize {
+                let (dx, dy) = (px as Float - x, py as Float - y);
+                let r2 = a * dx * dx + b * dx * dy + c * dy * dy;
+                if r2 < 1.0 {
+                    let weight = (-2.0 * r2).exp() - (-2.0 as Float).exp();
+                    sum += self.pixel(px, py, wrap) * weight;
+                    weights += weight;
+                }
+            }
+        }
+        iff!(weights > 0.0, sum / weights, self.bilinear(st, wrap))
+    }
+}
+
+fn srgb_to_linear(c: Float) -> Float {
+    iff!(c <= 0.04045, c / 12.92, ((c + 0.055) / 1.055).powf(2.4))
+}
+
+/// An image and copies of it at half the resolution, a quarter and so on down to a single pixel, so that it can be
+/// filtered over large areas in a few lookups (Williams, "Pyramidal Parametrics").
+#[derive(Clone, Debug)]
+pub struct MipMap {
+    /// From the full resolution image up.
+    levels: Vec<TextureImage>,
+}
+
+impl MipMap {
+    /// Builds the pyramid by averaging each 2×2 block of pixels into one. Odd sizes repeat their last row or column.
+    pub fn new(image: TextureImage) -> MipMap {
+        let mut levels = vec![image];
+        loop {
+            let below = &levels[levels.len() - 1];
+            if below.width == 1 && below.height == 1 {
+                break;
+            }
+            let (width, height) = (below.width.div_ceil(2), below.height.div_ceil(2));
+            let mut pixels = Vec::with_capacity(width * height);
+            for y in 0..height as isize {
+                for x in 0..width as isize {
+                    let sum = below.pixel(2 * x, 2 * y, WrapMode::Clamp)
+                        + below.pixel(2 * x + 1, 2 * y, WrapMode::Clamp)
+                        + below.pixel(2 * x, 2 * y + 1, WrapMode::Clamp)
+                        + below.pixel(2 * x + 1, 2 * y + 1, WrapMode::Clamp);
+                    pixels.push(sum / 4.0);
+                }
+            }
+            levels.push(TextureImage::new(width, height, pixels));
+        }
+        MipMap { levels }
+    }
+
+    /// The level whose pixels are `width` apart, in texture coordinates, as a fraction between two levels.
+    fn level(&self, width: Float) -> Float {
+        (self.levels.len() - 1) as Float + max!(width, 1e-8).log2()
+    }
+
+    /// Interpolates between the levels either side of `level`, looking each up with `lookup`. Past the top of the
+    /// pyramid, that is the texture's average color.
+    fn between_levels(&self, level: Float, lookup: impl Fn(&TextureImage) -> Vector3f) -> Vector3f {
+        let top = self.levels.len() - 1;
+        if level >= top as Float {
+            return self.levels[top].pixels[0];
+        }
+        let i = max!(level, 0.0).floor();
+        let delta = max!(level, 0.0) - i;
+        let i = i as usize;
+        lookup(&self.levels[i]) * (1.0 - delta) + lookup(&self.levels[i + 1]) * delta
+    }
+
+    /// Interpolates bilinearly in the full resolution image, without filtering.
+    pub fn bilinear(&self, st: Point2f, wrap: WrapMode) -> Vector3f {
+        self.levels[0].bilinear(st, wrap)
+    }
+
+    /// Filters over a square `width` across, in texture coordinates, by interpolating between the two levels whose
+    /// pixels are nearest that size.
+    pub fn trilinear(&self, st: Point2f, width: Float, wrap: WrapMode) -> Vector3f {
+        let level = self.level(width);
+        if level <= 0.0 {
+            return self.bilinear(st, wrap);
+        }
+        self.between_levels(level, |image| image.bilinear(st, wrap))
+    }
+
+    /// Filters over the ellipse with axes `dst0` and `dst1`, in texture coordinates, with Gaussian weights (Heckbert's
+    /// elliptically weighted average, as in PBRT 10.4.5). Long thin ellipses are widened until they are at most
+    /// `max_anisotropy` times longer than wide, which bounds the number of pixels summed.
+    pub fn ewa(
+        &self, st: Point2f, dst0: Vector2f, dst1: Vector2f, wrap: WrapMode, max_anisotropy: Float,
+    ) -> Vector3f {
+        let (major, minor) =
+            iff!(dst0.magnitude2() < dst1.magnitude2(), (dst1, dst0), (dst0, dst1));
+        let (major_length, minor_length) = (major.magnitude(), minor.magnitude());
+        if minor_length == 0.0 {
+            return self.bilinear(st, wrap);
+        }
+        let minor = iff!(
+            minor_length * max_anisotropy < major_length,
+            minor * (major_length / (minor_length * max_anisotropy)),
+            minor
+        );
+        // In the levels whose pixels are about as far apart as the ellipse is wide, it covers a few pixels across.
+        let level = self.level(minor.magnitude());
+        self.between_levels(level, |image| image.ewa(st, major, minor, wrap))
+    }
+}
+
+/// How an image texture is filtered over the part of it a pixel sees.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// Not at all, so that the texture aliases where its pixels are smaller than the image's.
+    Bilinear,
+    /// Over a square around the pixel's footprint, which blurs textures seen at an angle.
+    Trilinear,
+    /// Over an ellipse fitted to the footprint, which stays sharp at an angle.
+    Ewa { max_anisotropy: Float },
+}
+
+/// An image, filtered over each pixel's footprint on the surface.
+#[derive(Clone, Debug)]
+pub struct ImageTexture {
+    pub image: Arc<MipMap>,
+    pub mapping: Mapping,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+}
+
+impl Texture<Vector3f> for ImageTexture {
+    fn evaluate(&self, hit: &SurfaceInteraction) -> Vector3f {
+        let c = self.mapping.map(hit);
+        match self.filter {
+            Filter::Bilinear => self.image.bilinear(c.st, self.wrap),
+            Filter::Trilinear => {
+                let (dx, dy) = (c.dstdx.map(Float::abs), c.dstdy.map(Float::abs));
+                let width = 2.0 * max!(max!(dx.x, dx.y), dy.x, dy.y);
+                self.image.trilinear(c.st, width, self.wrap)
+            }
+            Filter::Ewa { max_anisotropy } => {
+                self.image.ewa(c.st, c.dstdx, c.dstdy, self.wrap, max_anisotropy)
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use cgmath::{Deg, Matrix, Matrix4, Quaternion, Rotation3, SquareMatrix, Transform as _};
+
+use crate::geom::*;
+use crate::shape::*;
+use crate::types::*;
+
+/// An invertible affine transform, kept together with its inverse.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    m: Matrix4<Float>,
+    m_inv: Matrix4<Float>,
+}
+
+impl Transform {
+    /// Returns None if `m` isn't invertible.
+    pub fn new(m: Matrix4<Float>) -> Option<Transform> {
+        m.invert().map(|m_inv| Transform { m, m_inv })
+    }
+
+    pub fn identity() -> Transform {
+        Transform { m: Matrix4::identity(), m_inv: Matrix4::identity() }
+    }
+
+    pub fn translate(v: Vector3f) -> Transform {
+        Transform { m: Matrix4::from_translation(v), m_inv: Matrix4::from_translation(-v) }
+    }
+
+    /// Scales by `v` along each axis. All components must be non-zero.
+    pub fn scale(v: Vector3f) -> Transform {
+        Transform {
+            m: Matrix4::from_nonuniform_scale(v.x, v.y, v.z),
+            m_inv: Matrix4::from_nonuniform_scale(1.0 / v.x, 1.0 / v.y, 1.0 / v.z),
+        }
+    }
+
+    /// Rotates counter-clockwise by `degrees` around `axis`.
+    pub fn rotate(axis: Vector3f, degrees: Float) -> Transform {
+        let m = Matrix4::from_axis_angle(axis.normalize(), Deg(degrees));
+        Transform { m, m_inv: m.transpose() }
+    }
+
+    /// Rotates by the unit quaternion `q`.
+    pub fn rotation(q: Quaternion<Float>) -> Transform {
+        let m = Matrix4::from(q);
+        Transform { m, m_inv: m.transpose() }
+    }
+
+    /// Returns the transform that applies `self` and then `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform { m: next.m * self.m, m_inv: self.m_inv * next.m_inv }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform { m: self.m_inv, m_inv: self.m }
+    }
+
+    pub fn matrix(&self) -> &Matrix4<Float> {
+        &self.m
+    }
+
+    pub fn point(&self, p: Point3f) -> Point3f {
+        self.m.transform_point(p)
+    }
+
+    /// Bounds the error in `self.point(p)`, given that `p` itself is off by up to `p_error` in each coordinate
+    /// (PBRT 3.9.4).
+    pub fn point_error(&self, p: Point3f, p_error: Vector3f) -> Vector3f {
+        let m = &self.m;
+        let abs = Matrix4::from_cols(
+            m.x.map(Float::abs),
+            m.y.map(Float::abs),
+            m.z.map(Float::abs),
+            m.w.map(Float::abs),
+        );
+        let rounding =
+            (abs.transform_vector(p.to_vec().map(Float::abs)) + abs.w.truncate()) * gamma(3);
+        rounding + abs.transform_vector(p_error) * (gamma(3) + 1.0)
+    }
+
+    pub fn vector(&self, v: Vector3f) -> Vector3f {
+        self.m.transform_vector(v)
+    }
+
+    /// Transforms a surface normal, which has to go through the inverse transpose to stay perpendicular to the
+    /// surface under non-uniform scaling. The result isn't normalized.
+    pub fn normal(&self, n: Vector3f) -> Vector3f {
+        self.m_inv.transpose().transform_vector(n)
+    }
+
+    pub fn ray(&self, r: Ray3f) -> Ray3f {
+        Ray3f::new_at(self.point(r.origin), self.vector(r.direction), r.time)
+    }
+
+    /// Bounds the transformed box by transforming all of its corners.
+    pub fn aabb(&self, b: &AABB) -> AABB {
+        (0..8).fold(AABB::empty(), |res, i| {
+            let corner = Point3f::new(
+                iff!(i & 1 == 0, b.min.x, b.max.x),
+                iff!(i & 2 == 0, b.min.y, b.max.y),
+                iff!(i & 4 == 0, b.min.z, b.max.z),
+            );
+            res.union_p(&self.point(corner))
+        })
+    }
+}
+
+/// The pose of an object at one moment of an animation, applied as scale, then rotation, then translation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: Float,
+    pub translation: Vector3f,
+    pub rotation: Quaternion<Float>,
+    /// Scale along each axis. All components must be non-zero.
+    pub scale: Vector3f,
+}
+
+impl Keyframe {
+    /// The identity pose at `time`.
+    pub fn new(time: Float) -> Keyframe {
+        Keyframe {
+            time,
+            translation: Vector3f::zero(),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3f::from_value(1.0),
+        }
+    }
+
+    pub fn translate(self, translation: Vector3f) -> Keyframe {
+        Keyframe { translation, ..self }
+    }
+
+    /// Rotates counter-clockwise by `degrees` around `axis`.
+    pub fn rotate(self, axis: Vector3f, degrees: Float) -> Keyframe {
+        Keyframe { rotation: Quaternion::from_axis_angle(axis.normalize(), Deg(degrees)), ..self }
+    }
+
+    pub fn scale(self, scale: Vector3f) -> Keyframe {
+        Keyframe { scale, ..self }
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::scale(self.scale)
+            .then(&Transform::rotation(self.rotation))
+            .then(&Transform::translate(self.translation))
+    }
+
+    /// Blends towards `next`, interpolating translation and scale linearly and rotation along the shortest arc.
+    fn lerp(&self, next: &Keyframe, time: Float) -> Keyframe {
+        if next.time <= self.time {
+            return *next;
+        }
+        let amount = (time - self.time) / (next.time - self.time);
+        // q and -q are the same rotation; pick the one that doesn't go the long way around.
+        let rotation = iff!(self.rotation.dot(next.rotation) < 0.0, -next.rotation, next.rotation);
+        Keyframe {
+            time,
+            translation: self.translation.lerp(next.translation, amount),
+            rotation: self.rotation.slerp(rotation, amount).normalize(),
+            scale: self.scale.lerp(next.scale, amount),
+        }
+    }
+}
+
+/// A transform that changes over time, interpolated between keyframes. Before the first and after the last keyframe
+/// it holds still.
+#[derive(Clone, Debug)]
+pub struct AnimatedTransform {
+    keys: Vec<Keyframe>,
+}
+
+/// Number of poses per keyframe interval at which `AnimatedTransform::motion_bounds` transforms the box.
+const MOTION_BOUND_STEPS: usize = 32;
+
+impl AnimatedTransform {
+    /// Returns None if there are no keyframes.
+    pub fn new(mut keys: Vec<Keyframe>) -> Option<AnimatedTransform> {
+        if keys.is_empty() {
+            return None;
+        }
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+        Some(AnimatedTransform { keys })
+    }
+
+    pub fn at(&self, time: Float) -> Transform {
+        let next = self.keys.iter().position(|k| k.time > time);
+        match next {
+            Some(0) => self.keys[0].transform(),
+            Some(i) => self.keys[i - 1].lerp(&self.keys[i], time).transform(),
+            None => self.keys[self.keys.len() - 1].transform(),
+        }
+    }
+
+    /// Bounds `b` over the whole animation.
+    ///
+    /// The box is transformed at a number of poses between each pair of keyframes, and then padded by the furthest
+    /// any corner moved from one pose to the next: steps are small enough that a corner's path between two poses
+    /// stays within that distance of them.
+    pub fn motion_bounds(&self, b: &AABB) -> AABB {
+        let corners: Vec<Point3f> = (0..8)
+            .map(|i| {
+                Point3f::new(
+                    iff!(i & 1 == 0, b.min.x, b.max.x),
+                    iff!(i & 2 == 0, b.min.y, b.max.y),
+                    iff!(i & 4 == 0, b.min.z, b.max.z),
+                )
+            })
+            .collect();
+        let mut bounds = AABB::empty();
+        let mut step: Float = 0.0;
+        let mut prev: Option<Vec<Point3f>> = None;
+        let mut add_pose = |t: &Transform| {
+            let moved: Vec<Point3f> = corners.iter().map(|&c| t.point(c)).collect();
+            for (i, p) in moved.iter().enumerate() {
+                bounds = bounds.union_p(p);
+                if let Some(prev) = &prev {
+                    step = max!(step, (p - prev[i]).magnitude());
+                }
+            }
+            prev = Some(moved);
+        };
+        add_pose(&self.keys[0].transform());
+        for pair in self.keys.windows(2) {
+            for s in 1..=MOTION_BOUND_STEPS {
+                let time = pair[0].time
+                    + (pair[1].time - pair[0].time) * s as Float / MOTION_BOUND_STEPS as Float;
+                add_pose(&pair[0].lerp(&pair[1], time).transform());
+            }
+        }
+        let pad = Vector3f::from_value(step);
+        AABB::new(bounds.min - pad, bounds.max + pad)
+    }
+}
@@ -1,14 +1,32 @@
 pub use cgmath::{Array, ElementWise, EuclideanSpace, InnerSpace, MetricSpace, VectorSpace, Zero};
 pub use cgmath::{Point2 as _Point2, Point3 as _Point3, Vector2 as _Vector2, Vector3 as _Vector3};
 
-pub use std::f64::consts::PI;
-pub use std::f64::MAX as FLOAT_MAX;
-pub use std::f64::MIN as FLOAT_MIN;
-
+// Geometry is f64 unless the `f32` feature is on, which halves the memory for BVH nodes and vertices and doubles the
+// width of the SIMD box tests.
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts::{LN_2, PI};
+#[cfg(not(feature = "f32"))]
 pub type Float = f64;
+
+#[cfg(feature = "f32")]
+pub use std::f32::consts::{LN_2, PI};
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+pub const FLOAT_EPSILON: Float = Float::EPSILON;
+pub const FLOAT_MAX: Float = Float::MAX;
+pub const FLOAT_MIN: Float = Float::MIN;
+
 pub type Vector2f = _Vector2<Float>;
 pub type Vector3f = _Vector3<Float>;
 pub type Point3f = _Point3<Float>;
 pub type Point2f = _Point2<Float>;
 pub type Point2i = _Point2<isize>;
 pub type Point2u = _Point2<usize>;
+
+/// Bounds the relative error of `n` chained floating point operations, each of which rounds by at most half an ulp
+/// (Higham, "Accuracy and Stability of Numerical Algorithms", as used by PBRT 3.9).
+pub fn gamma(n: i32) -> Float {
+    let eps = FLOAT_EPSILON / 2.0;
+    (n as Float * eps) / (1.0 - n as Float * eps)
+}
@@ -51,13 +51,217 @@ pub fn stratified_samples(samples: usize) -> Vec<Point2f> {
     ys.iter().enumerate().map(|(i, y)| Point2f::new(i as Float * interval, *y)).collect()
 }
 
-pub fn stratified_samples_in_disk(samples: usize) -> Vec<Point2f> {
-    stratified_samples(samples)
-        .iter()
-        .map(|v| {
-            let phi = v.x * PI * 2.0;
-            let r = v.y.sqrt();
-            Point2f::new(r * Float::cos(phi), r * Float::sin(phi))
-        })
-        .collect()
+/// Maps `u` from [0,1)² to the unit disk, keeping areas in proportion.
+pub fn disk_sample(u: Point2f) -> Point2f {
+    let phi = u.x * PI * 2.0;
+    let r = u.y.sqrt();
+    Point2f::new(r * Float::cos(phi), r * Float::sin(phi))
+}
+
+/// The luminance of a linear sRGB color (Rec. 709).
+pub fn luminance(c: &Vector3f) -> Float {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// The square root of `x`, or 0 where rounding has taken `x` just below 0.
+pub fn safe_sqrt(x: Float) -> Float {
+    max!(0.0, x).sqrt()
+}
+
+/// Returns two unit vectors that form a right-handed orthonormal basis with the unit vector `n`
+/// (Duff et al., "Building an Orthonormal Basis, Revisited").
+pub fn coordinate_system(n: Vector3f) -> (Vector3f, Vector3f) {
+    let sign = iff!(n.z >= 0.0, 1.0, -1.0);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vector3f::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3f::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+/// The real roots of a polynomial of degree at most four, in increasing order.
+#[derive(Copy, Clone, Debug)]
+pub struct Roots {
+    values: [Float; 4],
+    len: usize,
+}
+
+impl Roots {
+    fn push(&mut self, x: Float) {
+        self.values[self.len] = x;
+        self.len += 1;
+    }
+}
+
+impl std::ops::Deref for Roots {
+    type Target = [Float];
+    fn deref(&self) -> &[Float] {
+        &self.values[..self.len]
+    }
+}
+
+/// Finds the real roots of the polynomial whose coefficient of x^i is `coeffs[i]`.
+///
+/// Above degree two, the roots of the derivative split the real line into intervals on which the polynomial is
+/// monotonic, so each holds at most one root, which is found by bisection. Double roots that only touch zero are
+/// missed unless they are hit exactly.
+pub fn polynomial_roots(coeffs: &[Float]) -> Roots {
+    let mut roots = Roots { values: [0.0; 4], len: 0 };
+    let degree = match coeffs.iter().rposition(|&c| c != 0.0) {
+        Some(d) => d,
+        None => return roots,
+    };
+    let c = &coeffs[..=degree];
+    assert!(degree <= 4, "polynomial of degree {}", degree);
+    match degree {
+        0 => {}
+        1 => roots.push(-c[0] / c[1]),
+        2 => {
+            let discriminant = c[1] * c[1] - 4.0 * c[2] * c[0];
+            if discriminant >= 0.0 {
+                // Avoids cancellation between -b and the root of the discriminant.
+                let q = -0.5 * (c[1] + discriminant.sqrt().copysign(c[1]));
+                let (r0, r1) = iff!(q == 0.0, (0.0, 0.0), (q / c[2], c[0] / q));
+                roots.push(min!(r0, r1));
+                roots.push(max!(r0, r1));
+            }
+        }
+        _ => {
+            let mut derivative = [0.0; 4];
+            for i in 1..=degree {
+                derivative[i - 1] = c[i] * i as Float;
+            }
+            let eval = |x: Float| c.iter().rev().fold(0.0, |acc, &ci| acc * x + ci);
+            // Cauchy's bound on the magnitude of the roots.
+            let bound =
+                1.0 + c[..degree].iter().fold(0.0, |m: Float, ci| m.max((ci / c[degree]).abs()));
+            let critical = polynomial_roots(&derivative[..degree]);
+            let mut lo = -bound;
+            for &hi in
+                critical.iter().filter(|&&x| x > -bound && x < bound).chain(std::iter::once(&bound))
+            {
+                let (mut a, mut b) = (lo, hi);
+                let (fa, fb) = (eval(a), eval(b));
+                if fa == 0.0 && roots.last() != Some(&a) {
+                    roots.push(a);
+                } else if (fa < 0.0) != (fb < 0.0) && fb != 0.0 {
+                    loop {
+                        let mid = (a + b) / 2.0;
+                        if mid <= a || mid >= b {
+                            break;
+                        }
+                        let fm = eval(mid);
+                        if fm == 0.0 {
+                            a = mid;
+                            break;
+                        }
+                        if (fm < 0.0) == (fa < 0.0) {
+                            a = mid;
+                        } else {
+                            b = mid;
+                        }
+                    }
+                    roots.push(a);
+                }
+                lo = hi;
+            }
+            if eval(bound) == 0.0 {
+                roots.push(bound);
+            }
+        }
+    }
+    roots
+}
+
+/// The smallest float greater than `x`.
+pub fn next_float_up(x: Float) -> Float {
+    if x.is_infinite() && x > 0.0 {
+        return x;
+    }
+    // Skip -0, which has a different bit pattern from 0 but would come out the same.
+    let x = iff!(x == -0.0, 0.0, x);
+    Float::from_bits(iff!(x >= 0.0, x.to_bits() + 1, x.to_bits() - 1))
+}
+
+/// The largest float less than `x`.
+pub fn next_float_down(x: Float) -> Float {
+    -next_float_up(-x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The coefficients of the monic polynomial with `roots`, lowest degree first.
+    fn with_roots(roots: &[Float]) -> Vec<Float> {
+        let mut coeffs = vec![1.0];
+        for &r in roots {
+            let mut next = vec![0.0; coeffs.len() + 1];
+            for (i, &c) in coeffs.iter().enumerate() {
+                next[i + 1] += c;
+                next[i] -= r * c;
+            }
+            coeffs = next;
+        }
+        coeffs
+    }
+
+    fn assert_roots(coeffs: &[Float], expected: &[Float]) {
+        let roots = polynomial_roots(coeffs);
+        assert_eq!(roots.len(), expected.len(), "found {:?}, expected {:?}", &*roots, expected);
+        for (&r, &e) in roots.iter().zip(expected) {
+            assert!(
+                (r - e).abs() <= 1e-5 * max!(1.0, e.abs()),
+                "found {:?}, expected {:?}",
+                &*roots,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn low_degrees() {
+        assert_roots(&[0.0, 0.0, 0.0], &[]);
+        assert_roots(&[2.0], &[]);
+        assert_roots(&[3.0, -2.0], &[1.5]);
+        assert_roots(&[1.0, 0.0, 1.0], &[]);
+        assert_roots(&with_roots(&[3.0, 1.0]), &[1.0, 3.0]);
+    }
+
+    #[test]
+    fn quadratic_double_root() {
+        assert_roots(&with_roots(&[1.0, 1.0]), &[1.0, 1.0]);
+    }
+
+    #[test]
+    fn quadratic_root_near_zero() {
+        // Without care, -b plus the root of the discriminant cancels to nothing.
+        let roots = polynomial_roots(&with_roots(&[2.0, 1e-9]));
+        assert_eq!(roots.len(), 2);
+        assert!((roots[0] - 1e-9).abs() <= 1e-15, "found {:?}", &*roots);
+    }
+
+    #[test]
+    fn cubic() {
+        assert_roots(&with_roots(&[4.0, -2.0, 0.5]), &[-2.0, 0.5, 4.0]);
+        assert_roots(&[1.0, 0.0, 0.0, 1.0], &[-1.0]);
+    }
+
+    #[test]
+    fn quartic() {
+        assert_roots(&with_roots(&[3.0, 0.0, 2.0, 1.0]), &[0.0, 1.0, 2.0, 3.0]);
+        assert_roots(&[1.0, 0.0, 0.0, 0.0, 1.0], &[]);
+    }
+
+    #[test]
+    fn quartic_double_root() {
+        // The double root only touches zero, at a root of the derivative.
+        assert_roots(&with_roots(&[1.0, -2.0, 1.0, 3.0]), &[-2.0, 1.0, 3.0]);
+    }
+
+    #[test]
+    fn quartic_root_near_zero() {
+        assert_roots(&with_roots(&[2.0, -1.0, 1e-6, 3.0]), &[-1.0, 1e-6, 2.0, 3.0]);
+    }
 }
@@ -0,0 +1,225 @@
+use crate::bvh::*;
+use crate::geom::*;
+use crate::prims::*;
+use crate::shape::*;
+use crate::types::*;
+
+/// Number of children per node: as many bounds as fit in an AVX register, so four in f64 and eight in f32.
+const WIDTH: usize = 32 / std::mem::size_of::<Float>();
+
+#[derive(Copy, Clone, Debug)]
+enum Child {
+    Empty,
+    Node(usize),
+    /// A range of `WideBVH::indices`.
+    Leaf {
+        start: usize,
+        len: usize,
+    },
+}
+
+/// A node with the bounds of all its children laid out by axis, so one axis of all the boxes can be loaded into a
+/// single vector register.
+#[derive(Copy, Clone, Debug)]
+#[repr(C, align(32))]
+struct WideNode {
+    min: [[Float; WIDTH]; 3],
+    max: [[Float; WIDTH]; 3],
+    children: [Child; WIDTH],
+}
+
+/// A BVH collapsed from a binary one so that each node has up to `WIDTH` children, which are tested against a ray
+/// together.
+///
+/// Like `BVH`, leaves hold indices into the slice of primitives the binary hierarchy was built from.
+pub struct WideBVH {
+    nodes: Vec<WideNode>,
+    indices: Vec<usize>,
+    aabb: AABB,
+    /// Whether the CPU supports the instructions used by `intersect_children_avx`.
+    avx: bool,
+}
+
+impl WideBVH {
+    pub fn new(bvh: &BVH) -> Self {
+        let mut wide =
+            WideBVH { nodes: vec![], indices: vec![], aabb: bvh.bounding_box(), avx: has_avx() };
+        match bvh {
+            BVH::Leaf { .. } => wide.add_node(&[bvh]),
+            BVH::Node { left, right, .. } => wide.add_node(&Self::collapse(left, right)),
+        };
+        wide
+    }
+
+    /// Pulls up grandchildren until there are `WIDTH` children, opening the largest interior nodes first.
+    fn collapse<'a>(left: &'a BVH, right: &'a BVH) -> Vec<&'a BVH> {
+        let mut children = vec![left, right];
+        while children.len() < WIDTH {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| matches!(c, BVH::Node { .. }))
+                .max_by(|(_, a), (_, b)| {
+                    let (a, b) = (a.bounding_box().surface_area(), b.bounding_box().surface_area());
+                    a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .map(|(i, _)| i);
+            match largest.map(|i| children.swap_remove(i)) {
+                Some(BVH::Node { left, right, .. }) => children.extend_from_slice(&[left, right]),
+                _ => break,
+            }
+        }
+        children
+    }
+
+    fn add_node(&mut self, children: &[&BVH]) -> usize {
+        let index = self.nodes.len();
+        let empty = AABB::empty();
+        self.nodes.push(WideNode {
+            min: [[empty.min[0]; WIDTH], [empty.min[1]; WIDTH], [empty.min[2]; WIDTH]],
+            max: [[empty.max[0]; WIDTH], [empty.max[1]; WIDTH], [empty.max[2]; WIDTH]],
+            children: [Child::Empty; WIDTH],
+        });
+        for (i, child) in children.iter().enumerate() {
+            let aabb = child.bounding_box();
+            let child = match child {
+                BVH::Leaf { prims, .. } => {
+                    let start = self.indices.len();
+                    self.indices.extend_from_slice(prims);
+                    Child::Leaf { start, len: prims.len() }
+                }
+                BVH::Node { left, right, .. } => {
+                    Child::Node(self.add_node(&Self::collapse(left, right)))
+                }
+            };
+            let node = &mut self.nodes[index];
+            for dim in 0..3 {
+                node.min[dim][i] = aabb.min[dim];
+                node.max[dim][i] = aabb.max[dim];
+            }
+            node.children[i] = child;
+        }
+        index
+    }
+
+    /// Finds the closest intersection with `prims`, which must be the slice the hierarchy was built from.
+    pub fn intersect<'a>(
+        &self, prims: &'a [Box<dyn Primitive>], r: Ray3f,
+    ) -> Option<SurfaceInteraction<'a>> {
+        let mut best = None;
+        self.intersect_node(0, prims, &r, &mut best);
+        best
+    }
+
+    fn intersect_node<'a>(
+        &self, node: usize, prims: &'a [Box<dyn Primitive>], r: &Ray3f,
+        best: &mut Option<SurfaceInteraction<'a>>,
+    ) {
+        let node = &self.nodes[node];
+        // Children entirely behind the closest hit so far can be skipped.
+        let t_max = best.as_ref().map(|hit| hit.t).unwrap_or(FLOAT_MAX);
+        let mask = self.intersect_children(node, r, t_max);
+        for (i, child) in node.children.iter().enumerate() {
+            if mask & (1 << i) == 0 {
+                continue;
+            }
+            match *child {
+                Child::Empty => {}
+                Child::Node(child) => self.intersect_node(child, prims, r, best),
+                Child::Leaf { start, len } => {
+                    for &prim in &self.indices[start..start + len] {
+                        match (prims[prim].intersect(*r), best.as_ref()) {
+                            (Some(hit), Some(b)) if hit.t >= b.t => {}
+                            (Some(hit), _) => *best = Some(hit),
+                            (None, _) => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns a bit mask of the children whose bounds the ray enters before `t_max`.
+    fn intersect_children(&self, node: &WideNode, r: &Ray3f, t_max: Float) -> u8 {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if self.avx {
+                // Safe: `avx` is only set when the CPU supports AVX.
+                return unsafe { intersect_children_avx(node, r, t_max) };
+            }
+        }
+        intersect_children_scalar(node, r, t_max)
+    }
+
+    pub fn bounding_box(&self) -> AABB {
+        self.aabb
+    }
+}
+
+fn intersect_children_scalar(node: &WideNode, r: &Ray3f, t_max: Float) -> u8 {
+    let mut mask = 0;
+    for i in 0..WIDTH {
+        let mut t_near = 0.000_001;
+        let mut t_far = t_max;
+        for dim in 0..3 {
+            let mut t0 = (node.min[dim][i] - r.origin[dim]) * r.inv_d[dim];
+            let mut t1 = (node.max[dim][i] - r.origin[dim]) * r.inv_d[dim];
+            if r.inv_d[dim] < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t1 *= SLAB_ROUNDING;
+            t_near = iff!(t0 > t_near, t0, t_near);
+            t_far = iff!(t1 < t_far, t1, t_far);
+        }
+        if t_near < t_far {
+            mask |= 1 << i;
+        }
+    }
+    mask
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn intersect_children_avx(node: &WideNode, r: &Ray3f, t_max: Float) -> u8 {
+    use std::arch::x86_64::*;
+    // The same instructions for eight f32s as for four f64s.
+    #[cfg(not(feature = "f32"))]
+    use std::arch::x86_64::{
+        _mm256_cmp_pd as cmp, _mm256_loadu_pd as loadu, _mm256_max_pd as max, _mm256_min_pd as min,
+        _mm256_movemask_pd as movemask, _mm256_mul_pd as mul, _mm256_set1_pd as set1,
+        _mm256_sub_pd as sub,
+    };
+    #[cfg(feature = "f32")]
+    use std::arch::x86_64::{
+        _mm256_cmp_ps as cmp, _mm256_loadu_ps as loadu, _mm256_max_ps as max, _mm256_min_ps as min,
+        _mm256_movemask_ps as movemask, _mm256_mul_ps as mul, _mm256_set1_ps as set1,
+        _mm256_sub_ps as sub,
+    };
+
+    let mut t_near = set1(0.000_001);
+    let mut t_far = set1(t_max);
+    let rounding = set1(SLAB_ROUNDING);
+    for dim in 0..3 {
+        let (near, far) = iff!(r.inv_d[dim] < 0.0, (&node.max, &node.min), (&node.min, &node.max));
+        let origin = set1(r.origin[dim]);
+        let inv_d = set1(r.inv_d[dim]);
+        let t0 = mul(sub(loadu(near[dim].as_ptr()), origin), inv_d);
+        let t1 = mul(mul(sub(loadu(far[dim].as_ptr()), origin), inv_d), rounding);
+        // max/min return their second operand if either is NaN, which happens when the origin is on a slab
+        // boundary parallel to the ray; keep the running bound in that case, as the scalar version does.
+        t_near = max(t0, t_near);
+        t_far = min(t1, t_far);
+    }
+    movemask(cmp(t_near, t_far, _CMP_LT_OQ)) as u8
+}
+
+fn has_avx() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}